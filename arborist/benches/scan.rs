@@ -0,0 +1,79 @@
+//! Directory scan benchmarks.
+//!
+//! `sample_data` covers the per-file work on real documents, the synthetic
+//! tree covers the walk itself. The synthetic tree holds a million empty files
+//! unless `ARBORIST_BENCH_FILES` says otherwise; it is generated once into the
+//! temp directory and reused by later runs.
+
+use arborist::config::ScanConfig;
+use arborist::utils::DirScanConfig;
+use criterion::{criterion_group, criterion_main, Criterion};
+use indicatif::ProgressBar;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+const DEFAULT_SYNTHETIC_FILES: usize = 1_000_000;
+
+/// Files per folder of the synthetic tree, folders are nested two levels deep
+const FILES_PER_FOLDER: usize = 100;
+
+fn synthetic_tree(file_count: usize) -> io::Result<PathBuf> {
+    let root = std::env::temp_dir().join(format!("arborist-bench-{}", file_count));
+    let complete_marker = root.join(".complete");
+    if complete_marker.exists() {
+        return Ok(root);
+    }
+
+    for file_index in 0..file_count {
+        let folder_index = file_index / FILES_PER_FOLDER;
+        let folder = root
+            .join(format!("{:04}", folder_index / 100))
+            .join(format!("{:02}", folder_index % 100));
+        if file_index % FILES_PER_FOLDER == 0 {
+            fs::create_dir_all(&folder)?;
+        }
+        fs::File::create(folder.join(format!("file-{}.txt", file_index)))?;
+    }
+    fs::File::create(complete_marker)?;
+
+    Ok(root)
+}
+
+fn scan_config(root: &Path, walk_threads: usize) -> DirScanConfig {
+    DirScanConfig::from_config(
+        root.to_path_buf(),
+        &ScanConfig {
+            walk_threads,
+            ..Default::default()
+        },
+    )
+}
+
+fn bench_scan(c: &mut Criterion) {
+    let sample_data = Path::new(env!("CARGO_MANIFEST_DIR")).join("../sample_data");
+    c.bench_function("scan sample_data", |b| {
+        let config = scan_config(&sample_data, 0);
+        b.iter(|| config.walk(&ProgressBar::hidden()).unwrap())
+    });
+
+    let file_count = std::env::var("ARBORIST_BENCH_FILES")
+        .ok()
+        .and_then(|count| count.parse().ok())
+        .unwrap_or(DEFAULT_SYNTHETIC_FILES);
+    let root = synthetic_tree(file_count).expect("Failed to generate the synthetic tree");
+
+    let mut group = c.benchmark_group(format!("scan {} files", file_count));
+    group.sample_size(10);
+    // One thread shows what the work-stealing walk gains over a sequential one
+    for (name, walk_threads) in [("sequential", 1), ("parallel", 0)] {
+        let config = scan_config(&root, walk_threads);
+        group.bench_function(name, |b| {
+            b.iter(|| config.walk(&ProgressBar::hidden()).unwrap())
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, bench_scan);
+criterion_main!(benches);
@@ -0,0 +1,341 @@
+//! HTTP/JSON API for search and indexing, served by `arborist serve --http`.
+//!
+//! The endpoints are described in `openapi.json`, served at `/api/openapi.json`.
+//! Scans run in the background as jobs whose state is polled by id. Clients
+//! can only scan the directories already indexed or allowed under `[api]`,
+//! and have to send the configured bearer token when there is one.
+
+use crate::database::{self, CollectionStats, FileDetail};
+use crate::filters::QueryFilters;
+use crate::indexer::Indexer;
+use crate::output::QueryHit;
+use crate::progress::ScanProgress;
+use crate::report::ScanReport;
+use crate::roots;
+use anyhow::{anyhow, bail, Context, Result};
+use axum::extract::{Path as UrlPath, Query, Request, State};
+use axum::http::{header, StatusCode};
+use axum::middleware::{self, Next};
+use axum::response::{IntoResponse, Response};
+use axum::routing::{get, post};
+use axum::{Json, Router};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::collections::BTreeMap;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+
+/// OpenAPI description of the endpoints
+const OPENAPI: &str = include_str!("openapi.json");
+
+/// Finished scan jobs kept for polling, the oldest are dropped first
+const FINISHED_JOBS_KEPT: usize = 100;
+
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum JobState {
+    Running,
+    Finished,
+    Failed,
+}
+
+/// A scan started through the API
+#[derive(Serialize, Debug, Clone)]
+pub struct ScanJob {
+    pub id: u64,
+    pub path: String,
+    pub state: JobState,
+    /// Unix timestamps in seconds
+    pub started_at: i64,
+    pub finished_at: Option<i64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub report: Option<ScanReport>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+struct ApiState {
+    indexer: Indexer,
+    /// Bearer token every request has to carry, if one is configured
+    token: Option<String>,
+    jobs: Mutex<BTreeMap<u64, ScanJob>>,
+    next_job_id: AtomicU64,
+}
+
+impl ApiState {
+    fn update_job(&self, id: u64, update: impl FnOnce(&mut ScanJob)) {
+        let mut jobs = self.jobs.lock().unwrap();
+        if let Some(job) = jobs.get_mut(&id) {
+            update(job);
+        }
+
+        // Job ids grow with time, so the first finished ones are the oldest
+        let finished: Vec<u64> = jobs
+            .values()
+            .filter(|job| job.state != JobState::Running)
+            .map(|job| job.id)
+            .collect();
+        for id in finished
+            .iter()
+            .take(finished.len().saturating_sub(FINISHED_JOBS_KEPT))
+        {
+            jobs.remove(id);
+        }
+    }
+}
+
+/// An error answered with its status code and a JSON message
+struct ApiError(StatusCode, anyhow::Error);
+
+impl ApiError {
+    fn bad_request(error: anyhow::Error) -> Self {
+        ApiError(StatusCode::BAD_REQUEST, error)
+    }
+
+    fn not_found(message: String) -> Self {
+        ApiError(StatusCode::NOT_FOUND, anyhow!(message))
+    }
+
+    fn forbidden(message: String) -> Self {
+        ApiError(StatusCode::FORBIDDEN, anyhow!(message))
+    }
+}
+
+impl From<anyhow::Error> for ApiError {
+    fn from(error: anyhow::Error) -> Self {
+        ApiError(StatusCode::INTERNAL_SERVER_ERROR, error)
+    }
+}
+
+impl IntoResponse for ApiError {
+    fn into_response(self) -> Response {
+        let body = Json(json!({ "error": format!("{:#}", self.1) }));
+        (self.0, body).into_response()
+    }
+}
+
+type ApiResult<T> = std::result::Result<T, ApiError>;
+
+/// The token configured under `[api]`, failing when its variable is not set
+fn configured_token(indexer: &Indexer) -> Result<Option<String>> {
+    let Some(var) = indexer.config.api.token_env.as_deref() else {
+        return Ok(None);
+    };
+    match std::env::var(var) {
+        Ok(token) if !token.is_empty() => Ok(Some(token)),
+        _ => bail!("API token variable '{}' is not set", var),
+    }
+}
+
+/// The API routes, answering with the state of `indexer`
+pub fn router(indexer: Indexer) -> Result<Router> {
+    let state = Arc::new(ApiState {
+        token: configured_token(&indexer)?,
+        indexer,
+        jobs: Mutex::new(BTreeMap::new()),
+        next_job_id: AtomicU64::new(1),
+    });
+    Ok(Router::new()
+        .route("/api/openapi.json", get(openapi))
+        .route("/api/search", post(search))
+        .route("/api/files", get(file))
+        .route("/api/scans", get(list_scans).post(start_scan))
+        .route("/api/scans/:id", get(scan_job))
+        .route("/api/stats", get(stats))
+        .route_layer(middleware::from_fn_with_state(state.clone(), check_token))
+        .with_state(state))
+}
+
+/// Serve the API on `addr` until Ctrl-C
+pub async fn serve(indexer: Indexer, addr: SocketAddr) -> Result<()> {
+    // Anyone who can reach the port could scan and read the index
+    if !addr.ip().is_loopback() && indexer.config.api.token_env.is_none() {
+        bail!(
+            "Serving the HTTP API on {} needs a token, set `token_env` under [api] in config.toml",
+            addr
+        );
+    }
+    let app = router(indexer)?;
+
+    let listener = tokio::net::TcpListener::bind(addr)
+        .await
+        .with_context(|| format!("Failed to listen on {}", addr))?;
+    eprintln!("Serving the HTTP API on http://{}/api", addr);
+
+    axum::serve(listener, app)
+        .with_graceful_shutdown(async {
+            let _ = tokio::signal::ctrl_c().await;
+        })
+        .await
+        .context("HTTP server failed")
+}
+
+async fn check_token(State(state): State<Arc<ApiState>>, request: Request, next: Next) -> Response {
+    if let Some(token) = &state.token {
+        let sent = request
+            .headers()
+            .get(header::AUTHORIZATION)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.strip_prefix("Bearer "));
+        if !sent.is_some_and(|sent| same_token(sent, token)) {
+            return ApiError(StatusCode::UNAUTHORIZED, anyhow!("Missing or wrong token"))
+                .into_response();
+        }
+    }
+    next.run(request).await
+}
+
+/// Compare tokens in time independent of where they differ
+fn same_token(sent: &str, token: &str) -> bool {
+    sent.len() == token.len()
+        && sent
+            .bytes()
+            .zip(token.bytes())
+            .fold(0, |diff, (a, b)| diff | (a ^ b))
+            == 0
+}
+
+async fn openapi() -> impl IntoResponse {
+    ([(header::CONTENT_TYPE, "application/json")], OPENAPI)
+}
+
+#[derive(Deserialize, Debug)]
+struct SearchRequest {
+    query: String,
+    #[serde(default)]
+    filters: QueryFilters,
+}
+
+async fn search(
+    State(state): State<Arc<ApiState>>,
+    Json(request): Json<SearchRequest>,
+) -> ApiResult<Json<Vec<QueryHit>>> {
+    let filter = request.filters.to_filter().map_err(ApiError::bad_request)?;
+    let hits = state.indexer.search(&request.query, &filter).await?;
+    Ok(Json(hits))
+}
+
+#[derive(Deserialize, Debug)]
+struct FileQuery {
+    path: String,
+}
+
+async fn file(
+    State(state): State<Arc<ApiState>>,
+    Query(query): Query<FileQuery>,
+) -> ApiResult<Json<FileDetail>> {
+    let indexer = &state.indexer;
+    database::file_detail(
+        indexer.store.as_ref(),
+        &indexer.config.collection_name,
+        &query.path,
+    )
+    .await?
+    .map(Json)
+    .ok_or_else(|| ApiError::not_found(format!("{} is not indexed", query.path)))
+}
+
+#[derive(Deserialize, Debug)]
+struct ScanRequest {
+    path: PathBuf,
+}
+
+async fn start_scan(
+    State(state): State<Arc<ApiState>>,
+    Json(request): Json<ScanRequest>,
+) -> ApiResult<(StatusCode, Json<ScanJob>)> {
+    // Paths are indexed canonicalized
+    let path = request
+        .path
+        .canonicalize()
+        .with_context(|| format!("Invalid directory: {}", request.path.display()))
+        .map_err(ApiError::bad_request)?;
+    if !may_scan(&state.indexer, &path)? {
+        return Err(ApiError::forbidden(format!(
+            "{} is not below an indexed directory or one of the scan_roots under [api]",
+            path.display()
+        )));
+    }
+    if state.indexer.active_scans().contains(&path) {
+        return Err(ApiError(
+            StatusCode::CONFLICT,
+            anyhow!("{} is already being scanned", path.display()),
+        ));
+    }
+
+    let job = ScanJob {
+        id: state.next_job_id.fetch_add(1, Ordering::Relaxed),
+        path: path.display().to_string(),
+        state: JobState::Running,
+        started_at: chrono::Utc::now().timestamp(),
+        finished_at: None,
+        report: None,
+        error: None,
+    };
+    state.jobs.lock().unwrap().insert(job.id, job.clone());
+
+    let job_id = job.id;
+    let scan_state = state.clone();
+    tokio::spawn(async move {
+        let indexer = &scan_state.indexer;
+        let result = indexer
+            .scan(&path, &indexer.config.scan, &ScanProgress::hidden())
+            .await;
+        scan_state.update_job(job_id, |job| {
+            job.finished_at = Some(chrono::Utc::now().timestamp());
+            match result {
+                Ok(report) => {
+                    job.state = JobState::Finished;
+                    job.report = Some(report);
+                }
+                Err(e) => {
+                    job.state = JobState::Failed;
+                    job.error = Some(format!("{:#}", e));
+                }
+            }
+        });
+    });
+
+    Ok((StatusCode::ACCEPTED, Json(job)))
+}
+
+/// Whether clients may scan `path`, which has to be canonical
+fn may_scan(indexer: &Indexer, path: &Path) -> Result<bool> {
+    let indexed = roots::load(&indexer.config.collection_name)?
+        .into_iter()
+        .map(|root| PathBuf::from(root.path));
+    let allowed = indexer
+        .config
+        .api
+        .scan_roots
+        .iter()
+        .filter_map(|root| root.canonicalize().ok());
+    Ok(indexed.chain(allowed).any(|root| path.starts_with(root)))
+}
+
+async fn list_scans(State(state): State<Arc<ApiState>>) -> Json<Vec<ScanJob>> {
+    Json(state.jobs.lock().unwrap().values().cloned().collect())
+}
+
+async fn scan_job(
+    State(state): State<Arc<ApiState>>,
+    UrlPath(id): UrlPath<u64>,
+) -> ApiResult<Json<ScanJob>> {
+    state
+        .jobs
+        .lock()
+        .unwrap()
+        .get(&id)
+        .cloned()
+        .map(Json)
+        .ok_or_else(|| ApiError::not_found(format!("No scan job {}", id)))
+}
+
+async fn stats(State(state): State<Arc<ApiState>>) -> ApiResult<Json<CollectionStats>> {
+    let indexer = &state.indexer;
+    let stats =
+        database::collection_stats(indexer.store.as_ref(), &indexer.config.collection_name).await?;
+    Ok(Json(stats))
+}
@@ -0,0 +1,360 @@
+//! Answers to questions from the indexed files, for `arborist ask`.
+//!
+//! The best matching files are retrieved like `arborist query` does, the
+//! relevant passages are read from them again and the LLM answers from those
+//! passages only, citing the file every statement comes from. When the
+//! passages do not hold the answer, there is no answer.
+
+use crate::database;
+use crate::indexer::Indexer;
+use crate::output::QueryHit;
+use crate::store::Filter;
+use crate::summary::read_document;
+use anyhow::Result;
+use log::{debug, warn};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+/// Reply the LLM is told to give when the sources do not answer the question
+const NO_ANSWER: &str = "NO_ANSWER";
+
+const SYSTEM_PROMPT: &str = "You answer questions using only the numbered sources you are given. \
+After every statement, cite the sources it comes from by their number in square brackets, e.g. [2] or [1, 3]. \
+Do not use anything you know from elsewhere. \
+If the sources do not contain the answer, reply with exactly NO_ANSWER and nothing else.";
+
+/// A passage of a file given to the LLM
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Source {
+    pub path: String,
+    pub text: String,
+}
+
+/// The answer to a question, with the files it is based on
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Answer {
+    pub question: String,
+    /// The answer with its citations as file paths, `None` when the sources do not support one
+    pub answer: Option<String>,
+    /// Files cited by the answer
+    pub citations: Vec<String>,
+    /// Files the answer was looked for in
+    pub consulted: Vec<String>,
+}
+
+impl fmt::Display for Answer {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.answer {
+            Some(answer) => {
+                writeln!(f, "{}", answer.trim())?;
+                writeln!(f)?;
+                writeln!(f, "Sources:")?;
+                for path in &self.citations {
+                    writeln!(f, "  {}", path)?;
+                }
+            }
+            None if self.consulted.is_empty() => {
+                writeln!(f, "No indexed files match this question.")?;
+            }
+            None => {
+                writeln!(f, "The indexed files do not answer this question.")?;
+                writeln!(f)?;
+                writeln!(f, "Looked in:")?;
+                for path in &self.consulted {
+                    writeln!(f, "  {}", path)?;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Answer `question` from the files matching it and `filter`
+pub async fn answer(indexer: &Indexer, question: &str, filter: &Filter) -> Result<Answer> {
+    let hits = indexer.search(question, filter).await?;
+    let mut consulted: Vec<String> = Vec::new();
+    for hit in &hits {
+        if !consulted.contains(&hit.path) {
+            consulted.push(hit.path.clone());
+        }
+    }
+
+    let sources = gather_sources(indexer, question, &hits).await?;
+    if sources.is_empty() {
+        return Ok(Answer {
+            question: question.to_string(),
+            answer: None,
+            citations: Vec::new(),
+            consulted,
+        });
+    }
+
+    let prompt = build_prompt(question, &sources);
+    debug!("Ask prompt: {}", prompt);
+    let reply = indexer.summarizer.generate(SYSTEM_PROMPT, &prompt).await?;
+    debug!("Ask reply: {}", reply);
+
+    // An answer citing none of the sources is not supported by them either
+    let (answer, citations) = if reply.contains(NO_ANSWER) {
+        (None, Vec::new())
+    } else {
+        let (answer, citations) = resolve_citations(&reply, &sources);
+        if citations.is_empty() {
+            (None, Vec::new())
+        } else {
+            (Some(answer), citations)
+        }
+    };
+
+    Ok(Answer {
+        question: question.to_string(),
+        answer,
+        citations,
+        consulted,
+    })
+}
+
+/// Passages of the hits within the context budget, best hits first
+async fn gather_sources(
+    indexer: &Indexer,
+    question: &str,
+    hits: &[QueryHit],
+) -> Result<Vec<Source>> {
+    let budget = indexer.config.query.ask_context_chars;
+    let mut sources = Vec::new();
+    let mut used = 0;
+
+    for hit in hits {
+        for text in passages_of(indexer, question, hit).await? {
+            // The best source always goes in, even over budget
+            if !sources.is_empty() && used + text.len() > budget {
+                return Ok(sources);
+            }
+            used += text.len();
+            sources.push(Source {
+                path: hit.path.clone(),
+                text,
+            });
+        }
+    }
+
+    Ok(sources)
+}
+
+/// The passages of a hit that are relevant to the question.
+///
+/// Documents are read again, so the passages are the current text of the
+/// file: a matched chunk is only quoted while the file still holds it. Other
+/// files, and documents that cannot be read any more, are represented by what
+/// the index holds about them.
+async fn passages_of(indexer: &Indexer, question: &str, hit: &QueryHit) -> Result<Vec<String>> {
+    let indexed_text = hit
+        .passage
+        .as_ref()
+        .map(|passage| passage.text.clone())
+        .or_else(|| hit.summary.clone());
+    if hit.filetype.as_deref() != Some("document") {
+        return Ok(indexed_text.into_iter().collect());
+    }
+
+    let path = hit.path.clone();
+    let text = match tokio::task::spawn_blocking(move || read_document(&path)).await? {
+        Ok(text) => text,
+        Err(e) => {
+            warn!("Failed to read {} again: {:#}", hit.path, e);
+            return Ok(indexed_text.into_iter().collect());
+        }
+    };
+
+    // A matched chunk still at its offsets is current, otherwise the file
+    // changed since it was indexed and its passages are picked again
+    if let Some(passage) = &hit.passage {
+        let unchanged = usize::try_from(passage.start)
+            .ok()
+            .zip(usize::try_from(passage.end).ok())
+            .and_then(|(start, end)| text.get(start..end))
+            .is_some_and(|chunk| chunk == passage.text);
+        if unchanged {
+            return Ok(vec![passage.text.clone()]);
+        }
+        debug!("{} changed since it was indexed", hit.path);
+    }
+
+    best_chunks(indexer, question, text).await
+}
+
+/// The chunks of a document closest to the question, in document order
+async fn best_chunks(indexer: &Indexer, question: &str, text: String) -> Result<Vec<String>> {
+    let scan_config = indexer.config.scan.clone();
+    let keep = indexer.config.query.ask_passages_per_file.max(1);
+    let model = indexer.model.clone();
+    let question = question.to_string();
+    // Chunking and inference take a while, keep them off the runtime
+    tokio::task::spawn_blocking(move || {
+        let (min_tokens, max_tokens) = scan_config.chunk_tokens;
+        let chunks: Vec<String> = database::chunk_string_with_offsets(
+            &text,
+            &scan_config.tokenizer_name,
+            min_tokens..max_tokens,
+        )?
+        .into_iter()
+        .map(|(_, chunk)| chunk)
+        .collect();
+        if chunks.len() <= keep {
+            return Ok(chunks);
+        }
+
+        let question_vector = model.embed(vec![question], None)?.remove(0);
+        let chunk_vectors = model.embed(chunks.iter().map(String::as_str).collect(), None)?;
+
+        let mut ranked: Vec<(f32, usize)> = chunk_vectors
+            .iter()
+            .enumerate()
+            .map(|(index, vector)| (cosine_similarity(&question_vector, vector), index))
+            .collect();
+        ranked.sort_by(|a, b| b.0.total_cmp(&a.0));
+
+        let mut best: Vec<usize> = ranked.iter().take(keep).map(|(_, index)| *index).collect();
+        best.sort_unstable();
+        Ok(best
+            .into_iter()
+            .map(|index| chunks[index].clone())
+            .collect())
+    })
+    .await?
+}
+
+fn cosine_similarity(a: &[f32], b: &[f32]) -> f32 {
+    let dot: f32 = a.iter().zip(b).map(|(x, y)| x * y).sum();
+    let norm = |v: &[f32]| v.iter().map(|x| x * x).sum::<f32>().sqrt();
+    let norms = norm(a) * norm(b);
+    if norms == 0.0 {
+        0.0
+    } else {
+        dot / norms
+    }
+}
+
+fn build_prompt(question: &str, sources: &[Source]) -> String {
+    let mut prompt = String::from("Sources:\n\n");
+    for (number, source) in sources.iter().enumerate() {
+        prompt.push_str(&format!(
+            "[{}] {}\n{}\n\n",
+            number + 1,
+            source.path,
+            source.text.trim()
+        ));
+    }
+    prompt.push_str(&format!("Question: {}", question));
+    prompt
+}
+
+/// Replace the `[n]` citations of the reply by the paths of the sources.
+///
+/// Returns the rewritten reply and the cited paths in order of first
+/// citation. Brackets that do not cite a known source are left as they are.
+fn resolve_citations(reply: &str, sources: &[Source]) -> (String, Vec<String>) {
+    let mut answer = String::with_capacity(reply.len());
+    let mut citations: Vec<String> = Vec::new();
+    let mut rest = reply;
+
+    while let Some(open) = rest.find('[') {
+        answer.push_str(&rest[..open]);
+        let after = &rest[open + 1..];
+        let cited = after.find(']').and_then(|close| {
+            let numbers: Option<Vec<usize>> = after[..close]
+                .split(',')
+                .map(|number| number.trim().parse::<usize>().ok())
+                .collect();
+            let paths: Option<Vec<&str>> = numbers?
+                .into_iter()
+                .map(|number| {
+                    let index = number.checked_sub(1)?;
+                    sources.get(index).map(|source| source.path.as_str())
+                })
+                .collect();
+            Some((close, paths?))
+        });
+
+        match cited {
+            Some((close, paths)) => {
+                let mut unique: Vec<&str> = Vec::new();
+                for path in paths {
+                    if !unique.contains(&path) {
+                        unique.push(path);
+                    }
+                    if !citations.iter().any(|cited| cited == path) {
+                        citations.push(path.to_string());
+                    }
+                }
+                answer.push_str(&format!("[{}]", unique.join("; ")));
+                rest = &after[close + 1..];
+            }
+            None => {
+                answer.push('[');
+                rest = after;
+            }
+        }
+    }
+    answer.push_str(rest);
+
+    (answer, citations)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sources(paths: &[&str]) -> Vec<Source> {
+        paths
+            .iter()
+            .map(|path| Source {
+                path: path.to_string(),
+                text: String::new(),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn citations_are_replaced_by_paths_in_order_of_first_citation() {
+        let sources = sources(&["/a.txt", "/b.txt", "/c.txt"]);
+        let (answer, cited) = resolve_citations("Paid [2], then [1] and again [2].", &sources);
+        assert_eq!(answer, "Paid [/b.txt], then [/a.txt] and again [/b.txt].");
+        assert_eq!(cited, ["/b.txt", "/a.txt"]);
+    }
+
+    #[test]
+    fn lists_of_sources_are_resolved_once_each() {
+        let sources = sources(&["/a.txt", "/b.txt", "/c.txt"]);
+        let (answer, cited) = resolve_citations("Both [3, 1,3].", &sources);
+        assert_eq!(answer, "Both [/c.txt; /a.txt].");
+        assert_eq!(cited, ["/c.txt", "/a.txt"]);
+    }
+
+    #[test]
+    fn brackets_citing_no_known_source_are_kept() {
+        let sources = sources(&["/a.txt", "/b.txt"]);
+        for reply in [
+            "Empty []",
+            "Zero [0]",
+            "Out of range [1, 9]",
+            "Words [see above]",
+        ] {
+            let (answer, cited) = resolve_citations(reply, &sources);
+            assert_eq!(answer, reply);
+            assert!(cited.is_empty(), "{}", reply);
+        }
+    }
+
+    #[test]
+    fn an_unclosed_bracket_is_kept() {
+        let sources = sources(&["/a.txt"]);
+        let (answer, cited) = resolve_citations("See [1] and [1", &sources);
+        assert_eq!(answer, "See [/a.txt] and [1");
+        assert_eq!(cited, ["/a.txt"]);
+
+        let (answer, cited) = resolve_citations("[[1]", &sources);
+        assert_eq!(answer, "[[/a.txt]");
+        assert_eq!(cited, ["/a.txt"]);
+    }
+}
@@ -2,12 +2,32 @@ use serde::{Deserialize, Serialize};
 use std::fs;
 use std::path::PathBuf;
 
-#[derive(Serialize, Deserialize, Debug)]
+/// Which vector store the index is kept in
+#[derive(Serialize, Deserialize, Debug, Default, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum StoreBackend {
+    /// A Qdrant server reachable at `db_url`
+    #[default]
+    Qdrant,
+    /// A single file under the arborist data directory, no server needed
+    Embedded,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct Config {
     pub db_url: String,
     pub collection_name: String,
+    #[serde(default)]
+    pub store_backend: StoreBackend,
+    /// Index file of the embedded backend, defaults to `index.json` in the data directory
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub embedded_path: Option<PathBuf>,
     pub scan: ScanConfig,
     pub query: QueryConfig,
+    #[serde(default)]
+    pub embedding: EmbeddingConfig,
+    #[serde(default)]
+    pub api: ApiConfig,
 }
 
 impl Default for Config {
@@ -15,39 +35,236 @@ impl Default for Config {
         Self {
             db_url: "http://localhost:6334".to_string(),
             collection_name: "file_data".to_string(),
+            store_backend: StoreBackend::default(),
+            embedded_path: None,
             scan: ScanConfig::default(),
             query: QueryConfig::default(),
+            embedding: EmbeddingConfig::default(),
+            api: ApiConfig::default(),
         }
     }
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+/// Which kind of server summaries are generated with
+#[derive(Serialize, Deserialize, Debug, Default, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum LlmBackend {
+    #[default]
+    Ollama,
+    /// Any server implementing the OpenAI chat completions API
+    OpenAi,
+    /// Deterministic offline summaries, for tests
+    Stub,
+}
+
+/// What the points of an indexed file hold
+#[derive(Serialize, Deserialize, Debug, Default, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum IndexMode {
+    /// One point per file, embedding its LLM summary
+    #[default]
+    Summary,
+    /// One point per chunk of a document's extracted text, no LLM involved
+    Chunks,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default)]
 pub struct ScanConfig {
     pub max_tokens: (usize, usize),
+    /// Hugging Face tokenizer used to measure chunk sizes
+    pub tokenizer_name: String,
+    pub index_mode: IndexMode,
+    /// Token range of the document chunks in `chunks` mode
+    pub chunk_tokens: (usize, usize),
     pub model_name: String,
+    pub vision_model_name: String,
+    pub llm_backend: LlmBackend,
+    pub llm_host: String,
+    /// Port of the LLM server. Ollama listens on 11434 unless this is set; for
+    /// the OpenAI backend it only applies to a host URL without a port.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub llm_port: Option<u16>,
+    pub llm_timeout_secs: u64,
+    /// Environment variable holding the API key sent as a bearer token
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub llm_api_key_env: Option<String>,
+    /// Number of summaries requested from the LLM server at the same time
+    pub llm_concurrency: usize,
+    /// Number of files whose texts are embedded together
+    pub embed_batch_size: usize,
+    /// Number of points written to the store per request
+    pub upsert_batch_size: usize,
+    /// Threads walking the directory tree, 0 picks a number from the available cores
+    pub walk_threads: usize,
+    /// Deepest folder level scanned below the scanned directory
+    pub max_depth: usize,
+    /// Leave out files and folders whose name starts with a dot
+    pub skip_hidden: bool,
+    pub follow_symlinks: bool,
+    /// Stay on the file system of the scanned directory
+    pub same_file_system: bool,
+    /// Names of files and folders that are never scanned
+    pub paths_to_skip: Vec<String>,
+    /// Smallest file scanned, in bytes
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub min_file_size: Option<u64>,
+    /// Largest file scanned, in bytes
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_file_size: Option<u64>,
+    /// Globs of the files to scan, relative to the scanned directory; every file when empty
+    pub include: Vec<String>,
+    /// Gitignore-style patterns of files and folders to leave out, on top of the
+    /// `.gitignore`, `.ignore` and `.arboristignore` files
+    pub exclude: Vec<String>,
+    /// How long `arborist watch` waits for a burst of changes to settle
+    pub watch_debounce_ms: u64,
 }
 
 impl Default for ScanConfig {
     fn default() -> Self {
         Self {
             max_tokens: (20, 40),
+            tokenizer_name: "bert-base-cased".to_string(),
+            index_mode: IndexMode::default(),
+            chunk_tokens: (200, 400),
             model_name: "gemma2:2b".to_string(),
+            vision_model_name: "minicpm-v".to_string(),
+            llm_backend: LlmBackend::default(),
+            llm_host: "http://localhost".to_string(),
+            llm_port: None,
+            llm_timeout_secs: 300,
+            llm_api_key_env: None,
+            llm_concurrency: 1,
+            embed_batch_size: 16,
+            upsert_batch_size: 64,
+            walk_threads: 0,
+            max_depth: 10,
+            skip_hidden: true,
+            follow_symlinks: false,
+            same_file_system: false,
+            paths_to_skip: vec![
+                "node_modules".to_string(),
+                "downloaded-torrents".to_string(),
+                "target".to_string(),
+                "build".to_string(),
+                "dist".to_string(),
+                ".git".to_string(),
+            ],
+            min_file_size: None,
+            max_file_size: None,
+            include: Vec::new(),
+            exclude: Vec::new(),
+            watch_debounce_ms: 2000,
         }
     }
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+/// How dense and sparse search results are merged
+#[derive(Serialize, Deserialize, Debug, Default, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum FusionMode {
+    /// Reciprocal Rank Fusion
+    #[default]
+    Rrf,
+    /// Weighted sum of normalized scores, see `dense_weight` and `sparse_weight`
+    Weighted,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default)]
 pub struct QueryConfig {
     pub top_k_results: usize,
+    pub fusion: FusionMode,
+    pub dense_weight: f32,
+    pub sparse_weight: f32,
+    /// Blend matches on file names, paths and identifiers into the results
+    pub lexical: bool,
+    /// Weight of a full lexical match against the best semantic match
+    pub lexical_weight: f32,
+    /// Rescore the candidates of a query with a cross-encoder before keeping the best
+    pub rerank: bool,
+    /// fastembed reranker model, by its Hugging Face model code
+    pub rerank_model: String,
+    /// Candidates fetched for the reranker to choose `top_k_results` from
+    pub rerank_candidates: usize,
+    /// Passages of each retrieved document given to the LLM by `arborist ask`
+    pub ask_passages_per_file: usize,
+    /// Most characters of passages given to the LLM by `arborist ask`
+    pub ask_context_chars: usize,
 }
 
 impl Default for QueryConfig {
     fn default() -> Self {
-        Self { top_k_results: 5 }
+        Self {
+            top_k_results: 5,
+            fusion: FusionMode::default(),
+            dense_weight: 0.7,
+            sparse_weight: 0.3,
+            lexical: true,
+            lexical_weight: 2.0,
+            rerank: false,
+            rerank_model: "BAAI/bge-reranker-base".to_string(),
+            rerank_candidates: 30,
+            ask_passages_per_file: 2,
+            ask_context_chars: 12000,
+        }
+    }
+}
+
+/// Embedding models, named by their Hugging Face model code
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct EmbeddingConfig {
+    pub dense_model: String,
+    pub sparse_model: String,
+}
+
+impl Default for EmbeddingConfig {
+    fn default() -> Self {
+        Self {
+            dense_model: "nomic-ai/nomic-embed-text-v1.5".to_string(),
+            sparse_model: "Qdrant/Splade_PP_en_v1".to_string(),
+        }
+    }
+}
+
+/// Access to the HTTP API served by `arborist serve --http`
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+#[serde(default)]
+pub struct ApiConfig {
+    /// Directories clients may start scans of, besides the ones already indexed
+    pub scan_roots: Vec<PathBuf>,
+    /// Environment variable holding the bearer token clients have to send
+    pub token_env: Option<String>,
+}
+
+/// Directory arborist keeps its local state in, `ARBORIST_DATA_DIR` overrides it
+pub fn data_dir() -> PathBuf {
+    if let Some(dir) = std::env::var_os("ARBORIST_DATA_DIR") {
+        return PathBuf::from(dir);
     }
+    dirs::data_dir()
+        .unwrap_or_else(|| PathBuf::from("."))
+        .join("arborist")
 }
 
 impl Config {
+    /// Location of the embedded store's index file
+    pub fn embedded_path(&self) -> PathBuf {
+        self.embedded_path
+            .clone()
+            .unwrap_or_else(|| data_dir().join("index.json"))
+    }
+
+    /// Where the store keeps its collections, the server URL or the index file
+    pub fn store_location(&self) -> String {
+        match self.store_backend {
+            StoreBackend::Qdrant => format!("qdrant {}", self.db_url),
+            StoreBackend::Embedded => format!("embedded {}", self.embedded_path().display()),
+        }
+    }
+
     /// Load the configuration from the given path or fallback to defaults
     pub fn load(config_path: Option<PathBuf>) -> anyhow::Result<Self> {
         if let Some(path) = config_path {
@@ -71,7 +288,7 @@ impl Config {
 
                 fs::create_dir_all(default_path.parent().unwrap())?;
                 fs::write(&default_path, toml::to_string_pretty(&default_config)?)?;
-                println!("Default config file created at: {}", default_path.display());
+                eprintln!("Default config file created at: {}", default_path.display());
                 Ok(default_config)
             }
         }
@@ -0,0 +1,317 @@
+//! Long-running daemon that keeps the models and the store loaded.
+//!
+//! The daemon listens on a Unix socket in the data directory, one per
+//! collection, store and embedding models. Requests and responses are single
+//! lines of JSON. `scan`, `query`, `similar`, `ask` and `status` go through a
+//! running daemon on their own and do the work themselves when none answers.
+
+use crate::ask::{self, Answer};
+use crate::config::{data_dir, Config};
+use crate::indexer::Indexer;
+use crate::output::QueryHit;
+use crate::progress::ScanProgress;
+use crate::report::ScanReport;
+use crate::store::Filter;
+use crate::utils::WalkArgs;
+use anyhow::{anyhow, bail, Context, Result};
+use log::warn;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::fs;
+use std::os::unix::fs::DirBuilderExt;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::Instant;
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader, Lines};
+use tokio::net::unix::{OwnedReadHalf, OwnedWriteHalf};
+use tokio::net::{UnixListener, UnixStream};
+
+/// Socket of the daemon serving the configured collection.
+///
+/// Daemons of a collection of the same name in another store, or embedded with
+/// other models, listen on sockets of their own.
+pub fn socket_path(config: &Config) -> PathBuf {
+    let fingerprint = blake3::hash(
+        format!(
+            "{}\0{}\0{}",
+            config.store_location(),
+            config.embedding.dense_model,
+            config.embedding.sparse_model
+        )
+        .as_bytes(),
+    )
+    .to_hex();
+    data_dir().join("daemon").join(format!(
+        "{}-{}.sock",
+        config.collection_name,
+        &fingerprint[..16]
+    ))
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(tag = "request", rename_all = "snake_case")]
+pub enum Request {
+    /// Search the index, the filter is resolved by the client
+    Query {
+        query: String,
+        filter: Filter,
+    },
+    /// Find the files closest to a file, the path and filter are resolved by the client
+    Similar {
+        path: PathBuf,
+        filter: Filter,
+    },
+    /// Answer a question from the matching files, the filter is resolved by the client
+    Ask {
+        question: String,
+        filter: Filter,
+    },
+    /// Scan a canonicalized directory with the daemon's walk options and the client's flags
+    Scan {
+        path: PathBuf,
+        walk: WalkArgs,
+    },
+    Status,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(tag = "response", rename_all = "snake_case")]
+pub enum Response {
+    Hits { hits: Vec<QueryHit> },
+    Answer { answer: Box<Answer> },
+    Report { report: Box<ScanReport> },
+    Status { status: DaemonStatus },
+    Error { message: String },
+}
+
+/// What a running daemon is serving
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct DaemonStatus {
+    pub pid: u32,
+    pub version: String,
+    pub socket: String,
+    pub collection: String,
+    pub dense_model: String,
+    pub sparse_model: String,
+    pub uptime_secs: u64,
+    pub requests_served: u64,
+    /// Directories being scanned right now
+    pub active_scans: Vec<String>,
+}
+
+impl fmt::Display for DaemonStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "Daemon {} (pid {}) serving collection '{}' on {}",
+            self.version, self.pid, self.collection, self.socket
+        )?;
+        writeln!(f, "Models   {}, {}", self.dense_model, self.sparse_model)?;
+        writeln!(
+            f,
+            "Uptime   {}s, {} requests served",
+            self.uptime_secs, self.requests_served
+        )?;
+        if self.active_scans.is_empty() {
+            writeln!(f, "Scans    none running")?;
+        } else {
+            writeln!(f, "Scans    {}", self.active_scans.join(", "))?;
+        }
+        Ok(())
+    }
+}
+
+/// Connection to a running daemon
+pub struct Client {
+    lines: Lines<BufReader<OwnedReadHalf>>,
+    writer: OwnedWriteHalf,
+}
+
+impl Client {
+    /// Connect to the daemon serving the configured collection, `None` when none is running
+    pub async fn connect(config: &Config) -> Option<Self> {
+        let stream = UnixStream::connect(socket_path(config)).await.ok()?;
+        let (reader, writer) = stream.into_split();
+        Some(Self {
+            lines: BufReader::new(reader).lines(),
+            writer,
+        })
+    }
+
+    /// Send a request and wait for its response
+    pub async fn send(&mut self, request: &Request) -> Result<Response> {
+        write_line(&mut self.writer, request).await?;
+        let line = self
+            .lines
+            .next_line()
+            .await
+            .context("Failed to read the daemon response")?
+            .ok_or_else(|| anyhow!("The daemon closed the connection"))?;
+        serde_json::from_str(&line).context("Invalid daemon response")
+    }
+}
+
+async fn write_line(writer: &mut OwnedWriteHalf, message: &impl Serialize) -> Result<()> {
+    let mut line = serde_json::to_vec(message)?;
+    line.push(b'\n');
+    writer
+        .write_all(&line)
+        .await
+        .context("Failed to write to the daemon socket")
+}
+
+/// State shared by the connections of the daemon
+struct Daemon {
+    indexer: Indexer,
+    socket: PathBuf,
+    started: Instant,
+    requests_served: AtomicU64,
+}
+
+/// Serve requests on the collection's socket until Ctrl-C
+pub async fn serve(indexer: Indexer) -> Result<()> {
+    let collection_name = indexer.config.collection_name.clone();
+    let socket = socket_path(&indexer.config);
+    if Client::connect(&indexer.config).await.is_some() {
+        bail!(
+            "A daemon is already serving collection '{}' on {}",
+            collection_name,
+            socket.display()
+        );
+    }
+    // Left behind by a daemon that did not shut down cleanly
+    if socket.exists() {
+        fs::remove_file(&socket)
+            .with_context(|| format!("Failed to remove stale socket: {}", socket.display()))?;
+    }
+    fs::DirBuilder::new()
+        .recursive(true)
+        .mode(0o700)
+        .create(socket.parent().unwrap_or(Path::new(".")))?;
+
+    // Only the owner may talk to the daemon, from the moment the socket exists
+    let listener = with_umask(0o077, || UnixListener::bind(&socket))
+        .with_context(|| format!("Failed to listen on {}", socket.display()))?;
+    eprintln!(
+        "Serving collection '{}' on {}, Ctrl-C to stop.",
+        collection_name,
+        socket.display()
+    );
+
+    let daemon = Arc::new(Daemon {
+        indexer,
+        socket: socket.clone(),
+        started: Instant::now(),
+        requests_served: AtomicU64::new(0),
+    });
+    let ctrl_c = tokio::signal::ctrl_c();
+    tokio::pin!(ctrl_c);
+
+    loop {
+        tokio::select! {
+            accepted = listener.accept() => {
+                let (stream, _) = match accepted {
+                    Ok(accepted) => accepted,
+                    Err(e) => {
+                        warn!("Failed to accept a connection: {}", e);
+                        continue;
+                    }
+                };
+                let daemon = daemon.clone();
+                tokio::spawn(async move {
+                    if let Err(e) = daemon.handle_connection(stream).await {
+                        warn!("Connection failed: {:#}", e);
+                    }
+                });
+            }
+            _ = &mut ctrl_c => break,
+        }
+    }
+
+    fs::remove_file(&socket)
+        .with_context(|| format!("Failed to remove socket: {}", socket.display()))
+}
+
+/// Run `f` with the file mode creation mask of the process set to `mask`
+fn with_umask<T>(mask: libc::mode_t, f: impl FnOnce() -> T) -> T {
+    // SAFETY: umask only swaps the mask of the process and cannot fail
+    let previous = unsafe { libc::umask(mask) };
+    let result = f();
+    unsafe { libc::umask(previous) };
+    result
+}
+
+impl Daemon {
+    async fn handle_connection(&self, stream: UnixStream) -> Result<()> {
+        let (reader, mut writer) = stream.into_split();
+        let mut lines = BufReader::new(reader).lines();
+
+        while let Some(line) = lines.next_line().await? {
+            let response = match serde_json::from_str(&line) {
+                Ok(request) => self
+                    .handle(request)
+                    .await
+                    .unwrap_or_else(|e| Response::Error {
+                        message: format!("{:#}", e),
+                    }),
+                Err(e) => Response::Error {
+                    message: format!("Invalid request: {}", e),
+                },
+            };
+            write_line(&mut writer, &response).await?;
+        }
+
+        Ok(())
+    }
+
+    async fn handle(&self, request: Request) -> Result<Response> {
+        self.requests_served.fetch_add(1, Ordering::Relaxed);
+
+        match request {
+            Request::Query { query, filter } => Ok(Response::Hits {
+                hits: self.indexer.search(&query, &filter).await?,
+            }),
+            Request::Similar { path, filter } => Ok(Response::Hits {
+                hits: self.indexer.similar(&path, &filter).await?,
+            }),
+            Request::Ask { question, filter } => Ok(Response::Answer {
+                answer: Box::new(ask::answer(&self.indexer, &question, &filter).await?),
+            }),
+            Request::Scan { path, walk } => {
+                let mut walk_options = self.indexer.config.scan.clone();
+                walk.apply(&mut walk_options);
+                let report = self
+                    .indexer
+                    .scan(&path, &walk_options, &ScanProgress::hidden())
+                    .await?;
+                Ok(Response::Report {
+                    report: Box::new(report),
+                })
+            }
+            Request::Status => Ok(Response::Status {
+                status: self.status(),
+            }),
+        }
+    }
+
+    fn status(&self) -> DaemonStatus {
+        let config = &self.indexer.config;
+        DaemonStatus {
+            pid: std::process::id(),
+            version: env!("CARGO_PKG_VERSION").to_string(),
+            socket: self.socket.display().to_string(),
+            collection: config.collection_name.clone(),
+            dense_model: config.embedding.dense_model.clone(),
+            sparse_model: config.embedding.sparse_model.clone(),
+            uptime_secs: self.started.elapsed().as_secs(),
+            requests_served: self.requests_served.load(Ordering::Relaxed),
+            active_scans: self
+                .indexer
+                .active_scans()
+                .iter()
+                .map(|path| path.display().to_string())
+                .collect(),
+        }
+    }
+}
@@ -1,237 +1,2061 @@
-use crate::config::Config;
-use crate::file_management::FileMetadata;
-use crate::summary::generate_file_summary;
-use crate::utils::setup_fastembed;
-use anyhow::{Context, Result};
-use fastembed::{SparseEmbedding, SparseTextEmbedding, TextEmbedding};
-use log::info;
-use qdrant_client::qdrant::{
-    Condition, CreateCollectionBuilder, Distance, Filter, PointStruct, QueryPointsBuilder,
-    SearchParamsBuilder, SparseVectorParamsBuilder, SparseVectorsConfigBuilder, UpsertPoints,
-    Value, VectorParamsBuilder, VectorsConfigBuilder,
+use crate::config::{
+    data_dir, Config, EmbeddingConfig, FusionMode, IndexMode, QueryConfig, ScanConfig,
+};
+use crate::file_management::{hash_file, FileMetadata, FileType};
+use crate::journal::{JournalSnapshot, ScanJournal};
+use crate::lexical;
+use crate::llm::Summarizer;
+use crate::progress::ScanProgress;
+use crate::store::{
+    CollectionMetadata, CollectionSchema, Condition, Filter, Fusion, HybridQuery, IndexPoint,
+    Payload, PayloadFieldType, ScoredPoint, SparseVector, StoredPoint, VectorStore,
 };
-use qdrant_client::{Payload, Qdrant};
-use std::collections::HashMap;
+use crate::summary::{extract_content, read_document, summarize_content, FileContent};
+use crate::utils::{dense_model_info, DirScanResult};
+use anyhow::{anyhow, bail, Context, Result};
+use fastembed::{SparseEmbedding, SparseTextEmbedding, TextEmbedding};
+use indicatif::ProgressBar;
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fs;
+use std::future::Future;
+use std::num::NonZeroUsize;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, OnceLock};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 use text_splitter::{ChunkConfig, TextSplitter};
 use tokenizers::Tokenizer;
+use tokio::sync::mpsc;
+use tokio::task::JoinSet;
 use uuid::Uuid;
 
-pub async fn create_hybrid_collection(client: &Qdrant, collection_name: &str) -> Result<()> {
-    // Check if the collection already exists
-    if client.collection_exists(collection_name).await? {
-        info!(
-            "Collection '{}' already exists. Skipping creation.",
-            collection_name
-        );
-        return Ok(());
+/// Name of the dense vector of every point
+pub const DENSE_VECTOR_NAME: &str = "novum";
+
+/// Name of the sparse vector of every point
+pub const SPARSE_VECTOR_NAME: &str = "splade";
+
+/// Payload fields that queries filter on
+const PAYLOAD_INDEXES: [(&str, PayloadFieldType); 10] = [
+    ("file_path", PayloadFieldType::Keyword),
+    ("content_hash", PayloadFieldType::Keyword),
+    ("filetype", PayloadFieldType::Keyword),
+    ("extension", PayloadFieldType::Keyword),
+    ("parent_dirs", PayloadFieldType::Keyword),
+    ("file_size", PayloadFieldType::Integer),
+    ("created_at", PayloadFieldType::Integer),
+    ("modified_at", PayloadFieldType::Integer),
+    (lexical::NAME_TERMS, PayloadFieldType::Keyword),
+    (lexical::TEXT_TERMS, PayloadFieldType::Keyword),
+];
+
+/// Number of points written per request when migrating a collection
+const MIGRATE_BATCH_SIZE: usize = 256;
+
+/// Metadata describing the embedding models selected in the configuration
+fn embedding_metadata(embedding: &EmbeddingConfig) -> Result<CollectionMetadata> {
+    Ok(CollectionMetadata {
+        dense_model: embedding.dense_model.clone(),
+        sparse_model: embedding.sparse_model.clone(),
+        dimension: dense_model_info(embedding)?.dim,
+    })
+}
+
+fn collection_schema(metadata: &CollectionMetadata) -> CollectionSchema {
+    CollectionSchema {
+        dense_vector: DENSE_VECTOR_NAME.to_string(),
+        dimension: metadata.dimension,
+        sparse_vector: SPARSE_VECTOR_NAME.to_string(),
     }
-    // Configure sparse vectors using builder
-    let mut sparse_vector_config = SparseVectorsConfigBuilder::default();
-    sparse_vector_config.add_named_vector_params("splade", SparseVectorParamsBuilder::default());
-
-    // Configure dense vectors using builder
-    let mut dense_vector_config = VectorsConfigBuilder::default();
-    dense_vector_config
-        .add_named_vector_params("novum", VectorParamsBuilder::new(768, Distance::Cosine));
-
-    // Create collection using builders
-    client
-        .create_collection(
-            CreateCollectionBuilder::new(collection_name)
-                .sparse_vectors_config(sparse_vector_config)
-                .vectors_config(dense_vector_config),
-        )
-        .await?;
+}
 
-    println!("New collection created");
+/// Metadata of the collection, collections created before it was recorded
+/// were always embedded with the default models
+async fn stored_embedding_metadata(
+    store: &dyn VectorStore,
+    collection_name: &str,
+) -> Result<(CollectionMetadata, bool)> {
+    match store.collection_metadata(collection_name).await? {
+        Some(metadata) => Ok((metadata, true)),
+        None => Ok((embedding_metadata(&EmbeddingConfig::default())?, false)),
+    }
+}
 
+async fn create_payload_indexes(store: &dyn VectorStore, collection_name: &str) -> Result<()> {
+    for (field_name, field_type) in PAYLOAD_INDEXES {
+        store
+            .create_payload_index(collection_name, field_name, field_type)
+            .await?;
+    }
     Ok(())
 }
 
+pub async fn create_hybrid_collection(store: &dyn VectorStore, config: &Config) -> Result<()> {
+    let metadata = embedding_metadata(&config.embedding)?;
+
+    if store
+        .create_collection(&config.collection_name, &collection_schema(&metadata))
+        .await?
+    {
+        store
+            .set_collection_metadata(&config.collection_name, &metadata)
+            .await?;
+        info!("Created collection '{}'", config.collection_name);
+    }
+    // Collections created by older versions lack some of the indexes
+    create_payload_indexes(store, &config.collection_name).await
+}
+
+/// Fail if the collection was embedded with other models than the configured ones
+pub async fn check_embedding_model(store: &dyn VectorStore, config: &Config) -> Result<()> {
+    let expected = embedding_metadata(&config.embedding)?;
+    let (stored, recorded) = stored_embedding_metadata(store, &config.collection_name).await?;
+
+    if stored != expected {
+        bail!(
+            "Collection '{}' was embedded with {} ({} dimensions) but the config selects {} ({} dimensions). Run `arborist migrate` to re-embed it.",
+            config.collection_name,
+            stored.dense_model,
+            stored.dimension,
+            expected.dense_model,
+            expected.dimension
+        );
+    }
+    if !recorded {
+        store
+            .set_collection_metadata(&config.collection_name, &stored)
+            .await?;
+    }
+
+    Ok(())
+}
+
+/// The tokenizer of a Hugging Face model, downloaded and loaded once per process
+fn tokenizer(name: &str) -> Result<Arc<Tokenizer>> {
+    static TOKENIZERS: OnceLock<Mutex<HashMap<String, Arc<Tokenizer>>>> = OnceLock::new();
+    // Held while loading, so a tokenizer is never fetched twice at the same time
+    let mut tokenizers = TOKENIZERS.get_or_init(Mutex::default).lock().unwrap();
+    if let Some(tokenizer) = tokenizers.get(name) {
+        return Ok(tokenizer.clone());
+    }
+
+    let tokenizer = Tokenizer::from_pretrained(name, None)
+        .map_err(anyhow::Error::msg)
+        .with_context(|| format!("Failed to load tokenizer: {}", name))?;
+    let tokenizer = Arc::new(tokenizer);
+    tokenizers.insert(name.to_string(), tokenizer.clone());
+    Ok(tokenizer)
+}
+
 pub fn chunk_string(
     input: &str,
     tokenizer_name: &str,
     max_tokens: std::ops::Range<usize>,
-) -> Vec<String> {
-    // Initialize the tokenizer
-    let tokenizer =
-        Tokenizer::from_pretrained(tokenizer_name, None).expect("Failed to load tokenizer");
+) -> Result<Vec<String>> {
+    let tokenizer = tokenizer(tokenizer_name)?;
 
     // Create the TextSplitter with ChunkConfig
-    let splitter = TextSplitter::new(ChunkConfig::new(max_tokens).with_sizer(tokenizer));
+    let splitter = TextSplitter::new(ChunkConfig::new(max_tokens).with_sizer(tokenizer.as_ref()));
 
     // Chunk the input string and collect the results
-    splitter
+    Ok(splitter
         .chunks(input)
-        //.into_iter()
         .map(|chunk| chunk.to_string())
-        .collect()
+        .collect())
 }
 
-/// Generate both sparse and dense embeddings for a list of summaries
-async fn generate_embeddings(
-    summary: String,
-    model: &TextEmbedding,
-    sparse_model: &SparseTextEmbedding,
-) -> Result<(Vec<Vec<f32>>, Vec<SparseEmbedding>)> {
-    // Generate dense embeddings
-    let summary_chunks = chunk_string(&summary, "bert-base-cased", 20..40);
-    let dense_embeddings = model.embed(summary_chunks, None)?;
-
-    // Generate sparse embeddings
-    let sparse_embeddings = sparse_model.embed([summary].to_vec(), None)?;
-
-    Ok((dense_embeddings, sparse_embeddings))
-}
-
-/// Checks if a file has already been indexed in the database
-async fn is_file_already_indexed(client: &Qdrant, file_path: &str) -> anyhow::Result<bool> {
-    let query_result = client
-        .query(
-            QueryPointsBuilder::new("file_data")
-                .filter(Filter::must([Condition::matches(
-                    "file_path",
-                    file_path.to_string(),
-                )]))
-                .limit(1),
+/// Split text into token-bounded chunks, returning each chunk with its byte offset
+pub fn chunk_string_with_offsets(
+    input: &str,
+    tokenizer_name: &str,
+    max_tokens: std::ops::Range<usize>,
+) -> Result<Vec<(usize, String)>> {
+    let tokenizer = tokenizer(tokenizer_name)?;
+    let splitter = TextSplitter::new(ChunkConfig::new(max_tokens).with_sizer(tokenizer.as_ref()));
+
+    Ok(splitter
+        .chunk_indices(input)
+        .map(|(offset, chunk)| (offset, chunk.to_string()))
+        .collect())
+}
+
+/// Convert a fastembed sparse embedding into the store representation
+pub fn to_sparse_vector(embedding: &SparseEmbedding) -> SparseVector {
+    SparseVector {
+        indices: embedding.indices.iter().map(|&i| i as u32).collect(),
+        values: embedding.values.clone(),
+    }
+}
+
+/// What the index currently holds for a file path
+struct IndexedFile {
+    point_ids: Vec<String>,
+    modified_at: Option<i64>,
+    size: Option<i64>,
+    content_hash: Option<String>,
+    /// Whether the payload has the fields query filters and lexical matching rely on
+    has_filter_fields: bool,
+}
+
+/// How a scanned file should be handled given its indexed state
+enum IndexAction {
+    /// Nothing changed since the file was last indexed
+    Skip,
+    /// Only the timestamp or size changed, the contents hash the same
+    RefreshMetadata,
+    /// The file is new or its contents changed
+    Reindex,
+}
+
+fn unix_timestamp(time: SystemTime) -> i64 {
+    match time.duration_since(UNIX_EPOCH) {
+        Ok(duration) => duration.as_secs() as i64,
+        Err(e) => -(e.duration().as_secs() as i64),
+    }
+}
+
+/// Looks up the points stored for a file path, if it has already been indexed
+async fn fetch_indexed_file(
+    store: &dyn VectorStore,
+    collection_name: &str,
+    file_path: &str,
+) -> Result<Option<IndexedFile>> {
+    let points = store
+        .query_filter(
+            collection_name,
+            &Filter::must([Condition::keyword("file_path", file_path)]),
+            None,
         )
         .await
         .context("Failed to query existing file")?;
 
-    Ok(!query_result.result.is_empty())
+    let Some(first) = points.first() else {
+        return Ok(None);
+    };
+
+    Ok(Some(IndexedFile {
+        point_ids: points.iter().map(|point| point.id.clone()).collect(),
+        modified_at: first.payload.get("modified_at").and_then(Value::as_i64),
+        size: first.payload.get("file_size").and_then(Value::as_i64),
+        content_hash: first
+            .payload
+            .get("content_hash")
+            .and_then(Value::as_str)
+            .map(str::to_string),
+        has_filter_fields: first.payload.contains_key("parent_dirs")
+            && first.payload.contains_key(lexical::NAME_TERMS),
+    }))
 }
 
-/// Generate summary only if not already present
-async fn get_or_generate_summary(
-    config: &Config,
-    file: &FileMetadata,
-    force_regenerate: bool,
-) -> Result<String> {
-    // If summary is already present and we're not forcing regeneration, return it
-    if !force_regenerate && !file.summary.is_empty() {
-        return Ok(file.summary.clone());
+/// Hash a file on the blocking thread pool, unless it already has a hash.
+///
+/// A file that cannot be read keeps an empty hash and fails when extracted.
+async fn ensure_content_hash(file: &mut FileMetadata) {
+    if !file.content_hash.is_empty() {
+        return;
     }
+    let path = PathBuf::from(&file.path);
+    match tokio::task::spawn_blocking(move || hash_file(&path)).await {
+        Ok(Ok(hash)) => file.content_hash = hash,
+        Ok(Err(e)) => warn!("{:#}", e),
+        Err(e) => warn!("Failed to hash {}: {}", file.path, e),
+    }
+}
 
-    // Generate summary
-    generate_file_summary(&config.scan.model_name, file)
-        .await
-        .context("Failed to generate file summary")
+/// Decide whether a file needs to be summarized again by comparing its
+/// modification time, size and content hash with what was indexed.
+///
+/// The file is only hashed when it is new or its modification time or size
+/// changed, an unchanged file is never read.
+async fn plan_index_action(file: &mut FileMetadata, indexed: Option<&IndexedFile>) -> IndexAction {
+    let Some(indexed) = indexed else {
+        // New files are hashed too, to recognize moved ones and later changes
+        ensure_content_hash(file).await;
+        return IndexAction::Reindex;
+    };
+
+    if indexed.modified_at == Some(unix_timestamp(file.modified_at))
+        && indexed.size == Some(file.size as i64)
+    {
+        if indexed.has_filter_fields {
+            return IndexAction::Skip;
+        }
+        // Points indexed before the filter fields existed only need their payload completed
+        match &indexed.content_hash {
+            Some(hash) if !hash.is_empty() => file.content_hash = hash.clone(),
+            _ => ensure_content_hash(file).await,
+        }
+        return IndexAction::RefreshMetadata;
+    }
+
+    ensure_content_hash(file).await;
+    match &indexed.content_hash {
+        Some(hash) if !file.content_hash.is_empty() && *hash == file.content_hash => {
+            IndexAction::RefreshMetadata
+        }
+        _ => IndexAction::Reindex,
+    }
 }
 
-/// Process and prepare files sequentially
-pub async fn process_and_upload_files(
-    client: &Qdrant,
-    config: &Config,
-    file_metadata_list: &[FileMetadata],
-    force_regenerate: Option<bool>, // Changed to Option<bool>
+/// Update the stored file metadata of a file whose contents did not change
+async fn refresh_file_metadata(
+    store: &dyn VectorStore,
+    collection_name: &str,
+    file: &FileMetadata,
+    point_ids: Vec<String>,
 ) -> Result<()> {
-    // Set default value if force_regenerate is None
-    let force_regenerate = force_regenerate.unwrap_or(false);
+    let payload = file_payload(file);
+    let terms = lexical::terms_of([&payload]);
+    store
+        .set_payload(collection_name, point_ids, payload)
+        .await
+        .context("Failed to refresh file metadata")?;
+    lexical::record(store, collection_name, terms).await;
+    Ok(())
+}
 
-    // Setup embedding models
-    let (model, sparse_model) = setup_fastembed()?;
+/// Point the given points at a new location after the file was moved or renamed
+async fn relocate_points(
+    store: &dyn VectorStore,
+    collection_name: &str,
+    point_ids: Vec<String>,
+    file: &FileMetadata,
+) -> Result<()> {
+    let payload = file_payload(file);
+    let terms = lexical::terms_of([&payload]);
+    store
+        .set_payload(collection_name, point_ids, payload)
+        .await
+        .context("Failed to relocate points")?;
+    lexical::record(store, collection_name, terms).await;
+    Ok(())
+}
 
-    let mut points: Vec<PointStruct> = Vec::new();
+/// Finds the points of an indexed file with the same contents whose path no longer exists
+async fn find_moved_file(
+    store: &dyn VectorStore,
+    collection_name: &str,
+    content_hash: &str,
+) -> Result<Option<(String, Vec<String>)>> {
+    let points = store
+        .query_filter(
+            collection_name,
+            &Filter::must([Condition::keyword("content_hash", content_hash)]),
+            None,
+        )
+        .await
+        .context("Failed to query files by content hash")?;
 
-    // Process files sequentially
-    for file in file_metadata_list {
-        // Check if file is already indexed
-        if is_file_already_indexed(client, &file.path).await? {
-            println!("File path '{}' already exists. Skipping.", file.path);
+    let mut moved: Option<(String, Vec<String>)> = None;
+    for point in points {
+        let Some(path) = point.payload.get("file_path").and_then(Value::as_str) else {
+            continue;
+        };
+        if Path::new(path).exists() {
             continue;
         }
+        match &mut moved {
+            Some((moved_path, ids)) if moved_path == path => ids.push(point.id),
+            Some(_) => {}
+            None => moved = Some((path.to_string(), vec![point.id])),
+        }
+    }
 
-        // Generate summary sequentially to manage Ollama load
-        let summary = match get_or_generate_summary(config, file, force_regenerate).await {
-            Ok(sum) => sum,
-            Err(e) => {
-                eprintln!("Failed to generate summary for {}: {}", file.name, e);
-                continue;
+    Ok(moved)
+}
+
+/// Payload fields shared by every point of a file
+fn file_payload(file: &FileMetadata) -> Payload {
+    let path = Path::new(&file.path);
+    let extension = path
+        .extension()
+        .map(|ext| ext.to_string_lossy().to_lowercase())
+        .unwrap_or_default();
+    // Every ancestor is stored so `--under` is a single keyword match
+    let parent_dirs: Vec<Value> = path
+        .ancestors()
+        .skip(1)
+        .map(|dir| Value::from(dir.to_string_lossy()))
+        .collect();
+
+    let mut payload = Payload::new();
+    payload.insert("file_name".into(), Value::from(file.name.clone()));
+    payload.insert("file_path".into(), Value::from(file.path.clone()));
+    payload.insert("file_size".into(), Value::from(file.size as i64));
+    payload.insert("filetype".into(), Value::from(file.filetype.as_str()));
+    payload.insert("extension".into(), Value::from(extension));
+    payload.insert(
+        "parent_dir".into(),
+        parent_dirs.first().cloned().unwrap_or_default(),
+    );
+    payload.insert("parent_dirs".into(), Value::from(parent_dirs));
+    payload.insert(
+        "created_at".into(),
+        Value::from(unix_timestamp(file.created_at)),
+    );
+    payload.insert(
+        "modified_at".into(),
+        Value::from(unix_timestamp(file.modified_at)),
+    );
+    payload.insert(
+        "content_hash".into(),
+        Value::from(file.content_hash.clone()),
+    );
+    payload.insert(
+        lexical::NAME_TERMS.into(),
+        Value::from(lexical::name_terms(&file.path)),
+    );
+    payload
+}
+
+/// Build a point from the vectors and payload of a single text
+fn build_point(
+    dense_embedding: Vec<f32>,
+    sparse_embedding: Option<&SparseEmbedding>,
+    payload: Payload,
+) -> IndexPoint {
+    let mut vectors_map: HashMap<String, Vec<f32>> = HashMap::new();
+    vectors_map.insert(DENSE_VECTOR_NAME.to_string(), dense_embedding);
+
+    let mut sparse_vectors_map: HashMap<String, SparseVector> = HashMap::new();
+    if let Some(sparse_embedding) = sparse_embedding {
+        sparse_vectors_map.insert(
+            SPARSE_VECTOR_NAME.to_string(),
+            to_sparse_vector(sparse_embedding),
+        );
+    }
+
+    let uuid = Uuid::new_v4();
+    IndexPoint {
+        id: uuid.to_string(),
+        dense: vectors_map,
+        sparse: sparse_vectors_map,
+        payload,
+    }
+}
+
+/// Files queued between two pipeline stages, bounds the memory held by extracted texts
+const STAGE_QUEUE_SIZE: usize = 16;
+
+/// A file that has to be (re)indexed, with the points it replaces
+struct IndexJob {
+    file: FileMetadata,
+    stale_point_ids: Vec<String>,
+    /// Identifiers found in the text of the file, once extracted
+    text_terms: Vec<String>,
+}
+
+/// Anything passed between pipeline stages, each item belongs to one file
+trait PipelineItem {
+    fn file(&self) -> &FileMetadata;
+}
+
+impl PipelineItem for IndexJob {
+    fn file(&self) -> &FileMetadata {
+        &self.file
+    }
+}
+
+impl<T> PipelineItem for (IndexJob, T) {
+    fn file(&self) -> &FileMetadata {
+        &self.0.file
+    }
+}
+
+/// Text a file is embedded from
+enum EmbedText {
+    /// LLM summary, embedded as a single point
+    Summary(String),
+    /// Document chunks with their byte offsets, embedded as one point each
+    Chunks(Vec<(usize, String)>),
+}
+
+/// Output of the extraction stage
+enum Extracted {
+    /// Contents still to be summarized by the LLM
+    Content(FileContent),
+    /// Text that can be embedded without calling the LLM
+    Ready(EmbedText),
+}
+
+/// Points of a file, ready to be written
+struct FilePoints {
+    job: IndexJob,
+    points: Vec<IndexPoint>,
+}
+
+/// A file the pipeline gave up on
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct FailedFile {
+    pub path: String,
+    pub reason: String,
+}
+
+impl FailedFile {
+    fn new(file: &FileMetadata, error: anyhow::Error) -> Self {
+        FailedFile {
+            path: file.path.clone(),
+            reason: format!("{:#}", error),
+        }
+    }
+}
+
+/// Progress bar and busy time of a pipeline stage
+#[derive(Clone)]
+struct StageMeter {
+    bar: ProgressBar,
+    busy_nanos: Arc<AtomicU64>,
+}
+
+impl StageMeter {
+    fn new(bar: ProgressBar) -> Self {
+        StageMeter {
+            bar,
+            busy_nanos: Arc::new(AtomicU64::new(0)),
+        }
+    }
+
+    fn started(&self, file: &FileMetadata) {
+        self.bar.set_message(file.path.clone());
+    }
+
+    fn finished(&self, files: usize, elapsed: Duration) {
+        self.bar.inc(files as u64);
+        self.busy_nanos
+            .fetch_add(elapsed.as_nanos() as u64, Ordering::Relaxed);
+    }
+
+    fn busy(&self) -> Duration {
+        Duration::from_nanos(self.busy_nanos.load(Ordering::Relaxed))
+    }
+}
+
+/// Time the pipeline stages spent working.
+///
+/// Concurrent tasks add up, so a stage can be busy for longer than the scan took.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct PhaseTimes {
+    pub extract: Duration,
+    pub summarize: Duration,
+    pub embed: Duration,
+    pub upload: Duration,
+}
+
+/// Outcome of indexing the files of a scan
+#[derive(Debug, Default)]
+pub struct ScanStats {
+    pub indexed_files: usize,
+    pub indexed_points: usize,
+    /// Indexed files per file type
+    pub indexed_by_type: BTreeMap<String, usize>,
+    /// Files whose indexed version is current
+    pub unchanged_files: usize,
+    /// Files whose contents are unchanged but whose metadata was updated
+    pub refreshed_files: usize,
+    pub moved_files: usize,
+    /// Files already indexed by an interrupted earlier run
+    pub resumed_files: usize,
+    /// Files that failed in an earlier run and were tried again
+    pub retried_files: usize,
+    pub failed: Vec<FailedFile>,
+    /// Whether the scan was stopped before every file was processed
+    pub interrupted: bool,
+    pub phase_times: PhaseTimes,
+}
+
+impl ScanStats {
+    /// Files that did not have to go through the pipeline
+    pub fn skipped_files(&self) -> usize {
+        self.unchanged_files + self.refreshed_files + self.resumed_files
+    }
+}
+
+/// Run `work` on every item received from `input` with at most `limit` items
+/// in flight, forwarding the results to `output` and reporting failures.
+///
+/// Both channels are bounded, so a slow downstream stage holds this one back.
+async fn run_stage<I, O, F, Fut>(
+    mut input: mpsc::Receiver<I>,
+    output: mpsc::Sender<O>,
+    failures: mpsc::UnboundedSender<FailedFile>,
+    limit: usize,
+    meter: StageMeter,
+    work: F,
+) where
+    I: PipelineItem + Send + 'static,
+    O: Send + 'static,
+    F: Fn(I) -> Fut,
+    Fut: Future<Output = Result<O, FailedFile>> + Send + 'static,
+{
+    let mut tasks = JoinSet::new();
+    loop {
+        tokio::select! {
+            Some(item) = input.recv(), if tasks.len() < limit => {
+                meter.started(item.file());
+                let (meter, task) = (meter.clone(), work(item));
+                tasks.spawn(async move {
+                    let start = Instant::now();
+                    let result = task.await;
+                    meter.finished(1, start.elapsed());
+                    result
+                });
+            }
+            Some(result) = tasks.join_next() => match result {
+                Ok(Ok(out)) => {
+                    if output.send(out).await.is_err() {
+                        return;
+                    }
+                }
+                Ok(Err(failure)) => {
+                    let _ = failures.send(failure);
+                }
+                Err(e) => meter.bar.suspend(|| eprintln!("Indexing task failed: {}", e)),
+            },
+            else => break,
+        }
+    }
+}
+
+/// Read the text of a file on the blocking thread pool
+async fn extract_job(
+    mut job: IndexJob,
+    scan_config: Arc<ScanConfig>,
+    force_regenerate: bool,
+) -> Result<(IndexJob, Extracted), FailedFile> {
+    let file = job.file.clone();
+
+    tokio::task::spawn_blocking(move || -> Result<(IndexJob, Extracted)> {
+        let extracted = match (scan_config.index_mode, &job.file.filetype) {
+            // Long documents can be indexed passage by passage instead of by summary
+            (IndexMode::Chunks, FileType::Document) => {
+                let content = read_document(&job.file.path).context("Failed to read document")?;
+                let (min_tokens, max_tokens) = scan_config.chunk_tokens;
+                Extracted::Ready(EmbedText::Chunks(chunk_string_with_offsets(
+                    &content,
+                    &scan_config.tokenizer_name,
+                    min_tokens..max_tokens,
+                )?))
+            }
+            // A summary carried by the metadata is reused unless regenerating
+            _ if !force_regenerate && !job.file.summary.is_empty() => {
+                job.text_terms = lexical::text_terms(&job.file.summary);
+                Extracted::Ready(EmbedText::Summary(job.file.summary.clone()))
+            }
+            _ => {
+                let content = extract_content(&job.file)?;
+                if let FileContent::Text(text) = &content {
+                    job.text_terms = lexical::text_terms(text);
+                }
+                Extracted::Content(content)
             }
         };
+        Ok((job, extracted))
+    })
+    .await
+    .map_err(anyhow::Error::from)
+    .and_then(|result| result)
+    .map_err(|e| FailedFile::new(&file, e))
+}
 
-        // Generate embeddings
-        let (dense_embeddings, _sparse_embeddings) =
-            match generate_embeddings(summary.clone(), &model, &sparse_model).await {
-                Ok(embeddings) => embeddings,
-                Err(e) => {
-                    eprintln!("Failed to generate embeddings for {}: {}", file.name, e);
-                    continue;
+/// Summarize extracted contents with the LLM, passing ready texts through
+async fn summarize_job(
+    mut job: IndexJob,
+    extracted: Extracted,
+    summarizer: Arc<dyn Summarizer>,
+) -> Result<(IndexJob, EmbedText), FailedFile> {
+    let text = match extracted {
+        Extracted::Content(content) => {
+            let summary = summarize_content(summarizer.as_ref(), &content)
+                .await
+                .context("Failed to generate summary")
+                .map_err(|e| FailedFile::new(&job.file, e))?;
+            // Images have no text of their own, only what the summary reads off them
+            if job.text_terms.is_empty() {
+                job.text_terms = lexical::text_terms(&summary);
+            }
+            EmbedText::Summary(summary)
+        }
+        Extracted::Ready(text) => text,
+    };
+    Ok((job, text))
+}
+
+/// Payload of the single point of a summarized file
+fn summary_payload(file: &FileMetadata, summary: &str, text_terms: &[String]) -> Payload {
+    let mut payload = file_payload(file);
+    payload.insert("summary".into(), Value::from(summary));
+    payload.insert(lexical::TEXT_TERMS.into(), Value::from(text_terms));
+    payload
+}
+
+/// Payload of a document chunk, recording where it sits in the text so
+/// matches can point at the passage
+fn chunk_payload(file: &FileMetadata, chunk_index: usize, offset: usize, text: &str) -> Payload {
+    let mut payload = file_payload(file);
+    payload.insert("chunk_index".into(), Value::from(chunk_index as i64));
+    payload.insert("chunk_start".into(), Value::from(offset as i64));
+    payload.insert(
+        "chunk_end".into(),
+        Value::from((offset + text.len()) as i64),
+    );
+    payload.insert("chunk_text".into(), Value::from(text));
+    payload.insert(
+        lexical::TEXT_TERMS.into(),
+        Value::from(lexical::text_terms(text)),
+    );
+    payload
+}
+
+/// Embed the texts of a batch of files with one model call per vector type
+fn embed_batch(
+    items: Vec<(IndexJob, EmbedText)>,
+    scan_config: &ScanConfig,
+    model: &TextEmbedding,
+    sparse_model: &SparseTextEmbedding,
+) -> Result<Vec<FilePoints>> {
+    // Dense and sparse input of every point, with the payload it will carry
+    let mut dense_texts: Vec<String> = Vec::new();
+    let mut sparse_texts: Vec<String> = Vec::new();
+    let mut payloads: Vec<Vec<Payload>> = Vec::with_capacity(items.len());
+
+    for (job, text) in &items {
+        let mut file_payloads = Vec::new();
+        match text {
+            EmbedText::Summary(summary) => {
+                // The dense vector covers the opening of the summary, the sparse one all of it
+                let (min_tokens, max_tokens) = scan_config.max_tokens;
+                let first_chunk =
+                    chunk_string(summary, &scan_config.tokenizer_name, min_tokens..max_tokens)?
+                        .into_iter()
+                        .next();
+                if let Some(first_chunk) = first_chunk {
+                    dense_texts.push(first_chunk);
+                    sparse_texts.push(summary.clone());
+                    file_payloads.push(summary_payload(&job.file, summary, &job.text_terms));
                 }
-            };
-
-        // Prepare payload
-        let mut payload = Payload::new();
-        payload.insert("file_name", Value::from(file.name.clone()));
-        payload.insert("file_path", Value::from(file.path.clone()));
-        payload.insert("file_size", Value::from(file.size as i64));
-        payload.insert("summary", Value::from(summary));
-
-        // Create point if embeddings are available
-        if let Some(dense_embedding) = dense_embeddings.first() {
-            let mut vectors_map: HashMap<String, Vec<f32>> = HashMap::new();
-            vectors_map.insert("novum".to_string(), dense_embedding.clone());
-
-            let uuid = Uuid::new_v4();
-            let point = PointStruct::new(uuid.to_string(), vectors_map, payload);
-            points.push(point);
+            }
+            EmbedText::Chunks(chunks) => {
+                for (chunk_index, (offset, chunk)) in chunks.iter().enumerate() {
+                    dense_texts.push(chunk.clone());
+                    sparse_texts.push(chunk.clone());
+                    file_payloads.push(chunk_payload(&job.file, chunk_index, *offset, chunk));
+                }
+            }
+        }
+        payloads.push(file_payloads);
+    }
+
+    let dense_embeddings = model
+        .embed(dense_texts, None)
+        .context("Failed to generate embeddings")?;
+    let sparse_embeddings = sparse_model
+        .embed(sparse_texts, None)
+        .context("Failed to generate sparse embeddings")?;
+
+    let mut dense_embeddings = dense_embeddings.into_iter();
+    let mut sparse_embeddings = sparse_embeddings.iter();
+    Ok(items
+        .into_iter()
+        .zip(payloads)
+        .map(|((job, _), file_payloads)| {
+            let points = file_payloads
+                .into_iter()
+                .zip(dense_embeddings.by_ref())
+                .map(|(payload, dense_embedding)| {
+                    build_point(dense_embedding, sparse_embeddings.next(), payload)
+                })
+                .collect();
+            FilePoints { job, points }
+        })
+        .collect())
+}
+
+/// Embed files in batches on the blocking thread pool
+async fn embed_stage(
+    mut input: mpsc::Receiver<(IndexJob, EmbedText)>,
+    output: mpsc::Sender<FilePoints>,
+    failures: mpsc::UnboundedSender<FailedFile>,
+    scan_config: Arc<ScanConfig>,
+    model: Arc<TextEmbedding>,
+    sparse_model: Arc<SparseTextEmbedding>,
+    meter: StageMeter,
+) {
+    let batch_size = scan_config.embed_batch_size.max(1);
+    let mut batch = Vec::with_capacity(batch_size);
+
+    while input.recv_many(&mut batch, batch_size).await > 0 {
+        let items = std::mem::take(&mut batch);
+        let files: Vec<FileMetadata> = items.iter().map(|(job, _)| job.file.clone()).collect();
+        meter.started(&files[0]);
+
+        let start = Instant::now();
+        let (scan_config, model, sparse_model) =
+            (scan_config.clone(), model.clone(), sparse_model.clone());
+        let result = tokio::task::spawn_blocking(move || {
+            embed_batch(items, &scan_config, &model, &sparse_model)
+        })
+        .await
+        .map_err(anyhow::Error::from)
+        .and_then(|result| result);
+        meter.finished(files.len(), start.elapsed());
+
+        match result {
+            Ok(files) => {
+                for file_points in files {
+                    if output.send(file_points).await.is_err() {
+                        return;
+                    }
+                }
+            }
+            Err(e) => {
+                for file in &files {
+                    let _ = failures.send(FailedFile {
+                        path: file.path.clone(),
+                        reason: format!("{:#}", e),
+                    });
+                }
+            }
+        }
+    }
+}
+
+/// Compare every file against the index and queue those that need indexing.
+///
+/// Unchanged files are skipped, touched files get their metadata refreshed and
+/// moved files keep their points, all without going through the pipeline.
+/// Counts what it did in a fresh [`ScanStats`].
+async fn plan_jobs(
+    store: Arc<dyn VectorStore>,
+    collection_name: String,
+    files: Vec<FileMetadata>,
+    force_regenerate: bool,
+    journal: JournalSnapshot,
+    jobs: mpsc::Sender<IndexJob>,
+    progress: ScanProgress,
+) -> Result<ScanStats> {
+    let store = store.as_ref();
+    let collection_name = collection_name.as_str();
+    let mut stats = ScanStats::default();
+
+    for mut file in files {
+        // Files finished by an interrupted run are not looked at again
+        if journal.is_done(&file) {
+            stats.resumed_files += 1;
+            continue;
+        }
+
+        // Compare the file against what is already indexed
+        let indexed = fetch_indexed_file(store, collection_name, &file.path).await?;
+        let action = if force_regenerate {
+            ensure_content_hash(&mut file).await;
+            IndexAction::Reindex
         } else {
-            eprintln!("No dense embeddings generated for file: {}", file.name);
+            plan_index_action(&mut file, indexed.as_ref()).await
+        };
+
+        match action {
+            IndexAction::Skip => {
+                info!("File path '{}' is unchanged. Skipping.", file.path);
+                stats.unchanged_files += 1;
+                continue;
+            }
+            IndexAction::RefreshMetadata => {
+                if let Some(indexed) = indexed {
+                    refresh_file_metadata(store, collection_name, &file, indexed.point_ids).await?;
+                }
+                info!(
+                    "File path '{}' has identical contents. Skipping.",
+                    file.path
+                );
+                stats.refreshed_files += 1;
+                continue;
+            }
+            IndexAction::Reindex => {}
+        }
+
+        // A new path with known contents is a moved file, keep its summary
+        if indexed.is_none() && !file.content_hash.is_empty() {
+            if let Some((old_path, point_ids)) =
+                find_moved_file(store, collection_name, &file.content_hash).await?
+            {
+                relocate_points(store, collection_name, point_ids, &file).await?;
+                progress.println(format!("File '{}' was moved to '{}'.", old_path, file.path));
+                stats.moved_files += 1;
+                continue;
+            }
+        }
+
+        if journal.has_failed(&file.path) {
+            stats.retried_files += 1;
         }
+        let job = IndexJob {
+            file,
+            stale_point_ids: indexed.map(|indexed| indexed.point_ids).unwrap_or_default(),
+            text_terms: Vec::new(),
+        };
+        if jobs.send(job).await.is_err() {
+            break;
+        }
+        progress.queued();
+    }
+
+    Ok(stats)
+}
+
+/// Points waiting to be written, with the points of earlier versions they replace
+#[derive(Default)]
+struct UploadBuffer {
+    points: Vec<IndexPoint>,
+    stale_point_ids: Vec<String>,
+    files: Vec<FileMetadata>,
+}
+
+/// Write the buffered points, drop the outdated points of their files and
+/// record the files as done in the journal
+async fn flush_upload(
+    store: &dyn VectorStore,
+    collection_name: &str,
+    buffer: &mut UploadBuffer,
+    journal: &mut ScanJournal,
+    stats: &mut ScanStats,
+    meter: &StageMeter,
+) -> Result<()> {
+    let UploadBuffer {
+        points,
+        stale_point_ids,
+        files,
+    } = std::mem::take(buffer);
+    let point_count = points.len();
+    if let Some(file) = files.last() {
+        meter.started(file);
     }
+    let start = Instant::now();
 
-    // Upsert points
     if !points.is_empty() {
-        client
-            .upsert_points(UpsertPoints {
-                collection_name: "file_data".to_string(),
-                wait: Some(true),
-                points: points.clone(),
-                ..Default::default()
-            })
+        let terms = lexical::terms_of(points.iter().map(|point| &point.payload));
+        store
+            .upsert(collection_name, points)
             .await
             .context("Failed to upsert points")?;
+        lexical::record(store, collection_name, terms).await;
+    }
+    if !stale_point_ids.is_empty() {
+        store
+            .delete(collection_name, stale_point_ids)
+            .await
+            .context("Failed to delete outdated points")?;
+    }
+    // The journal may only list files whose points are on disk
+    store.flush().await.context("Failed to write points")?;
 
-        println!("Points upserted successfully: {} files", points.len());
+    for file in &files {
+        journal.record_done(file)?;
+        *stats
+            .indexed_by_type
+            .entry(file.filetype.as_str().to_string())
+            .or_default() += 1;
+    }
+    stats.indexed_points += point_count;
+    stats.indexed_files += files.len();
+    meter.finished(files.len(), start.elapsed());
+
+    Ok(())
+}
+
+/// Index files through a staged pipeline.
+///
+/// Files are planned against the index, extracted on the blocking thread
+/// pool, summarized with up to `llm_concurrency` concurrent LLM requests,
+/// embedded in batches and upserted in batches as they complete. Bounded
+/// queues between the stages keep a slow stage from piling up work.
+///
+/// Progress goes to the journal after every upsert, so a scan stopped with
+/// Ctrl-C, or by a crash, picks up where it left off when run again. With
+/// `force_regenerate`, unchanged files are summarized and embedded again too.
+#[allow(clippy::too_many_arguments)]
+pub async fn process_and_upload_files(
+    store: Arc<dyn VectorStore>,
+    summarizer: Arc<dyn Summarizer>,
+    config: &Config,
+    model: Arc<TextEmbedding>,
+    sparse_model: Arc<SparseTextEmbedding>,
+    file_metadata_list: &[FileMetadata],
+    journal: &mut ScanJournal,
+    progress: &ScanProgress,
+    force_regenerate: bool,
+) -> Result<ScanStats> {
+    let collection_name = config.collection_name.as_str();
+    let scan_config = Arc::new(config.scan.clone());
+
+    let (job_tx, job_rx) = mpsc::channel::<IndexJob>(STAGE_QUEUE_SIZE);
+    let (extracted_tx, extracted_rx) = mpsc::channel(STAGE_QUEUE_SIZE);
+    let (summarized_tx, summarized_rx) = mpsc::channel(STAGE_QUEUE_SIZE);
+    let (points_tx, mut points_rx) = mpsc::channel::<FilePoints>(STAGE_QUEUE_SIZE);
+    let (failure_tx, mut failure_rx) = mpsc::unbounded_channel::<FailedFile>();
+
+    progress.start_pipeline();
+    let extract_meter = StageMeter::new(progress.extract.clone());
+    let summarize_meter = StageMeter::new(progress.summarize.clone());
+    let embed_meter = StageMeter::new(progress.embed.clone());
+    let upload_meter = StageMeter::new(progress.upload.clone());
+
+    let planner = tokio::spawn(plan_jobs(
+        store.clone(),
+        collection_name.to_string(),
+        file_metadata_list.to_vec(),
+        force_regenerate,
+        journal.snapshot(),
+        job_tx,
+        progress.clone(),
+    ));
+
+    // Extraction is CPU and disk bound, run as much of it as there are cores
+    let extract_limit = std::thread::available_parallelism().map_or(1, NonZeroUsize::get);
+    let extract_config = scan_config.clone();
+    tokio::spawn(run_stage(
+        job_rx,
+        extracted_tx,
+        failure_tx.clone(),
+        extract_limit,
+        extract_meter.clone(),
+        move |job| extract_job(job, extract_config.clone(), force_regenerate),
+    ));
+
+    tokio::spawn(run_stage(
+        extracted_rx,
+        summarized_tx,
+        failure_tx.clone(),
+        scan_config.llm_concurrency.max(1),
+        summarize_meter.clone(),
+        move |(job, extracted)| summarize_job(job, extracted, summarizer.clone()),
+    ));
+
+    tokio::spawn(embed_stage(
+        summarized_rx,
+        points_tx,
+        failure_tx,
+        scan_config.clone(),
+        model,
+        sparse_model,
+        embed_meter.clone(),
+    ));
+
+    // Upload points in batches as files come out of the pipeline
+    let mut stats = ScanStats::default();
+    let mut failed = Vec::new();
+    let mut buffer = UploadBuffer::default();
+    let mut record_failure = |failure: FailedFile, journal: &mut ScanJournal| -> Result<()> {
+        progress.println(format!(
+            "Failed to index {}: {}",
+            failure.path, failure.reason
+        ));
+        progress.upload.inc(1);
+        journal.record_failed(&failure.path, &failure.reason)?;
+        failed.push(failure);
+        Ok(())
+    };
+    let ctrl_c = tokio::signal::ctrl_c();
+    tokio::pin!(ctrl_c);
+
+    loop {
+        tokio::select! {
+            file_points = points_rx.recv() => {
+                let Some(FilePoints { job, points }) = file_points else {
+                    break;
+                };
+                if points.is_empty() {
+                    let failure = FailedFile {
+                        path: job.file.path.clone(),
+                        reason: "No dense embeddings generated".to_string(),
+                    };
+                    record_failure(failure, journal)?;
+                    continue;
+                }
+
+                buffer.points.extend(points);
+                buffer.stale_point_ids.extend(job.stale_point_ids);
+                buffer.files.push(job.file);
+
+                if buffer.points.len() >= scan_config.upsert_batch_size {
+                    flush_upload(
+                        store.as_ref(),
+                        collection_name,
+                        &mut buffer,
+                        journal,
+                        &mut stats,
+                        &upload_meter,
+                    )
+                    .await?;
+                }
+            }
+            Some(failure) = failure_rx.recv() => {
+                record_failure(failure, journal)?;
+            }
+            _ = &mut ctrl_c => {
+                // Keep what is finished, the files still in flight are redone on the next run
+                progress.println("Interrupted, saving progress...");
+                stats.interrupted = true;
+                break;
+            }
+        }
+    }
+    flush_upload(
+        store.as_ref(),
+        collection_name,
+        &mut buffer,
+        journal,
+        &mut stats,
+        &upload_meter,
+    )
+    .await?;
+
+    if stats.interrupted {
+        planner.abort();
     } else {
-        println!("No new files to upsert.");
+        // Failures reported after the last points came through
+        while let Ok(failure) = failure_rx.try_recv() {
+            record_failure(failure, journal)?;
+        }
+
+        let plan_stats = planner.await??;
+        stats = ScanStats {
+            indexed_files: stats.indexed_files,
+            indexed_points: stats.indexed_points,
+            indexed_by_type: stats.indexed_by_type,
+            ..plan_stats
+        };
+    }
+    stats.failed = failed;
+    stats.phase_times = PhaseTimes {
+        extract: extract_meter.busy(),
+        summarize: summarize_meter.busy(),
+        embed: embed_meter.busy(),
+        upload: upload_meter.busy(),
+    };
+    // Metadata refreshed by the planner after the last upload
+    store.flush().await.context("Failed to write points")?;
+
+    Ok(stats)
+}
+
+/// Outcome of pruning the index against a directory scan
+#[derive(Serialize, Deserialize, Debug, Default, Clone)]
+pub struct PruneSummary {
+    pub deleted_files: usize,
+    pub deleted_points: usize,
+    pub relocated_files: usize,
+}
+
+/// Removes points for files under `root` that no longer exist on disk.
+///
+/// Points whose content hash matches a scanned file that is not indexed yet
+/// are treated as moved and get their path rewritten instead of being deleted.
+/// Files that still exist but were left out of the scan by ignore files or
+/// patterns keep their points, so a narrowed scan never empties the index.
+pub async fn prune_index(
+    store: &dyn VectorStore,
+    collection_name: &str,
+    root: &Path,
+    scan_result: &DirScanResult,
+) -> Result<PruneSummary> {
+    // Collect the indexed points under the root, grouped by file path
+    let mut indexed: HashMap<String, (Option<String>, Vec<String>)> = HashMap::new();
+    let points = store
+        .query_filter(collection_name, &Filter::default(), None)
+        .await
+        .context("Failed to scroll indexed points")?;
+
+    for point in points {
+        let Some(path) = point.payload.get("file_path").and_then(Value::as_str) else {
+            continue;
+        };
+        if !Path::new(path).starts_with(root) {
+            continue;
+        }
+        let content_hash = point
+            .payload
+            .get("content_hash")
+            .and_then(Value::as_str)
+            .map(str::to_string);
+        let entry = indexed
+            .entry(path.to_string())
+            .or_insert_with(|| (content_hash, Vec::new()));
+        entry.1.push(point.id);
+    }
+
+    let scanned: HashSet<&str> = scan_result.file_list.iter().map(String::as_str).collect();
+    let indexed_paths: HashSet<String> = indexed.keys().cloned().collect();
+    indexed.retain(|path, _| !scanned.contains(path.as_str()) && !Path::new(path).exists());
+    if indexed.is_empty() {
+        return Ok(PruneSummary::default());
+    }
+
+    // Scanned files that are not indexed yet, by content hash, are move targets.
+    // The walk does not hash, so they are hashed only when something is gone.
+    let mut unindexed_by_hash: HashMap<String, FileMetadata> = HashMap::new();
+    for file in &scan_result.file_metadata_list {
+        if indexed_paths.contains(&file.path) {
+            continue;
+        }
+        let mut file = file.clone();
+        ensure_content_hash(&mut file).await;
+        if !file.content_hash.is_empty() {
+            unindexed_by_hash.insert(file.content_hash.clone(), file);
+        }
+    }
+
+    let mut summary = PruneSummary::default();
+    let mut orphaned_point_ids: Vec<String> = Vec::new();
+
+    for (path, (content_hash, point_ids)) in indexed {
+        let moved_to = content_hash
+            .as_deref()
+            .and_then(|hash| unindexed_by_hash.remove(hash));
+
+        match moved_to {
+            Some(file) => {
+                relocate_points(store, collection_name, point_ids, &file).await?;
+                eprintln!("File '{}' was moved to '{}'.", path, file.path);
+                summary.relocated_files += 1;
+            }
+            None => {
+                info!("Pruning deleted file: {}", path);
+                summary.deleted_files += 1;
+                summary.deleted_points += point_ids.len();
+                orphaned_point_ids.extend(point_ids);
+            }
+        }
+    }
+
+    let deleted = !orphaned_point_ids.is_empty();
+    if deleted {
+        store
+            .delete(collection_name, orphaned_point_ids)
+            .await
+            .context("Failed to delete orphaned points")?;
+    }
+    store.flush().await.context("Failed to write points")?;
+    if deleted {
+        forget_vocabulary(store, collection_name).await;
+    }
+
+    Ok(summary)
+}
+
+/// Removes the points of deleted files, and of every file below deleted folders.
+///
+/// Paths that exist again by the time this runs were replaced rather than
+/// deleted and keep their points.
+pub async fn delete_paths(
+    store: &dyn VectorStore,
+    collection_name: &str,
+    paths: &[PathBuf],
+) -> Result<PruneSummary> {
+    let mut deleted_files = HashSet::new();
+    let mut deleted_point_ids: Vec<String> = Vec::new();
+
+    for path in paths {
+        if path.exists() {
+            continue;
+        }
+        let path = path.to_string_lossy();
+
+        // A file is matched by its own path, a folder by the paths of its files
+        for field in ["file_path", "parent_dirs"] {
+            let points = store
+                .query_filter(
+                    collection_name,
+                    &Filter::must([Condition::keyword(field, path.as_ref())]),
+                    None,
+                )
+                .await
+                .with_context(|| format!("Failed to look up deleted path: {}", path))?;
+
+            for point in points {
+                if let Some(file_path) = point.payload.get("file_path").and_then(Value::as_str) {
+                    if deleted_files.insert(file_path.to_string()) {
+                        info!("Removing deleted file: {}", file_path);
+                    }
+                }
+                deleted_point_ids.push(point.id);
+            }
+        }
+    }
+
+    // A file can be matched through more than one of the deleted paths
+    deleted_point_ids.sort();
+    deleted_point_ids.dedup();
+    let summary = PruneSummary {
+        deleted_files: deleted_files.len(),
+        deleted_points: deleted_point_ids.len(),
+        relocated_files: 0,
+    };
+    let deleted = !deleted_point_ids.is_empty();
+    if deleted {
+        store
+            .delete(collection_name, deleted_point_ids)
+            .await
+            .context("Failed to delete points of deleted files")?;
+    }
+    store.flush().await.context("Failed to write points")?;
+    if deleted {
+        forget_vocabulary(store, collection_name).await;
+    }
+
+    Ok(summary)
+}
+
+/// Drop the vocabulary once points were deleted, the next query builds it
+/// from the points that are left. The points are gone either way, so a
+/// failure is only logged.
+async fn forget_vocabulary(store: &dyn VectorStore, collection_name: &str) {
+    if let Err(e) = lexical::reset(store, collection_name).await {
+        warn!("{:#}", e);
+    }
+}
+
+/// Outcome of re-embedding a collection with the configured models
+#[derive(Debug, Default)]
+pub struct MigrateSummary {
+    pub migrated_points: usize,
+    pub dropped_points: usize,
+    pub backup_path: Option<PathBuf>,
+}
+
+/// Collection a migration is built in before it replaces the original
+fn staging_collection(collection_name: &str) -> String {
+    format!("{}-migrating", collection_name)
+}
+
+/// Re-embed every point of the collection with the configured models.
+///
+/// Points are embedded again from the summary or chunk text kept in their
+/// payload, so no LLM calls are made. The payloads are backed up to the data
+/// directory first. The new points are written to a staging collection, and
+/// the original is only replaced once that one is complete. When replacing it
+/// fails, the staging collection is kept and the next run finishes the switch.
+pub async fn migrate_collection(
+    store: &dyn VectorStore,
+    config: &Config,
+    model: Arc<TextEmbedding>,
+    sparse_model: Arc<SparseTextEmbedding>,
+) -> Result<MigrateSummary> {
+    let collection_name = config.collection_name.as_str();
+    let staging = staging_collection(collection_name);
+    let target = embedding_metadata(&config.embedding)?;
+    let mut summary = MigrateSummary::default();
+
+    // The metadata of the staging collection is written last, only complete ones have it
+    if store.collection_exists(&staging).await? {
+        match store.collection_metadata(&staging).await? {
+            Some(built) if built == target => {
+                eprintln!(
+                    "Finishing the interrupted migration of '{}' to {}",
+                    collection_name, target.dense_model
+                );
+                summary.migrated_points =
+                    switch_to_migrated(store, collection_name, &staging, &target).await?;
+                return Ok(summary);
+            }
+            // The collection may be half replaced already, the staging one is all there is
+            Some(built) => bail!(
+                "The migration of '{}' to {} was interrupted and its points are kept in '{}'. Set `dense_model` to {} and run `arborist migrate` again to finish it.",
+                collection_name,
+                built.dense_model,
+                staging,
+                built.dense_model
+            ),
+            None => store.delete_collection(&staging).await?,
+        }
+    }
+
+    let (current, recorded) = stored_embedding_metadata(store, collection_name).await?;
+    if current == target {
+        if !recorded {
+            store
+                .set_collection_metadata(collection_name, &target)
+                .await?;
+        }
+        println!(
+            "Collection '{}' already uses {}.",
+            collection_name, target.dense_model
+        );
+        return Ok(summary);
+    }
+
+    let points: Vec<StoredPoint> = store
+        .query_filter(collection_name, &Filter::default(), None)
+        .await
+        .context("Failed to read indexed points")?
+        .into_iter()
+        .filter(|point| point.payload.contains_key("file_path"))
+        .collect();
+
+    // Keep the payloads around in case the migration is interrupted
+    let backup_path = data_dir().join(format!(
+        "migrate-{}-{}.json",
+        collection_name,
+        unix_timestamp(SystemTime::now())
+    ));
+    let backup: Vec<(&str, &Payload)> = points
+        .iter()
+        .map(|point| (point.id.as_str(), &point.payload))
+        .collect();
+    fs::create_dir_all(data_dir())?;
+    fs::write(&backup_path, serde_json::to_vec(&backup)?)
+        .with_context(|| format!("Failed to write backup: {}", backup_path.display()))?;
+    summary.backup_path = Some(backup_path);
+
+    // Write the new collection next to the old one, so a failing model
+    // leaves the old index intact
+    store
+        .create_collection(&staging, &collection_schema(&target))
+        .await?;
+    create_payload_indexes(store, &staging).await?;
+    let scan_config = Arc::new(config.scan.clone());
+    let mut points = points.into_iter().peekable();
+    while points.peek().is_some() {
+        let batch: Vec<StoredPoint> = points.by_ref().take(MIGRATE_BATCH_SIZE).collect();
+        let (scan_config, model, sparse_model) =
+            (scan_config.clone(), model.clone(), sparse_model.clone());
+        let (embedded, dropped) = tokio::task::spawn_blocking(move || {
+            embed_stored_points(batch, &scan_config, &model, &sparse_model)
+        })
+        .await??;
+        summary.migrated_points += embedded.len();
+        summary.dropped_points += dropped;
+        if !embedded.is_empty() {
+            store
+                .upsert(&staging, embedded)
+                .await
+                .context("Failed to upsert migrated points")?;
+        }
+    }
+    store
+        .flush()
+        .await
+        .context("Failed to write migrated points")?;
+    store.set_collection_metadata(&staging, &target).await?;
+
+    switch_to_migrated(store, collection_name, &staging, &target).await?;
+    Ok(summary)
+}
+
+/// Embed stored points again from the text in their payload.
+///
+/// Returns the new points and the number of points without text, which are dropped.
+fn embed_stored_points(
+    points: Vec<StoredPoint>,
+    scan_config: &ScanConfig,
+    model: &TextEmbedding,
+    sparse_model: &SparseTextEmbedding,
+) -> Result<(Vec<IndexPoint>, usize)> {
+    let mut dense_texts: Vec<String> = Vec::new();
+    let mut sparse_texts: Vec<String> = Vec::new();
+    let mut kept: Vec<StoredPoint> = Vec::new();
+    let mut dropped = 0;
+
+    for point in points {
+        let chunk_text = point.payload.get("chunk_text").and_then(Value::as_str);
+        let summary_text = point.payload.get("summary").and_then(Value::as_str);
+        let (dense_text, sparse_text) = match (chunk_text, summary_text) {
+            (Some(text), _) => (Some(text.to_string()), text.to_string()),
+            // The dense vector covers the opening of the summary, the sparse one all of it
+            (None, Some(text)) => {
+                let (min_tokens, max_tokens) = scan_config.max_tokens;
+                let first_chunk =
+                    chunk_string(text, &scan_config.tokenizer_name, min_tokens..max_tokens)?
+                        .into_iter()
+                        .next();
+                (first_chunk, text.to_string())
+            }
+            (None, None) => (None, String::new()),
+        };
+        let Some(dense_text) = dense_text else {
+            dropped += 1;
+            continue;
+        };
+        dense_texts.push(dense_text);
+        sparse_texts.push(sparse_text);
+        kept.push(point);
+    }
+
+    if kept.is_empty() {
+        return Ok((Vec::new(), dropped));
     }
+    let dense_embeddings = model
+        .embed(dense_texts, None)
+        .context("Failed to generate embeddings")?;
+    let sparse_embeddings = sparse_model
+        .embed(sparse_texts, None)
+        .context("Failed to generate sparse embeddings")?;
+
+    let migrated = kept
+        .into_iter()
+        .zip(dense_embeddings)
+        .zip(&sparse_embeddings)
+        .map(|((point, dense_embedding), sparse_embedding)| IndexPoint {
+            id: point.id,
+            ..build_point(dense_embedding, Some(sparse_embedding), point.payload)
+        })
+        .collect();
+    Ok((migrated, dropped))
+}
+
+/// Copy every point of one collection to another, a page at a time.
+///
+/// Returns the number of points copied.
+async fn copy_points(store: &dyn VectorStore, from: &str, to: &str) -> Result<usize> {
+    create_payload_indexes(store, to).await?;
+    let mut copied = 0;
+    let mut offset = None;
+    loop {
+        let (points, next) = store
+            .scroll_vectors(from, offset.take(), MIGRATE_BATCH_SIZE)
+            .await
+            .with_context(|| format!("Failed to read the points of '{}'", from))?;
+        copied += points.len();
+        if !points.is_empty() {
+            store
+                .upsert(to, points)
+                .await
+                .context("Failed to upsert migrated points")?;
+        }
+        match next {
+            Some(next) => offset = Some(next),
+            None => break,
+        }
+    }
+    store
+        .flush()
+        .await
+        .context("Failed to write migrated points")?;
+    Ok(copied)
+}
+
+/// Replace the collection by the staging collection, returning the number of points moved over.
+///
+/// The staging collection is only removed once the collection is complete
+/// again, until then `check_interrupted_migration` stops other commands and
+/// `arborist migrate` starts the copy over.
+async fn switch_to_migrated(
+    store: &dyn VectorStore,
+    collection_name: &str,
+    staging: &str,
+    target: &CollectionMetadata,
+) -> Result<usize> {
+    let replace = async {
+        store.delete_collection(collection_name).await?;
+        store
+            .create_collection(collection_name, &collection_schema(target))
+            .await?;
+        let copied = copy_points(store, staging, collection_name).await?;
+        store
+            .set_collection_metadata(collection_name, target)
+            .await?;
+        anyhow::Ok(copied)
+    };
+    let migrated_points = replace.await.with_context(|| {
+        format!(
+            "Failed to replace collection '{}', the migrated points are kept in '{}'. Run `arborist migrate` again to finish.",
+            collection_name, staging
+        )
+    })?;
 
+    store.delete_collection(staging).await?;
+    // The terms are the same, but the vocabulary is rebuilt along with the index
+    lexical::reset(store, collection_name).await?;
+    Ok(migrated_points)
+}
+
+/// Fail while a migration is left half done.
+///
+/// A complete staging collection means the collection may have been replaced
+/// only partly, so nothing else should touch it until `arborist migrate`
+/// finishes the switch. An incomplete one is left over from a migration that
+/// failed while embedding and is only reported.
+pub async fn check_interrupted_migration(store: &dyn VectorStore, config: &Config) -> Result<()> {
+    let staging = staging_collection(&config.collection_name);
+    if !store.collection_exists(&staging).await? {
+        return Ok(());
+    }
+    if store.collection_metadata(&staging).await?.is_some() {
+        bail!(
+            "The migration of collection '{}' was interrupted while replacing it, its points are kept in '{}'. Run `arborist migrate` to finish it.",
+            config.collection_name,
+            staging
+        );
+    }
+    warn!(
+        "Collection '{}' is left over from an interrupted migration of '{}', `arborist migrate` removes it",
+        staging, config.collection_name
+    );
     Ok(())
 }
 
-/// Query the database using a vector and print matching file paths
-pub async fn query_and_print_file_paths(
-    client: &Qdrant,
+/// Factor by which queries over-fetch points before grouping them by file
+const GROUP_OVERFETCH: usize = 4;
+
+/// A passage of a document matched at chunk level
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Passage {
+    pub chunk_index: i64,
+    pub start: i64,
+    pub end: i64,
+    pub text: String,
+}
+
+/// The best match for a file, with the matching passage when chunks are indexed
+#[derive(Debug, Clone)]
+pub struct FileMatch {
+    /// Semantic score of the best point, 0 for files only matched lexically
+    pub score: f32,
+    pub payload: Payload,
+    pub passage: Option<Passage>,
+    /// How well the name, path or identifiers matched the query, see `lexical::search`
+    pub lexical_score: Option<f32>,
+    /// Reciprocal rank score the matches are ordered by, when lexical matches were blended in
+    pub blended_score: Option<f32>,
+}
+
+/// Keep only the best scoring point of every file, in score order
+pub fn group_by_file(points: Vec<ScoredPoint>, limit: usize) -> Vec<FileMatch> {
+    let mut seen: HashSet<String> = HashSet::new();
+    let mut matches = Vec::new();
+
+    // Points arrive sorted by score, so the first point of a file is its best
+    for point in points {
+        let file_path = point
+            .payload
+            .get("file_path")
+            .and_then(Value::as_str)
+            .unwrap_or(&point.id)
+            .to_string();
+        if !seen.insert(file_path) {
+            continue;
+        }
+
+        let passage = point
+            .payload
+            .get("chunk_text")
+            .and_then(Value::as_str)
+            .map(|text| Passage {
+                chunk_index: point
+                    .payload
+                    .get("chunk_index")
+                    .and_then(Value::as_i64)
+                    .unwrap_or(0),
+                start: point
+                    .payload
+                    .get("chunk_start")
+                    .and_then(Value::as_i64)
+                    .unwrap_or(0),
+                end: point
+                    .payload
+                    .get("chunk_end")
+                    .and_then(Value::as_i64)
+                    .unwrap_or(0),
+                text: text.to_string(),
+            });
+
+        matches.push(FileMatch {
+            score: point.score,
+            payload: point.payload,
+            passage,
+            lexical_score: None,
+            blended_score: None,
+        });
+        if matches.len() == limit {
+            break;
+        }
+    }
+
+    matches
+}
+
+/// Query the database with hybrid dense and sparse search, returning the best match of each file
+pub async fn query_files(
+    store: &dyn VectorStore,
     collection_name: &str,
     query_vector: Vec<f32>,
-    limit: usize,
-    use_sparse: bool, // Option to toggle between dense and sparse vector search
-) -> anyhow::Result<()> {
-    // Specify the vector type to use in the query
-    let vector_type = if use_sparse { "splade" } else { "novum" };
-
-    // Perform the query
-    let query_result = client
-        .query(
-            QueryPointsBuilder::new(collection_name)
-                .query(query_vector)
-                .using(vector_type)
-                .limit(limit as u64)
-                .with_payload(true)
-                .params(SearchParamsBuilder::default().hnsw_ef(128).exact(false)), // Configure search parameters
+    sparse_query_vector: SparseVector,
+    filter: &Filter,
+    query_config: &QueryConfig,
+) -> Result<Vec<FileMatch>> {
+    let fusion = match query_config.fusion {
+        FusionMode::Rrf => Fusion::Rrf,
+        FusionMode::Weighted => Fusion::Weighted {
+            dense: query_config.dense_weight,
+            sparse: query_config.sparse_weight,
+        },
+    };
+
+    // Perform the query, over-fetching so chunks of the same file can be grouped
+    let query_result = store
+        .query_hybrid(
+            collection_name,
+            &HybridQuery {
+                dense: Some((DENSE_VECTOR_NAME.to_string(), query_vector)),
+                sparse: Some((SPARSE_VECTOR_NAME.to_string(), sparse_query_vector)),
+                filter: filter.clone(),
+                limit: query_config.top_k_results * GROUP_OVERFETCH,
+                fusion,
+            },
         )
         .await?;
 
-    // Extract and print matching file paths
-    for point in query_result.result {
-        //if let Some(payload) = point.payload {
-        //    if let Some(file_path) = payload.get("file_path").and_then(Value::as_str) {
-        //        println!("File Path: {}", file_path);
-        //    }
-        //}
-        println!("Result for Query: {:#?}", point);
+    Ok(group_by_file(query_result, query_config.top_k_results))
+}
+
+/// Dense and sparse vectors standing for a whole file
+#[derive(Debug, Clone)]
+pub struct FileVectors {
+    pub dense: Vec<f32>,
+    pub sparse: SparseVector,
+}
+
+impl FileVectors {
+    /// Mean of the vectors of a file's points, there is one point per chunk in `chunks` mode
+    pub fn mean(points: &[IndexPoint]) -> Option<Self> {
+        let dense: Vec<&Vec<f32>> = points
+            .iter()
+            .filter_map(|point| point.dense.get(DENSE_VECTOR_NAME))
+            .collect();
+        let mut mean = vec![0.0; dense.first()?.len()];
+        for vector in &dense {
+            for (sum, value) in mean.iter_mut().zip(vector.iter()) {
+                *sum += value;
+            }
+        }
+        for value in &mut mean {
+            *value /= dense.len() as f32;
+        }
+
+        let sparse: Vec<&SparseVector> = points
+            .iter()
+            .filter_map(|point| point.sparse.get(SPARSE_VECTOR_NAME))
+            .collect();
+        let mut weights: BTreeMap<u32, f32> = BTreeMap::new();
+        for vector in &sparse {
+            for (&index, &value) in vector.indices.iter().zip(&vector.values) {
+                *weights.entry(index).or_default() += value;
+            }
+        }
+        let (indices, values) = weights
+            .into_iter()
+            .map(|(index, weight)| (index, weight / sparse.len() as f32))
+            .unzip();
+
+        Some(FileVectors {
+            dense: mean,
+            sparse: SparseVector { indices, values },
+        })
     }
+}
 
-    Ok(())
+/// The vectors of an indexed file, `None` when it is not indexed
+pub async fn file_vectors(
+    store: &dyn VectorStore,
+    collection_name: &str,
+    file_path: &str,
+) -> Result<Option<FileVectors>> {
+    let points = store
+        .query_vectors(
+            collection_name,
+            &Filter::must([Condition::keyword("file_path", file_path)]),
+            None,
+        )
+        .await
+        .context("Failed to read the vectors of the file")?;
+    Ok(FileVectors::mean(&points))
+}
+
+/// Embed a file the way a scan does, without writing it to the index
+pub async fn embed_file(
+    file: FileMetadata,
+    scan_config: &ScanConfig,
+    summarizer: Arc<dyn Summarizer>,
+    model: Arc<TextEmbedding>,
+    sparse_model: Arc<SparseTextEmbedding>,
+) -> Result<Vec<IndexPoint>> {
+    let scan_config = Arc::new(scan_config.clone());
+    let job = IndexJob {
+        file,
+        stale_point_ids: Vec::new(),
+        text_terms: Vec::new(),
+    };
+    let failed = |failure: FailedFile| anyhow!(failure.reason);
+
+    let (job, extracted) = extract_job(job, scan_config.clone(), false)
+        .await
+        .map_err(failed)?;
+    let item = summarize_job(job, extracted, summarizer)
+        .await
+        .map_err(failed)?;
+    let files = tokio::task::spawn_blocking(move || {
+        embed_batch(vec![item], &scan_config, &model, &sparse_model)
+    })
+    .await??;
+
+    Ok(files.into_iter().flat_map(|file| file.points).collect())
+}
+
+/// What the index holds for a file
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct FileDetail {
+    pub path: String,
+    pub name: Option<String>,
+    pub size: Option<u64>,
+    pub filetype: Option<String>,
+    pub extension: Option<String>,
+    /// Unix timestamps in seconds
+    pub created_at: Option<i64>,
+    pub modified_at: Option<i64>,
+    pub content_hash: Option<String>,
+    pub summary: Option<String>,
+    /// Points stored for the file, one per chunk when chunks are indexed
+    pub points: usize,
+}
+
+/// Look up the indexed metadata and summary of a file
+pub async fn file_detail(
+    store: &dyn VectorStore,
+    collection_name: &str,
+    file_path: &str,
+) -> Result<Option<FileDetail>> {
+    let points = store
+        .query_filter(
+            collection_name,
+            &Filter::must([Condition::keyword("file_path", file_path)]),
+            None,
+        )
+        .await
+        .context("Failed to query file")?;
+
+    let Some(first) = points.first() else {
+        return Ok(None);
+    };
+    let payload = &first.payload;
+    let string = |key: &str| payload.get(key).and_then(Value::as_str).map(str::to_string);
+
+    Ok(Some(FileDetail {
+        path: file_path.to_string(),
+        name: string("file_name"),
+        size: payload.get("file_size").and_then(Value::as_u64),
+        filetype: string("filetype"),
+        extension: string("extension"),
+        created_at: payload.get("created_at").and_then(Value::as_i64),
+        modified_at: payload.get("modified_at").and_then(Value::as_i64),
+        content_hash: string("content_hash"),
+        // Chunk points carry no summary, any point of a summarized file does
+        summary: points.iter().find_map(|point| {
+            point
+                .payload
+                .get("summary")
+                .and_then(Value::as_str)
+                .map(str::to_string)
+        }),
+        points: points.len(),
+    }))
+}
+
+/// Size of the index
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct CollectionStats {
+    pub collection: String,
+    /// Models the collection was embedded with
+    pub embedding: CollectionMetadata,
+    pub points: usize,
+    pub files: usize,
+    /// Bytes of the indexed files
+    pub total_size: u64,
+    pub files_by_type: BTreeMap<String, usize>,
+}
+
+/// Count the points and files of the collection
+pub async fn collection_stats(
+    store: &dyn VectorStore,
+    collection_name: &str,
+) -> Result<CollectionStats> {
+    let (embedding, _) = stored_embedding_metadata(store, collection_name).await?;
+    let points = store
+        .query_filter(collection_name, &Filter::default(), None)
+        .await
+        .context("Failed to scroll indexed points")?;
+
+    let mut stats = CollectionStats {
+        collection: collection_name.to_string(),
+        embedding,
+        points: points.len(),
+        files: 0,
+        total_size: 0,
+        files_by_type: BTreeMap::new(),
+    };
+    // Chunked files have several points, count each file once
+    let mut seen: HashSet<&str> = HashSet::new();
+    for point in &points {
+        let Some(path) = point.payload.get("file_path").and_then(Value::as_str) else {
+            continue;
+        };
+        if !seen.insert(path) {
+            continue;
+        }
+        stats.files += 1;
+        stats.total_size += point
+            .payload
+            .get("file_size")
+            .and_then(Value::as_u64)
+            .unwrap_or_default();
+        let filetype = point
+            .payload
+            .get("filetype")
+            .and_then(Value::as_str)
+            .unwrap_or("other");
+        *stats.files_by_type.entry(filetype.to_string()).or_default() += 1;
+    }
+
+    Ok(stats)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::store::EmbeddedStore;
+
+    fn store(name: &str) -> (EmbeddedStore, PathBuf) {
+        let dir = std::env::temp_dir().join(format!("arborist-database-{}", std::process::id()));
+        // The vocabulary reset after a migration goes below the data directory
+        std::env::set_var("ARBORIST_DATA_DIR", &dir);
+        let path = dir.join(format!("{}.json", name));
+        (EmbeddedStore::open(path.clone()).unwrap(), path)
+    }
+
+    fn metadata(dense_model: &str) -> CollectionMetadata {
+        CollectionMetadata {
+            dense_model: dense_model.to_string(),
+            sparse_model: "sparse".to_string(),
+            dimension: 2,
+        }
+    }
+
+    fn points(ids: &[&str]) -> Vec<IndexPoint> {
+        ids.iter()
+            .map(|id| IndexPoint {
+                id: id.to_string(),
+                dense: HashMap::from([(DENSE_VECTOR_NAME.to_string(), vec![1.0, 0.0])]),
+                sparse: HashMap::new(),
+                payload: Payload::from_iter([("file_path".to_string(), format!("/{}", id).into())]),
+            })
+            .collect()
+    }
+
+    async fn ids(store: &EmbeddedStore, collection_name: &str) -> Vec<String> {
+        let points = store
+            .query_filter(collection_name, &Filter::default(), None)
+            .await
+            .unwrap();
+        points.into_iter().map(|point| point.id).collect()
+    }
+
+    #[tokio::test]
+    async fn switching_copies_every_staged_point_over() -> Result<()> {
+        let (store, path) = store("switch");
+        let target = metadata("new");
+        // Interrupted halfway through an earlier switch
+        store
+            .create_collection("files", &collection_schema(&target))
+            .await?;
+        store.upsert("files", points(&["a"])).await?;
+        store
+            .create_collection("files-migrating", &collection_schema(&target))
+            .await?;
+        let staged_ids: Vec<String> = (0..MIGRATE_BATCH_SIZE * 2 + 3)
+            .map(|index| format!("{:04}", index))
+            .collect();
+        let staged: Vec<&str> = staged_ids.iter().map(String::as_str).collect();
+        store.upsert("files-migrating", points(&staged)).await?;
+        store
+            .set_collection_metadata("files-migrating", &target)
+            .await?;
+
+        let moved = switch_to_migrated(&store, "files", "files-migrating", &target).await?;
+        assert_eq!(moved, staged.len());
+        assert_eq!(ids(&store, "files").await, staged_ids);
+        assert_eq!(store.collection_metadata("files").await?, Some(target));
+        assert!(!store.collection_exists("files-migrating").await?);
+
+        drop(store);
+        fs::remove_file(path)?;
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn deleted_files_leave_the_vocabulary() -> Result<()> {
+        let (store, path) = store("vocabulary");
+        store
+            .create_collection("files", &collection_schema(&metadata("dense")))
+            .await?;
+        let mut points = points(&["a", "b"]);
+        for (point, file_path) in points
+            .iter_mut()
+            .zip(["/gone/invoice.txt", "/kept/notes.txt"])
+        {
+            point.payload.insert("file_path".into(), file_path.into());
+            point.payload.insert(
+                lexical::NAME_TERMS.into(),
+                lexical::name_terms(file_path).into(),
+            );
+        }
+        store.upsert("files", points).await?;
+
+        let vocabulary = lexical::Vocabulary::load(&store, "files").await?;
+        assert!(!vocabulary.expand("invoice").is_empty());
+
+        let summary = delete_paths(&store, "files", &[PathBuf::from("/gone/invoice.txt")]).await?;
+        assert_eq!(summary.deleted_files, 1);
+        let vocabulary = lexical::Vocabulary::load(&store, "files").await?;
+        assert!(vocabulary.expand("invoice").is_empty());
+        assert!(!vocabulary.expand("notes").is_empty());
+
+        drop(store);
+        fs::remove_file(path)?;
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn interrupted_switches_stop_other_commands() -> Result<()> {
+        let (store, path) = store("interrupted");
+        let config = Config {
+            collection_name: "files".to_string(),
+            ..Config::default()
+        };
+        check_interrupted_migration(&store, &config).await?;
+
+        // Still embedding, the collection itself is untouched
+        let target = metadata("new");
+        store
+            .create_collection("files-migrating", &collection_schema(&target))
+            .await?;
+        check_interrupted_migration(&store, &config).await?;
+
+        // Complete, the collection may be half replaced
+        store
+            .set_collection_metadata("files-migrating", &target)
+            .await?;
+        let error = check_interrupted_migration(&store, &config)
+            .await
+            .unwrap_err();
+        assert!(
+            error.to_string().contains("arborist migrate"),
+            "{:#}",
+            error
+        );
+
+        drop(store);
+        fs::remove_file(path)?;
+        Ok(())
+    }
 }
@@ -1,6 +1,9 @@
 //use mime_guess::from_path;
+use anyhow::{Context, Result};
 use serde::{Deserialize, Serialize};
 use serde_with::serde_as;
+use std::fs::File;
+use std::io;
 use std::{path::Path, time::SystemTime};
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
@@ -24,6 +27,10 @@ pub struct FileMetadata {
     pub created_at: SystemTime,
     #[serde_as(as = "serde_with::TimestampSeconds<i64>")]
     pub modified_at: SystemTime,
+    /// Hex-encoded BLAKE3 digest of the file contents.
+    ///
+    /// Empty until the file is compared against the index, and when it could not be read.
+    pub content_hash: String,
     pub summary: String,
 }
 
@@ -32,18 +39,59 @@ pub struct FileMetadata {
 pub struct FolderMetadata {
     pub name: String,
     pub path: String,
+    /// Total size of the scanned files below the folder
     pub size: u64,
     #[serde_as(as = "serde_with::TimestampSeconds<i64>")]
     pub created_at: SystemTime,
     #[serde_as(as = "serde_with::TimestampSeconds<i64>")]
     pub modified_at: SystemTime,
-    pub file_count: u32,
+    /// Scanned files below the folder, at any depth
+    pub file_count: usize,
+    /// Files directly in the folder
     pub files: Vec<FileMetadata>,
-    pub folder_count: u32,
+    /// Folders below the folder, at any depth
+    pub folder_count: usize,
     pub summary: String,
 }
 
+impl FileMetadata {
+    /// Metadata of a single file, read outside of a directory walk
+    pub fn from_path(path: &Path) -> Result<Self> {
+        let meta = std::fs::metadata(path)
+            .with_context(|| format!("Failed to read metadata: {}", path.display()))?;
+        let modified_at = meta.modified()?;
+        let path_string = path.to_string_lossy().into_owned();
+
+        Ok(FileMetadata {
+            name: path
+                .file_name()
+                .map(|name| name.to_string_lossy().into_owned())
+                .unwrap_or_default(),
+            filetype: FileType::from_path(&path_string),
+            path: path_string,
+            size: meta.len(),
+            // Not every file system records a creation time
+            created_at: meta.created().unwrap_or(modified_at),
+            modified_at,
+            content_hash: String::new(),
+            summary: String::new(),
+        })
+    }
+}
+
 impl FileType {
+    /// Lowercase name stored in the index payload
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            FileType::Document => "document",
+            FileType::Image => "image",
+            FileType::Audio => "audio",
+            FileType::Video => "video",
+            FileType::Archive => "archive",
+            FileType::Other => "other",
+        }
+    }
+
     pub fn from_path(path: &str) -> FileType {
         let file_extension = match Path::new(path).extension() {
             Some(ext) => ext.to_string_lossy().to_lowercase(),
@@ -69,3 +117,13 @@ impl FileType {
         }
     }
 }
+
+/// Hash the contents of a file, streaming it so large files are not loaded into memory
+pub fn hash_file(path: &Path) -> Result<String> {
+    let mut file =
+        File::open(path).with_context(|| format!("Failed to open file: {}", path.display()))?;
+    let mut hasher = blake3::Hasher::new();
+    io::copy(&mut file, &mut hasher)
+        .with_context(|| format!("Failed to hash file: {}", path.display()))?;
+    Ok(hasher.finalize().to_hex().to_string())
+}
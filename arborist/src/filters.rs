@@ -0,0 +1,240 @@
+//! Metadata filters that narrow a query down by file attributes.
+
+use crate::store::{Condition, Filter};
+use anyhow::{anyhow, bail, Context, Result};
+use chrono::{DateTime, Local, NaiveDate, TimeZone};
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+
+/// File attributes a query can be restricted to
+#[derive(clap::Args, Serialize, Deserialize, Debug, Default, Clone)]
+#[serde(default)]
+pub struct QueryFilters {
+    /// Only files of these types (document, image, audio, video, archive, other)
+    #[arg(long = "type", value_delimiter = ',')]
+    pub types: Vec<String>,
+
+    /// Only files with these extensions
+    #[arg(long = "ext", value_delimiter = ',')]
+    pub extensions: Vec<String>,
+
+    /// Minimum file size, e.g. 500, 10K, 2M or 1G
+    #[arg(long, value_parser = parse_size)]
+    pub min_size: Option<u64>,
+
+    /// Maximum file size, e.g. 500, 10K, 2M or 1G
+    #[arg(long, value_parser = parse_size)]
+    pub max_size: Option<u64>,
+
+    /// Only files modified on or after this date (YYYY-MM-DD or RFC 3339)
+    #[arg(long, value_parser = parse_date)]
+    pub modified_after: Option<i64>,
+
+    /// Only files modified before this date (YYYY-MM-DD or RFC 3339)
+    #[arg(long, value_parser = parse_date)]
+    pub modified_before: Option<i64>,
+
+    /// Only files somewhere below this directory
+    #[arg(long)]
+    pub under: Option<String>,
+}
+
+impl QueryFilters {
+    /// Translate the filters into store conditions on the indexed payload
+    pub fn to_filter(&self) -> Result<Filter> {
+        let mut must = Vec::new();
+
+        if !self.types.is_empty() {
+            let types = self.types.iter().map(|t| t.to_lowercase()).collect();
+            must.push(Condition::any_keyword("filetype", types));
+        }
+        if !self.extensions.is_empty() {
+            let extensions = self
+                .extensions
+                .iter()
+                .map(|ext| ext.trim_start_matches('.').to_lowercase())
+                .collect();
+            must.push(Condition::any_keyword("extension", extensions));
+        }
+        if self.min_size.is_some() || self.max_size.is_some() {
+            must.push(Condition::range(
+                "file_size",
+                self.min_size.map(|size| size as f64),
+                self.max_size.map(|size| size as f64),
+            ));
+        }
+        if self.modified_after.is_some() || self.modified_before.is_some() {
+            // The upper bound is exclusive, timestamps are whole seconds
+            must.push(Condition::range(
+                "modified_at",
+                self.modified_after.map(|time| time as f64),
+                self.modified_before.map(|time| (time - 1) as f64),
+            ));
+        }
+        if let Some(dir) = &self.under {
+            // Paths are indexed canonicalized, so the directory has to be as well
+            let dir = Path::new(dir)
+                .canonicalize()
+                .with_context(|| format!("Invalid directory: {}", dir))?;
+            must.push(Condition::keyword(
+                "parent_dirs",
+                dir.to_string_lossy().into_owned(),
+            ));
+        }
+
+        Ok(Filter::must(must))
+    }
+}
+
+/// Parse a byte count with an optional binary unit suffix
+pub fn parse_size(input: &str) -> Result<u64> {
+    let input = input.trim();
+    let split = input
+        .find(|c: char| !c.is_ascii_digit())
+        .unwrap_or(input.len());
+    let (number, unit) = input.split_at(split);
+
+    let number: u64 = number
+        .parse()
+        .map_err(|_| anyhow!("Invalid size: {}", input))?;
+    let multiplier: u64 = match unit.trim().to_ascii_uppercase().as_str() {
+        "" | "B" => 1,
+        "K" | "KB" | "KIB" => 1 << 10,
+        "M" | "MB" | "MIB" => 1 << 20,
+        "G" | "GB" | "GIB" => 1 << 30,
+        "T" | "TB" | "TIB" => 1 << 40,
+        _ => bail!("Unknown size unit: {}", unit),
+    };
+
+    number
+        .checked_mul(multiplier)
+        .ok_or_else(|| anyhow!("Size too large: {}", input))
+}
+
+/// Parse a date or timestamp into Unix seconds, plain dates are local midnight
+pub fn parse_date(input: &str) -> Result<i64> {
+    if let Ok(time) = DateTime::parse_from_rfc3339(input) {
+        return Ok(time.timestamp());
+    }
+
+    let date = NaiveDate::parse_from_str(input, "%Y-%m-%d")
+        .map_err(|_| anyhow!("Invalid date, expected YYYY-MM-DD: {}", input))?;
+    let midnight = date
+        .and_hms_opt(0, 0, 0)
+        .ok_or_else(|| anyhow!("Invalid date: {}", input))?;
+    Local
+        .from_local_datetime(&midnight)
+        .earliest()
+        .map(|time| time.timestamp())
+        .ok_or_else(|| anyhow!("Date does not exist in the local time zone: {}", input))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::store::Payload;
+    use serde_json::json;
+
+    fn payload(value: serde_json::Value) -> Payload {
+        value.as_object().unwrap().clone()
+    }
+
+    #[test]
+    fn sizes_take_binary_units() {
+        assert_eq!(parse_size("500").unwrap(), 500);
+        assert_eq!(parse_size("10K").unwrap(), 10 << 10);
+        assert_eq!(parse_size(" 2 mb ").unwrap(), 2 << 20);
+        assert_eq!(parse_size("1GiB").unwrap(), 1 << 30);
+        assert_eq!(parse_size("3t").unwrap(), 3 << 40);
+
+        for invalid in ["", "K", "1.5M", "5X", "99999999999T"] {
+            assert!(parse_size(invalid).is_err(), "{}", invalid);
+        }
+    }
+
+    #[test]
+    fn plain_dates_are_local_midnight() {
+        let midnight = Local.with_ymd_and_hms(2024, 3, 1, 0, 0, 0).unwrap();
+        assert_eq!(parse_date("2024-03-01").unwrap(), midnight.timestamp());
+
+        for invalid in ["01/03/2024", "2024-02-30", "yesterday"] {
+            assert!(parse_date(invalid).is_err(), "{}", invalid);
+        }
+    }
+
+    #[test]
+    fn timestamps_keep_their_offset() {
+        assert_eq!(parse_date("2024-03-01T12:00:00Z").unwrap(), 1_709_294_400);
+        assert_eq!(
+            parse_date("2024-03-01T12:00:00+02:00").unwrap(),
+            1_709_294_400 - 2 * 3600
+        );
+    }
+
+    #[test]
+    fn no_filters_match_everything() {
+        assert!(QueryFilters::default().to_filter().unwrap().is_empty());
+    }
+
+    #[test]
+    fn modified_before_is_exclusive() {
+        let filter = QueryFilters {
+            modified_after: Some(1_000),
+            modified_before: Some(2_000),
+            ..QueryFilters::default()
+        }
+        .to_filter()
+        .unwrap();
+
+        let modified_at = |time: i64| payload(json!({ "modified_at": time }));
+        assert!(!filter.matches(&modified_at(999)));
+        assert!(filter.matches(&modified_at(1_000)));
+        assert!(filter.matches(&modified_at(1_999)));
+        assert!(!filter.matches(&modified_at(2_000)));
+    }
+
+    #[test]
+    fn types_and_extensions_are_normalised() {
+        let filter = QueryFilters {
+            types: vec!["Document".to_string()],
+            extensions: vec![".PDF".to_string(), "txt".to_string()],
+            ..QueryFilters::default()
+        }
+        .to_filter()
+        .unwrap();
+
+        assert!(filter.matches(&payload(
+            json!({ "filetype": "document", "extension": "pdf" })
+        )));
+        assert!(filter.matches(&payload(
+            json!({ "filetype": "document", "extension": "txt" })
+        )));
+        assert!(!filter.matches(&payload(json!({ "filetype": "image", "extension": "pdf" }))));
+        assert!(!filter.matches(&payload(
+            json!({ "filetype": "document", "extension": "md" })
+        )));
+    }
+
+    #[test]
+    fn under_is_canonicalised() {
+        let dir = std::env::temp_dir().join(format!("arborist-filters-{}", std::process::id()));
+        std::fs::create_dir_all(dir.join("sub")).unwrap();
+        let canonical = dir.canonicalize().unwrap().to_string_lossy().into_owned();
+
+        let filter = QueryFilters {
+            under: Some(dir.join("sub/..").to_string_lossy().into_owned()),
+            ..QueryFilters::default()
+        }
+        .to_filter()
+        .unwrap();
+        assert!(filter.matches(&payload(json!({ "parent_dirs": ["/", canonical] }))));
+        assert!(!filter.matches(&payload(json!({ "parent_dirs": ["/"] }))));
+
+        let missing = QueryFilters {
+            under: Some(dir.join("missing").to_string_lossy().into_owned()),
+            ..QueryFilters::default()
+        };
+        assert!(missing.to_filter().is_err());
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
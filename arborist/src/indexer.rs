@@ -0,0 +1,377 @@
+//! Indexing and search with loaded models, shared by the commands and the daemon.
+
+use crate::config::{Config, ScanConfig};
+use crate::database::{self, FileVectors, PruneSummary, ScanStats};
+use crate::file_management::FileMetadata;
+use crate::journal::ScanJournal;
+use crate::lexical::{self, VocabularyCache};
+use crate::llm::Summarizer;
+use crate::output::QueryHit;
+use crate::progress::ScanProgress;
+use crate::report::ScanReport;
+use crate::roots::{self, IndexedRoot};
+use crate::store::{Condition, Filter, VectorStore};
+use crate::utils::DirScanConfig;
+use anyhow::{anyhow, bail, Result};
+use fastembed::{SparseTextEmbedding, TextEmbedding, TextRerank};
+use log::debug;
+use std::collections::BTreeSet;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+
+/// The store, models and summarizer files are indexed with
+#[derive(Clone)]
+pub struct Indexer {
+    pub config: Arc<Config>,
+    pub store: Arc<dyn VectorStore>,
+    pub summarizer: Arc<dyn Summarizer>,
+    pub model: Arc<TextEmbedding>,
+    pub sparse_model: Arc<SparseTextEmbedding>,
+    /// Cross-encoder rescoring query results, when reranking is on
+    pub reranker: Option<Arc<TextRerank>>,
+    /// Directories being scanned, two scans of one directory would share a journal
+    active_scans: Arc<Mutex<BTreeSet<PathBuf>>>,
+    /// Terms of the collection, for lexical matching
+    vocabulary: Arc<VocabularyCache>,
+}
+
+impl Indexer {
+    pub fn new(
+        config: Arc<Config>,
+        store: Arc<dyn VectorStore>,
+        summarizer: Arc<dyn Summarizer>,
+        model: Arc<TextEmbedding>,
+        sparse_model: Arc<SparseTextEmbedding>,
+    ) -> Self {
+        Self {
+            config,
+            store,
+            summarizer,
+            model,
+            sparse_model,
+            reranker: None,
+            active_scans: Arc::default(),
+            vocabulary: Arc::default(),
+        }
+    }
+
+    /// Rerank query results with `reranker`, see `QueryConfig::rerank`
+    pub fn with_reranker(mut self, reranker: Option<TextRerank>) -> Self {
+        self.reranker = reranker.map(Arc::new);
+        self
+    }
+
+    /// Directories being scanned right now
+    pub fn active_scans(&self) -> Vec<PathBuf> {
+        self.active_scans.lock().unwrap().iter().cloned().collect()
+    }
+
+    /// Walk `root`, index new and changed files and prune deleted ones.
+    ///
+    /// An interrupted scan is not pruned, its journal lets the next scan resume.
+    pub async fn scan(
+        &self,
+        root: &Path,
+        walk_options: &ScanConfig,
+        progress: &ScanProgress,
+    ) -> Result<ScanReport> {
+        self.begin_scan(root)?;
+        let report = self.scan_unguarded(root, walk_options, progress).await;
+        self.active_scans.lock().unwrap().remove(root);
+        report
+    }
+
+    /// Claim `root` for a scan, failing while a scan of it, above it or below it runs
+    fn begin_scan(&self, root: &Path) -> Result<()> {
+        let mut active_scans = self.active_scans.lock().unwrap();
+        if let Some(active) = active_scans
+            .iter()
+            .find(|active| root.starts_with(active) || active.starts_with(root))
+        {
+            if active == root {
+                bail!("{} is already being scanned", root.display());
+            }
+            bail!(
+                "{} overlaps the scan of {} running now",
+                root.display(),
+                active.display()
+            );
+        }
+        active_scans.insert(root.to_path_buf());
+        Ok(())
+    }
+
+    async fn scan_unguarded(
+        &self,
+        root: &Path,
+        walk_options: &ScanConfig,
+        progress: &ScanProgress,
+    ) -> Result<ScanReport> {
+        let start_time = Instant::now();
+        let tokens_before = self.summarizer.token_usage();
+
+        let scan_config = DirScanConfig::from_config(root.to_path_buf(), walk_options);
+        let scan_result = scan_config.scan_dir_with_progress(&progress.walk).await?;
+
+        let mut journal = ScanJournal::open(&self.config.collection_name, root)?;
+        if journal.is_resuming() {
+            progress.println(format!(
+                "Resuming the interrupted scan of {}",
+                root.display()
+            ));
+        }
+
+        let stats = self
+            .index_files(&scan_result.file_metadata_list, &mut journal, progress)
+            .await?;
+        progress.finish();
+
+        // Drop files that disappeared since the last scan, unless the scan was cut short
+        let pruned = if stats.interrupted {
+            None
+        } else {
+            journal.finish()?;
+            roots::record(
+                &self.config.collection_name,
+                IndexedRoot {
+                    path: root.display().to_string(),
+                    scanned_at: chrono::Utc::now().timestamp(),
+                    files: scan_result.file_metadata_list.len(),
+                },
+            )?;
+            Some(
+                database::prune_index(
+                    self.store.as_ref(),
+                    &self.config.collection_name,
+                    root,
+                    &scan_result,
+                )
+                .await?,
+            )
+        };
+
+        Ok(ScanReport::new(
+            root,
+            &scan_result,
+            &stats,
+            self.summarizer.token_usage().since(tokens_before),
+            pruned,
+            start_time.elapsed(),
+        ))
+    }
+
+    /// Index the files at or below `paths`, walked with the rules of `scan_config`.
+    ///
+    /// Fails while a scan of the directory of `scan_config`, or one above or below it, runs.
+    pub async fn index_paths(
+        &self,
+        scan_config: &DirScanConfig,
+        paths: Vec<PathBuf>,
+    ) -> Result<ScanStats> {
+        // An empty focus would walk the whole tree
+        if paths.is_empty() {
+            return Ok(ScanStats::default());
+        }
+        let scan_result = scan_config.clone().focused_on(paths).scan_dir().await?;
+        if scan_result.file_metadata_list.is_empty() {
+            return Ok(ScanStats::default());
+        }
+
+        let root = scan_config.path();
+        self.begin_scan(root)?;
+        let stats = self
+            .index_changes(root, &scan_result.file_metadata_list)
+            .await;
+        self.active_scans.lock().unwrap().remove(root);
+        stats
+    }
+
+    async fn index_changes(&self, root: &Path, files: &[FileMetadata]) -> Result<ScanStats> {
+        let mut journal = ScanJournal::open_changes(&self.config.collection_name, root)?;
+        let stats = self
+            .index_files(files, &mut journal, &ScanProgress::hidden())
+            .await?;
+        if !stats.interrupted {
+            journal.finish()?;
+        }
+
+        Ok(stats)
+    }
+
+    /// Embed `query` and return the best matching files
+    pub async fn search(&self, query: &str, filter: &Filter) -> Result<Vec<QueryHit>> {
+        // Inference takes a while, keep it off the runtime serving other requests
+        let (model, sparse_model) = (self.model.clone(), self.sparse_model.clone());
+        let text = query.to_string();
+        let (query_vector, sparse_query_vector) =
+            tokio::task::spawn_blocking(move || -> Result<_> {
+                let sparse = database::to_sparse_vector(&sparse_model.embed(vec![&text], None)?[0]);
+                let dense = model.embed(vec![&text], None)?.swap_remove(0);
+                Ok((dense, sparse))
+            })
+            .await??;
+        debug!("Query Vector: {:?}", query_vector);
+        debug!("Sparse Query Vector: {:?}", sparse_query_vector);
+
+        // Over-fetch for the reranker, it picks the best ones among the candidates
+        let top_k = self.config.query.top_k_results;
+        let mut candidates_config = self.config.query.clone();
+        if self.reranker.is_some() {
+            candidates_config.top_k_results = candidates_config.rerank_candidates.max(top_k);
+        }
+        let candidates = candidates_config.top_k_results;
+        let mut matches = database::query_files(
+            self.store.as_ref(),
+            &self.config.collection_name,
+            query_vector,
+            sparse_query_vector,
+            filter,
+            &candidates_config,
+        )
+        .await?;
+
+        if self.config.query.lexical {
+            let vocabulary = self
+                .vocabulary
+                .get(self.store.as_ref(), &self.config.collection_name)
+                .await?;
+            let lexical_matches = lexical::search(
+                self.store.as_ref(),
+                &self.config.collection_name,
+                &vocabulary,
+                query,
+                filter,
+                candidates,
+            )
+            .await?;
+            matches = lexical::blend(
+                matches,
+                lexical_matches,
+                self.config.query.lexical_weight,
+                candidates,
+            );
+        }
+        let hits = matches.into_iter().map(QueryHit::from).collect();
+
+        match &self.reranker {
+            Some(reranker) => rerank(reranker.clone(), query, hits, top_k).await,
+            None => Ok(hits),
+        }
+    }
+
+    /// Files closest to the file at `path`, which does not need to be indexed
+    pub async fn similar(&self, path: &Path, filter: &Filter) -> Result<Vec<QueryHit>> {
+        // Paths are indexed canonicalized, a deleted file can only be found as given
+        let path = path.canonicalize().unwrap_or_else(|_| path.to_path_buf());
+        let file_path = path.to_string_lossy().into_owned();
+
+        let stored = database::file_vectors(
+            self.store.as_ref(),
+            &self.config.collection_name,
+            &file_path,
+        )
+        .await?;
+        let vectors = match stored {
+            Some(vectors) => vectors,
+            None => {
+                if !path.is_file() {
+                    bail!("{} is neither indexed nor a file", file_path);
+                }
+                debug!("{} is not indexed, embedding it", file_path);
+                let points = database::embed_file(
+                    FileMetadata::from_path(&path)?,
+                    &self.config.scan,
+                    self.summarizer.clone(),
+                    self.model.clone(),
+                    self.sparse_model.clone(),
+                )
+                .await?;
+                FileVectors::mean(&points)
+                    .ok_or_else(|| anyhow!("Nothing to compare in {}", file_path))?
+            }
+        };
+
+        // The file itself would be the best match
+        let mut filter = filter.clone();
+        filter
+            .must_not
+            .push(Condition::keyword("file_path", file_path));
+
+        let matches = database::query_files(
+            self.store.as_ref(),
+            &self.config.collection_name,
+            vectors.dense,
+            vectors.sparse,
+            &filter,
+            &self.config.query,
+        )
+        .await?;
+
+        Ok(matches.into_iter().map(QueryHit::from).collect())
+    }
+
+    /// Remove deleted files and folders from the index
+    pub async fn delete_paths(&self, paths: &[PathBuf]) -> Result<PruneSummary> {
+        database::delete_paths(self.store.as_ref(), &self.config.collection_name, paths).await
+    }
+
+    async fn index_files(
+        &self,
+        files: &[FileMetadata],
+        journal: &mut ScanJournal,
+        progress: &ScanProgress,
+    ) -> Result<ScanStats> {
+        database::process_and_upload_files(
+            self.store.clone(),
+            self.summarizer.clone(),
+            &self.config,
+            self.model.clone(),
+            self.sparse_model.clone(),
+            files,
+            journal,
+            progress,
+            false,
+        )
+        .await
+    }
+}
+
+/// Reorder hits by their cross-encoder score against the query and keep the best `limit`
+async fn rerank(
+    reranker: Arc<TextRerank>,
+    query: &str,
+    mut hits: Vec<QueryHit>,
+    limit: usize,
+) -> Result<Vec<QueryHit>> {
+    if hits.is_empty() {
+        return Ok(hits);
+    }
+
+    // The matched passage, or else the summary, stands for the file
+    let documents: Vec<String> = hits
+        .iter()
+        .map(|hit| {
+            let text = match &hit.passage {
+                Some(passage) => Some(passage.text.as_str()),
+                None => hit.summary.as_deref(),
+            };
+            format!("{}\n{}", hit.name, text.unwrap_or_default())
+        })
+        .collect();
+    let query = query.to_string();
+    let results =
+        tokio::task::spawn_blocking(move || reranker.rerank(query, documents, false, None))
+            .await??;
+
+    for result in &results {
+        hits[result.index].rerank_score = Some(result.score);
+    }
+    // Results come sorted by score, best first
+    let mut hits: Vec<Option<QueryHit>> = hits.into_iter().map(Some).collect();
+    Ok(results
+        .iter()
+        .filter_map(|result| hits[result.index].take())
+        .take(limit)
+        .collect())
+}
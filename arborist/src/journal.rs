@@ -0,0 +1,348 @@
+//! Progress journal that lets an interrupted scan resume where it stopped.
+//!
+//! Every file written to the index or given up on is appended to a JSON lines
+//! file in the data directory, keyed by collection and scan root. A scan that
+//! finds a journal skips the files it lists as done and retries the failed ones.
+//! Changes indexed below a root, as `watch` does, have a journal of their own.
+
+use crate::config::data_dir;
+use crate::file_management::FileMetadata;
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::fs::{self, File, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::time::UNIX_EPOCH;
+
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(tag = "status", rename_all = "lowercase")]
+enum JournalEntry {
+    Done {
+        path: String,
+        modified_at: i64,
+        size: u64,
+    },
+    Failed {
+        path: String,
+        reason: String,
+    },
+}
+
+/// Modification time and size a file was indexed at
+type FileVersion = (i64, u64);
+
+fn file_version(file: &FileMetadata) -> FileVersion {
+    let modified_at = file
+        .modified_at
+        .duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_secs() as i64)
+        .unwrap_or_default();
+    (modified_at, file.size)
+}
+
+/// Read-only copy of the journal taken when a scan starts
+#[derive(Debug, Default, Clone)]
+pub struct JournalSnapshot {
+    done: HashMap<String, FileVersion>,
+    failed: HashSet<String>,
+}
+
+impl JournalSnapshot {
+    /// Whether the file was indexed in an earlier run and has not changed since
+    pub fn is_done(&self, file: &FileMetadata) -> bool {
+        self.done.get(&file.path) == Some(&file_version(file))
+    }
+
+    /// Whether the file failed in an earlier run
+    pub fn has_failed(&self, path: &str) -> bool {
+        self.failed.contains(path)
+    }
+}
+
+pub struct ScanJournal {
+    path: PathBuf,
+    file: File,
+    done: HashMap<String, FileVersion>,
+    /// Files that failed in earlier runs
+    previous_failures: HashSet<String>,
+    /// Files that failed in this run, with the reason
+    failures: HashMap<String, String>,
+    resumed: bool,
+}
+
+impl ScanJournal {
+    /// Open the journal of a scan, picking up the entries of an earlier run
+    pub fn open(collection_name: &str, root: &Path) -> Result<Self> {
+        Self::open_keyed(&format!("{}\0{}", collection_name, root.display()))
+    }
+
+    /// Open the journal of the changes indexed below `root`, kept apart from
+    /// the journal of a scan of `root` so neither finishes the other
+    pub fn open_changes(collection_name: &str, root: &Path) -> Result<Self> {
+        Self::open_keyed(&format!("{}\0{}\0changes", collection_name, root.display()))
+    }
+
+    fn open_keyed(key: &str) -> Result<Self> {
+        let key = blake3::hash(key.as_bytes());
+        let path = data_dir()
+            .join("journal")
+            .join(format!("{}.jsonl", &key.to_hex()[..16]));
+
+        let mut contents = if path.exists() {
+            fs::read(&path)
+                .with_context(|| format!("Failed to read journal: {}", path.display()))?
+        } else {
+            Vec::new()
+        };
+        // A line cut short by a crash is dropped, the file is simply redone
+        let complete = contents
+            .iter()
+            .rposition(|&byte| byte == b'\n')
+            .map_or(0, |end| end + 1);
+        let cut_short = complete < contents.len();
+        contents.truncate(complete);
+
+        let mut done = HashMap::new();
+        let mut previous_failures = HashSet::new();
+        for line in contents.split(|&byte| byte == b'\n') {
+            match serde_json::from_slice(line) {
+                Ok(JournalEntry::Done {
+                    path,
+                    modified_at,
+                    size,
+                }) => {
+                    previous_failures.remove(&path);
+                    done.insert(path, (modified_at, size));
+                }
+                Ok(JournalEntry::Failed { path, .. }) => {
+                    done.remove(&path);
+                    previous_failures.insert(path);
+                }
+                Err(_) => continue,
+            }
+        }
+
+        fs::create_dir_all(path.parent().unwrap_or(Path::new(".")))?;
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&path)
+            .with_context(|| format!("Failed to open journal: {}", path.display()))?;
+        // Otherwise the next entry would be glued to the partial line
+        if cut_short {
+            file.set_len(complete as u64)
+                .with_context(|| format!("Failed to repair journal: {}", path.display()))?;
+        }
+
+        Ok(Self {
+            path,
+            file,
+            resumed: !done.is_empty(),
+            done,
+            previous_failures,
+            failures: HashMap::new(),
+        })
+    }
+
+    /// Whether an interrupted run left files behind that are already done
+    pub fn is_resuming(&self) -> bool {
+        self.resumed
+    }
+
+    /// State of earlier runs, for planning which files to skip or retry
+    pub fn snapshot(&self) -> JournalSnapshot {
+        JournalSnapshot {
+            done: self.done.clone(),
+            failed: self.previous_failures.clone(),
+        }
+    }
+
+    fn append(&mut self, entry: &JournalEntry) -> Result<()> {
+        let mut line = serde_json::to_vec(entry)?;
+        line.push(b'\n');
+        self.file
+            .write_all(&line)
+            .with_context(|| format!("Failed to write journal: {}", self.path.display()))
+    }
+
+    /// Record a file whose points have been written to the index
+    pub fn record_done(&mut self, file: &FileMetadata) -> Result<()> {
+        let (modified_at, size) = file_version(file);
+        self.failures.remove(&file.path);
+        self.done.insert(file.path.clone(), (modified_at, size));
+        self.append(&JournalEntry::Done {
+            path: file.path.clone(),
+            modified_at,
+            size,
+        })
+    }
+
+    /// Record a file that could not be indexed
+    pub fn record_failed(&mut self, path: &str, reason: &str) -> Result<()> {
+        self.failures.insert(path.to_string(), reason.to_string());
+        self.append(&JournalEntry::Failed {
+            path: path.to_string(),
+            reason: reason.to_string(),
+        })
+    }
+
+    /// Close the journal of a completed scan.
+    ///
+    /// Only the failures of this run are kept, so the next scan reports them as retried.
+    pub fn finish(self) -> Result<()> {
+        if self.failures.is_empty() {
+            return fs::remove_file(&self.path)
+                .with_context(|| format!("Failed to remove journal: {}", self.path.display()));
+        }
+
+        // Rewrite through a temporary file so a crash never loses the failures
+        let tmp_path = self.path.with_extension("jsonl.tmp");
+        let mut contents = Vec::new();
+        for (path, reason) in &self.failures {
+            serde_json::to_writer(
+                &mut contents,
+                &JournalEntry::Failed {
+                    path: path.clone(),
+                    reason: reason.clone(),
+                },
+            )?;
+            contents.push(b'\n');
+        }
+        fs::write(&tmp_path, contents)
+            .with_context(|| format!("Failed to write journal: {}", tmp_path.display()))?;
+        fs::rename(&tmp_path, &self.path)
+            .with_context(|| format!("Failed to replace journal: {}", self.path.display()))?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::file_management::FileType;
+    use std::sync::Once;
+    use std::time::{Duration, SystemTime};
+
+    /// Journals of the tests go to a temporary data directory
+    fn root(name: &str) -> PathBuf {
+        static DATA_DIR: Once = Once::new();
+        DATA_DIR.call_once(|| {
+            let dir = std::env::temp_dir().join(format!("arborist-journal-{}", std::process::id()));
+            std::env::set_var("ARBORIST_DATA_DIR", dir);
+        });
+        PathBuf::from("/scanned").join(name)
+    }
+
+    fn file(path: &str) -> FileMetadata {
+        let modified_at = SystemTime::UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+        FileMetadata {
+            name: path.rsplit('/').next().unwrap().to_string(),
+            filetype: FileType::from_path(path),
+            path: path.to_string(),
+            size: 42,
+            created_at: modified_at,
+            modified_at,
+            content_hash: String::new(),
+            summary: String::new(),
+        }
+    }
+
+    #[test]
+    fn a_line_cut_short_is_dropped() -> Result<()> {
+        let root = root("cut_short");
+        let mut journal = ScanJournal::open("files", &root)?;
+        journal.record_done(&file("/scanned/a.txt"))?;
+        let path = journal.path.clone();
+        drop(journal);
+
+        let mut contents = fs::read(&path)?;
+        contents.extend_from_slice(br#"{"status":"done","path":"/scanned/b.t"#);
+        fs::write(&path, contents)?;
+
+        let mut journal = ScanJournal::open("files", &root)?;
+        assert!(journal.is_resuming());
+        journal.record_done(&file("/scanned/c.txt"))?;
+        drop(journal);
+
+        // The next entry starts on a line of its own
+        let contents = fs::read_to_string(&path)?;
+        assert_eq!(contents.lines().count(), 2);
+        for line in contents.lines() {
+            serde_json::from_str::<JournalEntry>(line)?;
+        }
+        let snapshot = ScanJournal::open("files", &root)?.snapshot();
+        assert!(snapshot.is_done(&file("/scanned/a.txt")));
+        assert!(snapshot.is_done(&file("/scanned/c.txt")));
+        fs::remove_file(path)?;
+        Ok(())
+    }
+
+    #[test]
+    fn later_entries_override_earlier_ones() -> Result<()> {
+        let root = root("override");
+        let mut journal = ScanJournal::open("files", &root)?;
+        journal.record_done(&file("/scanned/a.txt"))?;
+        journal.record_failed("/scanned/a.txt", "timed out")?;
+        journal.record_failed("/scanned/b.txt", "timed out")?;
+        journal.record_done(&file("/scanned/b.txt"))?;
+        let path = journal.path.clone();
+        drop(journal);
+
+        let snapshot = ScanJournal::open("files", &root)?.snapshot();
+        assert!(!snapshot.is_done(&file("/scanned/a.txt")));
+        assert!(snapshot.has_failed("/scanned/a.txt"));
+        assert!(snapshot.is_done(&file("/scanned/b.txt")));
+        assert!(!snapshot.has_failed("/scanned/b.txt"));
+
+        // A file changed since it was done is indexed again
+        let mut changed = file("/scanned/b.txt");
+        changed.size += 1;
+        assert!(!snapshot.is_done(&changed));
+        fs::remove_file(path)?;
+        Ok(())
+    }
+
+    #[test]
+    fn finishing_keeps_only_the_failures_of_this_run() -> Result<()> {
+        let root = root("finish");
+        let mut journal = ScanJournal::open("files", &root)?;
+        journal.record_failed("/scanned/a.txt", "timed out")?;
+        journal.record_done(&file("/scanned/b.txt"))?;
+        drop(journal);
+
+        let mut journal = ScanJournal::open("files", &root)?;
+        journal.record_done(&file("/scanned/a.txt"))?;
+        journal.record_failed("/scanned/c.txt", "unreadable")?;
+        let path = journal.path.clone();
+        journal.finish()?;
+
+        let journal = ScanJournal::open("files", &root)?;
+        assert!(!journal.is_resuming());
+        let snapshot = journal.snapshot();
+        assert!(!snapshot.has_failed("/scanned/a.txt"));
+        assert!(snapshot.has_failed("/scanned/c.txt"));
+        assert_eq!(fs::read_to_string(&path)?.lines().count(), 1);
+
+        journal.finish()?;
+        assert!(!path.exists());
+        Ok(())
+    }
+
+    #[test]
+    fn changes_have_a_journal_of_their_own() -> Result<()> {
+        let root = root("changes");
+        let mut scan = ScanJournal::open("files", &root)?;
+        scan.record_done(&file("/scanned/a.txt"))?;
+        let changes = ScanJournal::open_changes("files", &root)?;
+        assert_ne!(scan.path, changes.path);
+        assert!(!changes.is_resuming());
+
+        changes.finish()?;
+        drop(scan);
+        let scan = ScanJournal::open("files", &root)?;
+        assert!(scan.is_resuming());
+        scan.finish()?;
+        Ok(())
+    }
+}
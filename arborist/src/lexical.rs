@@ -0,0 +1,832 @@
+//! Lexical matching of file names, paths and identifiers, next to the semantic search.
+//!
+//! Embeddings know little about part numbers, ticket IDs and odd file names.
+//! Every point carries the terms of its path and the identifier-like tokens of
+//! its text as keyword fields, and the terms of the collection are listed in a
+//! vocabulary file in the data directory, built again from the store once
+//! files were removed. Query terms are expanded against the vocabulary by
+//! prefix and with typos, the points holding the expansions are looked up by
+//! keyword and the files found are blended into the semantic results by rank.
+
+use crate::config::data_dir;
+use crate::database::{group_by_file, FileMatch};
+use crate::store::{Condition, Filter, Payload, ScoredPoint, VectorStore};
+use anyhow::{Context, Result};
+use log::warn;
+use serde_json::Value;
+use std::collections::{BTreeSet, HashMap, HashSet};
+use std::fs::{self, File, OpenOptions};
+use std::io::Write;
+use std::ops::Bound;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::SystemTime;
+
+/// Payload field with the terms of the file name and of its directories
+pub const NAME_TERMS: &str = "name_terms";
+
+/// Payload field with the identifier-like terms of the indexed text
+pub const TEXT_TERMS: &str = "text_terms";
+
+/// Most text terms stored per point
+const MAX_TEXT_TERMS: usize = 256;
+
+/// Most vocabulary terms a query term is expanded to, per kind of match
+const MAX_EXPANSIONS: usize = 16;
+
+/// Shortest query term matched by prefix
+const MIN_PREFIX_LEN: usize = 3;
+
+/// Most points fetched per field for a query
+const LOOKUP_LIMIT: usize = 256;
+
+/// Rank offset of reciprocal rank fusion, as used by Qdrant
+const RRF_K: f32 = 60.0;
+
+// Weight of a query term found exactly, as the prefix of a term or with typos
+const EXACT_WEIGHT: f32 = 1.0;
+const PREFIX_WEIGHT: f32 = 0.7;
+const FUZZY_WEIGHT: f32 = 0.5;
+
+// Weight of a term found in the file name, in a directory name or in the text
+const NAME_WEIGHT: f32 = 1.0;
+const PATH_WEIGHT: f32 = 0.6;
+const TEXT_WEIGHT: f32 = 0.4;
+
+/// Query words too common to say anything about a file name
+const STOP_WORDS: [&str; 20] = [
+    "an", "and", "are", "by", "for", "from", "how", "in", "is", "my", "of", "on", "or", "the",
+    "to", "was", "what", "where", "which", "with",
+];
+
+fn is_joiner(c: char) -> bool {
+    matches!(c, '-' | '_' | '.')
+}
+
+/// Lowercased terms of a piece of text, in order of first appearance.
+///
+/// Words are runs of letters and digits of at least two characters. Words
+/// joined by `-`, `_` or `.` are also kept whole, so `INV-2023-0042` gives
+/// `inv-2023-0042`, `inv`, `2023` and `0042`.
+pub fn tokenize(text: &str) -> Vec<String> {
+    let mut terms = Vec::new();
+    let mut seen = HashSet::new();
+    let mut push = |term: String, terms: &mut Vec<String>| {
+        if seen.insert(term.clone()) {
+            terms.push(term);
+        }
+    };
+
+    for piece in text.split(|c: char| !c.is_alphanumeric() && !is_joiner(c)) {
+        let piece = piece.trim_matches(is_joiner).to_lowercase();
+        let words: Vec<&str> = piece
+            .split(|c: char| !c.is_alphanumeric())
+            .filter(|word| !word.is_empty())
+            .collect();
+        if words.len() > 1 {
+            push(piece.clone(), &mut terms);
+        }
+        for word in words {
+            if word.chars().count() >= 2 {
+                push(word.to_string(), &mut terms);
+            }
+        }
+    }
+
+    terms
+}
+
+/// Terms of the file name, with its stem, followed by those of its directories
+pub fn name_terms(path: &str) -> Vec<String> {
+    let path = Path::new(path);
+    let mut terms = file_name_terms(path);
+    for dir in path.parent().into_iter().flat_map(Path::components) {
+        for term in tokenize(&dir.as_os_str().to_string_lossy()) {
+            if !terms.contains(&term) {
+                terms.push(term);
+            }
+        }
+    }
+    terms
+}
+
+fn file_name_terms(path: &Path) -> Vec<String> {
+    let mut terms = tokenize(&path.file_name().unwrap_or_default().to_string_lossy());
+    if let Some(stem) = path.file_stem() {
+        for term in tokenize(&stem.to_string_lossy()) {
+            if !terms.contains(&term) {
+                terms.push(term);
+            }
+        }
+    }
+    terms
+}
+
+/// Terms of a text that look like identifiers: those with a digit or an underscore.
+///
+/// Plain words are left to the sparse vectors, which already match them.
+pub fn text_terms(text: &str) -> Vec<String> {
+    tokenize(text)
+        .into_iter()
+        .filter(|term| {
+            term.contains('_') || (term.len() >= 3 && term.chars().any(|c| c.is_ascii_digit()))
+        })
+        .take(MAX_TEXT_TERMS)
+        .collect()
+}
+
+/// Terms of a query, without the stop words
+pub fn query_terms(query: &str) -> Vec<String> {
+    let terms = tokenize(query);
+    let kept: Vec<String> = terms
+        .iter()
+        .filter(|term| !STOP_WORDS.contains(&term.as_str()))
+        .cloned()
+        .collect();
+    // A query of stop words only is still looked for
+    if kept.is_empty() {
+        terms
+    } else {
+        kept
+    }
+}
+
+/// Vocabulary file of a collection.
+///
+/// Collections of the same name on another server or in another index file
+/// hold other files, so the file is also named after where the store is.
+fn vocabulary_path(store: &dyn VectorStore, collection_name: &str) -> PathBuf {
+    let location = blake3::hash(store.location().as_bytes()).to_hex();
+    data_dir()
+        .join("lexical")
+        .join(format!("{}-{}.terms", collection_name, &location[..16]))
+}
+
+/// Hold the lock of a vocabulary file, every change to the file is made under it
+fn lock_vocabulary(path: &Path) -> Result<File> {
+    fs::create_dir_all(path.parent().unwrap_or(Path::new(".")))?;
+    let lock_path = path.with_extension("lock");
+    let lock = OpenOptions::new()
+        .create(true)
+        .truncate(false)
+        .write(true)
+        .open(&lock_path)
+        .with_context(|| format!("Failed to open vocabulary lock: {}", lock_path.display()))?;
+    lock.lock()
+        .with_context(|| format!("Failed to lock vocabulary: {}", path.display()))?;
+    Ok(lock)
+}
+
+/// Take the lock of a vocabulary file off the async runtime
+async fn lock_vocabulary_async(path: &Path) -> Result<File> {
+    let path = path.to_path_buf();
+    tokio::task::spawn_blocking(move || lock_vocabulary(&path)).await?
+}
+
+fn payload_terms(payload: &Payload) -> impl Iterator<Item = &str> {
+    [NAME_TERMS, TEXT_TERMS]
+        .into_iter()
+        .filter_map(|field| payload.get(field).and_then(Value::as_array))
+        .flatten()
+        .filter_map(Value::as_str)
+}
+
+/// Terms of the payloads written to a collection, for `record`
+pub fn terms_of<'a>(payloads: impl IntoIterator<Item = &'a Payload>) -> BTreeSet<String> {
+    payloads
+        .into_iter()
+        .flat_map(payload_terms)
+        .map(str::to_string)
+        .collect()
+}
+
+/// Add the terms of points written to the collection to its vocabulary.
+///
+/// Terms are appended, one per line. Without a vocabulary file nothing is
+/// written, the next query builds the whole vocabulary from the store, so
+/// terms have to be recorded after their points were written. The points are
+/// indexed either way, so a failure is only logged.
+pub async fn record(store: &dyn VectorStore, collection_name: &str, terms: BTreeSet<String>) {
+    let path = vocabulary_path(store, collection_name);
+    if terms.is_empty() || !path.exists() {
+        return;
+    }
+
+    let mut lines = String::new();
+    for term in terms {
+        lines.push_str(&term);
+        lines.push('\n');
+    }
+    let result = tokio::task::spawn_blocking(move || append_terms(&path, &lines)).await;
+    match result {
+        Ok(Ok(())) => {}
+        Ok(Err(e)) => warn!("{:#}", e),
+        Err(e) => warn!("Failed to update vocabulary: {}", e),
+    }
+}
+
+fn append_terms(path: &Path, lines: &str) -> Result<()> {
+    let _lock = lock_vocabulary(path)?;
+    // Removed while waiting for the lock
+    if !path.exists() {
+        return Ok(());
+    }
+    OpenOptions::new()
+        .append(true)
+        .open(path)
+        .and_then(|mut file| file.write_all(lines.as_bytes()))
+        .with_context(|| format!("Failed to update vocabulary: {}", path.display()))
+}
+
+/// Forget the vocabulary of a collection, the next query builds it again from the store
+pub async fn reset(store: &dyn VectorStore, collection_name: &str) -> Result<()> {
+    let path = vocabulary_path(store, collection_name);
+    tokio::task::spawn_blocking(move || {
+        let _lock = lock_vocabulary(&path)?;
+        if path.exists() {
+            fs::remove_file(&path)
+                .with_context(|| format!("Failed to remove vocabulary: {}", path.display()))?;
+        }
+        Ok(())
+    })
+    .await?
+}
+
+/// Every term stored in a collection, for expanding query terms
+pub struct Vocabulary {
+    terms: BTreeSet<String>,
+    /// The terms by their number of characters, typos only change it by a few
+    by_length: HashMap<usize, Vec<String>>,
+}
+
+impl Vocabulary {
+    fn new(terms: BTreeSet<String>) -> Self {
+        let mut by_length: HashMap<usize, Vec<String>> = HashMap::new();
+        for term in &terms {
+            by_length
+                .entry(term.chars().count())
+                .or_default()
+                .push(term.clone());
+        }
+        Vocabulary { terms, by_length }
+    }
+
+    /// Read the vocabulary of a collection, building it from the store when missing
+    pub async fn load(store: &dyn VectorStore, collection_name: &str) -> Result<Self> {
+        let path = vocabulary_path(store, collection_name);
+        if !path.exists() {
+            return Self::rebuild(store, collection_name, &path).await;
+        }
+
+        let contents = tokio::fs::read_to_string(&path)
+            .await
+            .with_context(|| format!("Failed to read vocabulary: {}", path.display()))?;
+        let line_count = contents.lines().count();
+        let terms: BTreeSet<String> = contents.lines().map(str::to_string).collect();
+
+        // Files indexed again append their terms again, drop the repeats now and then
+        if line_count > 2 * terms.len() {
+            let terms = tokio::task::spawn_blocking(move || compact(&path)).await??;
+            return Ok(Vocabulary::new(terms));
+        }
+        Ok(Vocabulary::new(terms))
+    }
+
+    async fn rebuild(store: &dyn VectorStore, collection_name: &str, path: &Path) -> Result<Self> {
+        // Points written meanwhile record their terms once the file is there
+        let _lock = lock_vocabulary_async(path).await?;
+        if path.exists() {
+            let contents = tokio::fs::read_to_string(path)
+                .await
+                .with_context(|| format!("Failed to read vocabulary: {}", path.display()))?;
+            let terms = contents.lines().map(str::to_string).collect();
+            return Ok(Vocabulary::new(terms));
+        }
+
+        let points = store
+            .query_filter(collection_name, &Filter::default(), None)
+            .await
+            .context("Failed to scroll indexed points")?;
+        let terms = terms_of(points.iter().map(|point| &point.payload));
+
+        let path = path.to_path_buf();
+        tokio::task::spawn_blocking(move || {
+            write_terms(&path, &terms)?;
+            Ok(Vocabulary::new(terms))
+        })
+        .await?
+    }
+
+    /// Terms matching a query term exactly, by prefix or within a few typos, with their weight
+    pub fn expand(&self, term: &str) -> Vec<(String, f32)> {
+        let mut expansions = Vec::new();
+        if self.terms.contains(term) {
+            expansions.push((term.to_string(), EXACT_WEIGHT));
+        }
+
+        // A number off by a digit is another number, not a typo, and a
+        // number continued by digits is another number, not a prefix
+        let numeric = term.chars().all(|c| c.is_ascii_digit());
+        let length = term.chars().count();
+        if length >= MIN_PREFIX_LEN {
+            expansions.extend(
+                self.terms
+                    .range::<str, _>((Bound::Excluded(term), Bound::Unbounded))
+                    .take_while(|candidate| candidate.starts_with(term))
+                    .filter(|candidate| {
+                        !numeric
+                            || !candidate[term.len()..].starts_with(|c: char| c.is_ascii_digit())
+                    })
+                    .take(MAX_EXPANSIONS)
+                    .map(|candidate| (candidate.clone(), PREFIX_WEIGHT)),
+            );
+        }
+
+        let max_edits = match length {
+            _ if numeric => 0,
+            0..=3 => 0,
+            4..=7 => 1,
+            _ => 2,
+        };
+        if max_edits > 0 {
+            // Only terms of about the same length can be within reach
+            let mut fuzzy: Vec<(usize, &String)> = (length.saturating_sub(max_edits)
+                ..=length + max_edits)
+                .filter_map(|length| self.by_length.get(&length))
+                .flatten()
+                .filter(|candidate| !candidate.starts_with(term))
+                .filter_map(|candidate| {
+                    edit_distance(term, candidate, max_edits).map(|distance| (distance, candidate))
+                })
+                .collect();
+            fuzzy.sort();
+            expansions.extend(
+                fuzzy
+                    .into_iter()
+                    .take(MAX_EXPANSIONS)
+                    .map(|(_, candidate)| (candidate.clone(), FUZZY_WEIGHT)),
+            );
+        }
+
+        expansions
+    }
+}
+
+/// Size and modification time of a vocabulary file, to tell when it changed
+type FileStamp = (u64, SystemTime);
+
+async fn file_stamp(path: &Path) -> Option<FileStamp> {
+    let metadata = tokio::fs::metadata(path).await.ok()?;
+    Some((metadata.len(), metadata.modified().ok()?))
+}
+
+/// The vocabulary last loaded, read again once its file changed
+#[derive(Default)]
+pub struct VocabularyCache {
+    cached: tokio::sync::Mutex<Option<(PathBuf, FileStamp, Arc<Vocabulary>)>>,
+}
+
+impl VocabularyCache {
+    /// The vocabulary of a collection, read from its file only when that changed
+    pub async fn get(
+        &self,
+        store: &dyn VectorStore,
+        collection_name: &str,
+    ) -> Result<Arc<Vocabulary>> {
+        let path = vocabulary_path(store, collection_name);
+        let mut cached = self.cached.lock().await;
+        // Taken before reading, a change made meanwhile gets it read again next time
+        let stamp = file_stamp(&path).await;
+        if let (Some((cached_path, cached_stamp, vocabulary)), Some(stamp)) = (&*cached, stamp) {
+            if *cached_path == path && *cached_stamp == stamp {
+                return Ok(vocabulary.clone());
+            }
+        }
+
+        let vocabulary = Arc::new(Vocabulary::load(store, collection_name).await?);
+        *cached = stamp.map(|stamp| (path, stamp, vocabulary.clone()));
+        Ok(vocabulary)
+    }
+}
+
+/// Rewrite a vocabulary file without its repeated terms
+fn compact(path: &Path) -> Result<BTreeSet<String>> {
+    let _lock = lock_vocabulary(path)?;
+    let contents = fs::read_to_string(path)
+        .with_context(|| format!("Failed to read vocabulary: {}", path.display()))?;
+    let terms = contents.lines().map(str::to_string).collect();
+    write_terms(path, &terms)?;
+    Ok(terms)
+}
+
+/// Replace a vocabulary file, with its lock held
+fn write_terms(path: &Path, terms: &BTreeSet<String>) -> Result<()> {
+    let mut contents = String::new();
+    for term in terms {
+        contents.push_str(term);
+        contents.push('\n');
+    }
+    // Readers see either the old or the new file, never a partial one
+    let tmp_path = path.with_extension("terms.tmp");
+    fs::write(&tmp_path, contents)
+        .with_context(|| format!("Failed to write vocabulary: {}", tmp_path.display()))?;
+    fs::rename(&tmp_path, path)
+        .with_context(|| format!("Failed to replace vocabulary: {}", path.display()))
+}
+
+/// Levenshtein distance between two terms, `None` when above `max`
+fn edit_distance(a: &str, b: &str, max: usize) -> Option<usize> {
+    let a: Vec<char> = a.chars().collect();
+    let b: Vec<char> = b.chars().collect();
+    if a.len().abs_diff(b.len()) > max {
+        return None;
+    }
+
+    let mut previous: Vec<usize> = (0..=b.len()).collect();
+    let mut current = vec![0; b.len() + 1];
+    for (i, ca) in a.iter().enumerate() {
+        current[0] = i + 1;
+        for (j, cb) in b.iter().enumerate() {
+            let substitution = previous[j] + usize::from(ca != cb);
+            current[j + 1] = substitution.min(previous[j + 1] + 1).min(current[j] + 1);
+        }
+        // Every later row is at least the smallest of this one
+        if current.iter().min().is_some_and(|&min| min > max) {
+            return None;
+        }
+        std::mem::swap(&mut previous, &mut current);
+    }
+
+    Some(previous[b.len()]).filter(|&distance| distance <= max)
+}
+
+/// Vocabulary terms each query term matches, with the weight of its best match
+fn expand_query(vocabulary: &Vocabulary, query: &str) -> Vec<HashMap<String, f32>> {
+    query_terms(query)
+        .iter()
+        .map(|term| {
+            let mut weights: HashMap<String, f32> = HashMap::new();
+            for (candidate, weight) in vocabulary.expand(term) {
+                let best = weights.entry(candidate).or_default();
+                *best = best.max(weight);
+            }
+            weights
+        })
+        .collect()
+}
+
+/// Files whose name, path or identifiers match the terms of `query`, best first.
+///
+/// A file has to match at least half of the query terms. Its lexical score
+/// is the mean over the query terms of the best weighted match, up to 1 for a
+/// query found whole in the file name.
+pub async fn search(
+    store: &dyn VectorStore,
+    collection_name: &str,
+    vocabulary: &Vocabulary,
+    query: &str,
+    filter: &Filter,
+    limit: usize,
+) -> Result<Vec<FileMatch>> {
+    let expansions = expand_query(vocabulary, query);
+    let mut lookup: Vec<String> = expansions
+        .iter()
+        .flat_map(|weights| weights.keys().cloned())
+        .collect();
+    if lookup.is_empty() {
+        return Ok(Vec::new());
+    }
+    lookup.sort_unstable();
+    lookup.dedup();
+
+    let mut points = HashMap::new();
+    for field in [NAME_TERMS, TEXT_TERMS] {
+        let mut field_filter = filter.clone();
+        field_filter
+            .must
+            .push(Condition::any_keyword(field, lookup.clone()));
+        let found = store
+            .query_filter(collection_name, &field_filter, Some(LOOKUP_LIMIT))
+            .await
+            .context("Failed to look up matching terms")?;
+        for point in found {
+            points.entry(point.id.clone()).or_insert(point);
+        }
+    }
+
+    let mut scored: Vec<ScoredPoint> = points
+        .into_values()
+        .filter_map(|point| {
+            let score = score_point(&point.payload, &expansions)?;
+            Some(ScoredPoint {
+                id: point.id,
+                score,
+                payload: point.payload,
+            })
+        })
+        .collect();
+    scored.sort_by(|a, b| b.score.total_cmp(&a.score));
+
+    // Found by name, not by meaning
+    let mut matches = group_by_file(scored, limit);
+    for file_match in &mut matches {
+        file_match.lexical_score = Some(file_match.score);
+        file_match.score = 0.0;
+    }
+    Ok(matches)
+}
+
+/// Score of a point against the expanded query terms, `None` when it matches too few
+fn score_point(payload: &Payload, expansions: &[HashMap<String, f32>]) -> Option<f32> {
+    let field_terms = |field: &str| -> HashSet<&str> {
+        payload
+            .get(field)
+            .and_then(Value::as_array)
+            .map(|terms| terms.iter().filter_map(Value::as_str).collect())
+            .unwrap_or_default()
+    };
+    let file_name = payload
+        .get("file_path")
+        .and_then(Value::as_str)
+        .map(|path| file_name_terms(Path::new(path)))
+        .unwrap_or_default();
+    let path_terms = field_terms(NAME_TERMS);
+    let text_terms = field_terms(TEXT_TERMS);
+
+    let mut total = 0.0;
+    let mut matched = 0;
+    for weights in expansions {
+        let best = weights
+            .iter()
+            .map(|(term, weight)| {
+                let field_weight = if file_name.contains(term) {
+                    NAME_WEIGHT
+                } else if path_terms.contains(term.as_str()) {
+                    PATH_WEIGHT
+                } else if text_terms.contains(term.as_str()) {
+                    TEXT_WEIGHT
+                } else {
+                    0.0
+                };
+                weight * field_weight
+            })
+            .fold(0.0, f32::max);
+        if best > 0.0 {
+            matched += 1;
+            total += best;
+        }
+    }
+
+    (matched * 2 >= expansions.len()).then(|| total / expansions.len() as f32)
+}
+
+/// Merge lexical matches into semantic ones by reciprocal rank.
+///
+/// Each list adds `1 / (60 + rank)` to the blended score of the files in it,
+/// the lexical list scaled by `weight` and by how well the file matched. The
+/// matches keep their semantic `score` and carry their `lexical_score` and
+/// `blended_score` next to it. The semantic matches are returned as they are
+/// when nothing matched lexically.
+pub fn blend(
+    semantic: Vec<FileMatch>,
+    lexical: Vec<FileMatch>,
+    weight: f32,
+    limit: usize,
+) -> Vec<FileMatch> {
+    if lexical.is_empty() {
+        return semantic;
+    }
+
+    let file_path = |file_match: &FileMatch| {
+        file_match
+            .payload
+            .get("file_path")
+            .and_then(Value::as_str)
+            .unwrap_or_default()
+            .to_string()
+    };
+    let mut blended: Vec<FileMatch> = Vec::new();
+    let mut positions: HashMap<String, usize> = HashMap::new();
+
+    for (rank, mut file_match) in semantic.into_iter().enumerate() {
+        file_match.blended_score = Some(1.0 / (RRF_K + rank as f32 + 1.0));
+        positions.insert(file_path(&file_match), blended.len());
+        blended.push(file_match);
+    }
+    for (rank, mut file_match) in lexical.into_iter().enumerate() {
+        let lexical_score = file_match.lexical_score.unwrap_or(0.0);
+        let rank_score = weight * lexical_score / (RRF_K + rank as f32 + 1.0);
+        let path = file_path(&file_match);
+        match positions.get(&path) {
+            // The semantic match keeps its score and passage
+            Some(&position) => {
+                let existing = &mut blended[position];
+                existing.lexical_score = Some(lexical_score);
+                existing.blended_score = Some(existing.blended_score.unwrap_or(0.0) + rank_score);
+            }
+            None => {
+                file_match.blended_score = Some(rank_score);
+                positions.insert(path, blended.len());
+                blended.push(file_match);
+            }
+        }
+    }
+
+    let blended_score = |file_match: &FileMatch| file_match.blended_score.unwrap_or(0.0);
+    blended.sort_by(|a, b| blended_score(b).total_cmp(&blended_score(a)));
+    blended.truncate(limit);
+    blended
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn payload(path: &str, text: &str) -> Payload {
+        let mut payload = Payload::new();
+        payload.insert("file_path".into(), Value::from(path));
+        payload.insert(NAME_TERMS.into(), Value::from(name_terms(path)));
+        payload.insert(TEXT_TERMS.into(), Value::from(text_terms(text)));
+        payload
+    }
+
+    fn vocabulary(payloads: &[Payload]) -> Vocabulary {
+        Vocabulary::new(terms_of(payloads))
+    }
+
+    fn score(payload: &Payload, vocabulary: &Vocabulary, query: &str) -> Option<f32> {
+        score_point(payload, &expand_query(vocabulary, query))
+    }
+
+    fn file_match(path: &str, score: f32, lexical_score: Option<f32>) -> FileMatch {
+        FileMatch {
+            score,
+            payload: payload(path, ""),
+            passage: None,
+            lexical_score,
+            blended_score: None,
+        }
+    }
+
+    fn paths(matches: &[FileMatch]) -> Vec<&str> {
+        matches
+            .iter()
+            .map(|file_match| file_match.payload["file_path"].as_str().unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn tokenize_keeps_joined_words_whole() {
+        assert_eq!(
+            tokenize("INV-2023-0042"),
+            ["inv-2023-0042", "inv", "2023", "0042"]
+        );
+        assert_eq!(
+            tokenize("Report_final (v2).PDF"),
+            ["report_final", "report", "final", "v2", "pdf"]
+        );
+        // Single characters and repeats are left out, joiners at the ends dropped
+        assert_eq!(tokenize("a -draft- Draft x"), ["draft"]);
+    }
+
+    #[test]
+    fn name_terms_put_the_file_name_first() {
+        assert_eq!(
+            name_terms("/home/user/invoices/INV-2023-0042.pdf"),
+            [
+                "inv-2023-0042.pdf",
+                "inv",
+                "2023",
+                "0042",
+                "pdf",
+                "inv-2023-0042",
+                "home",
+                "user",
+                "invoices",
+            ]
+        );
+    }
+
+    #[test]
+    fn text_terms_only_keep_identifiers() {
+        assert_eq!(
+            text_terms("Invoice INV-2023-0042 for order_id 17, paid in 2023"),
+            ["inv-2023-0042", "2023", "0042", "order_id"]
+        );
+        assert!(text_terms("Nothing but plain words here").is_empty());
+    }
+
+    #[test]
+    fn edit_distance_stops_above_max() {
+        assert_eq!(edit_distance("invoice", "invoice", 2), Some(0));
+        assert_eq!(edit_distance("invoce", "invoice", 1), Some(1));
+        assert_eq!(edit_distance("kitten", "sitting", 3), Some(3));
+        assert_eq!(edit_distance("kitten", "sitting", 2), None);
+        // Too far apart in length to be compared at all
+        assert_eq!(edit_distance("inv", "invoices", 2), None);
+    }
+
+    #[test]
+    fn identifiers_match_their_file() {
+        let invoice = payload("/home/user/invoices/INV-2023-0042.pdf", "");
+        let other = payload("/home/user/invoices/INV-2024-0007.pdf", "");
+        let vocabulary = vocabulary(&[invoice.clone(), other.clone()]);
+
+        // Found whole in the file name
+        assert_eq!(score(&invoice, &vocabulary, "INV-2023-0042"), Some(1.0));
+        assert_eq!(score(&other, &vocabulary, "INV-2023-0042"), None);
+    }
+
+    #[test]
+    fn typos_and_prefixes_match() {
+        let invoice = payload("/home/user/taxes/invoice_2023.pdf", "");
+        let vocabulary = vocabulary(std::slice::from_ref(&invoice));
+
+        let expanded = vocabulary.expand("invoce");
+        assert!(expanded.contains(&("invoice".to_string(), FUZZY_WEIGHT)));
+        let expanded = vocabulary.expand("invo");
+        assert!(expanded.contains(&("invoice".to_string(), PREFIX_WEIGHT)));
+
+        let typo = score(&invoice, &vocabulary, "invoce 2023").unwrap();
+        let exact = score(&invoice, &vocabulary, "invoice 2023").unwrap();
+        assert!(0.0 < typo && typo < exact, "{} {}", typo, exact);
+    }
+
+    #[test]
+    fn numbers_only_match_exactly() {
+        let invoice = payload("/home/user/taxes/2023.pdf", "Order 20231 of 2024");
+        let vocabulary = vocabulary(std::slice::from_ref(&invoice));
+
+        assert_eq!(
+            vocabulary.expand("2023"),
+            [
+                ("2023".to_string(), EXACT_WEIGHT),
+                ("2023.pdf".to_string(), PREFIX_WEIGHT)
+            ]
+        );
+        // Neither a typo of 2023 nor 2024
+        assert!(vocabulary.expand("2025").is_empty());
+        assert_eq!(score(&invoice, &vocabulary, "2025"), None);
+        // Found in the text only
+        assert_eq!(
+            score(&invoice, &vocabulary, "2024"),
+            Some(EXACT_WEIGHT * TEXT_WEIGHT)
+        );
+    }
+
+    #[test]
+    fn half_of_the_query_terms_have_to_match() {
+        let notes = payload("/home/user/notes/garden.txt", "");
+        let vocabulary = vocabulary(std::slice::from_ref(&notes));
+
+        assert!(score(&notes, &vocabulary, "garden notes").is_some());
+        assert!(score(&notes, &vocabulary, "garden plans").is_some());
+        assert_eq!(score(&notes, &vocabulary, "garden plans budget"), None);
+    }
+
+    #[test]
+    fn blend_orders_by_rank_and_keeps_the_semantic_score() {
+        let semantic = vec![
+            file_match("/a.txt", 0.9, None),
+            file_match("/b.txt", 0.8, None),
+            file_match("/c.txt", 0.7, None),
+        ];
+        let lexical = vec![
+            file_match("/c.txt", 0.0, Some(1.0)),
+            file_match("/d.txt", 0.0, Some(0.4)),
+        ];
+
+        let blended = blend(semantic, lexical, 2.0, 10);
+        assert_eq!(paths(&blended), ["/c.txt", "/a.txt", "/b.txt", "/d.txt"]);
+        assert_eq!(blended[0].score, 0.7);
+        assert_eq!(blended[0].lexical_score, Some(1.0));
+        assert_eq!(blended[1].lexical_score, None);
+        assert_eq!(blended[3].score, 0.0);
+        assert_eq!(blended[3].lexical_score, Some(0.4));
+        assert!(blended
+            .windows(2)
+            .all(|pair| pair[0].blended_score >= pair[1].blended_score));
+
+        let blended = blend(
+            vec![file_match("/a.txt", 0.9, None)],
+            vec![file_match("/d.txt", 0.0, Some(1.0))],
+            2.0,
+            1,
+        );
+        assert_eq!(paths(&blended), ["/d.txt"]);
+    }
+
+    #[test]
+    fn blend_without_lexical_matches_changes_nothing() {
+        let semantic = vec![
+            file_match("/a.txt", 0.9, None),
+            file_match("/b.txt", 0.8, None),
+        ];
+
+        let blended = blend(semantic, Vec::new(), 2.0, 10);
+        assert_eq!(paths(&blended), ["/a.txt", "/b.txt"]);
+        assert!(blended
+            .iter()
+            .all(|file_match| file_match.blended_score.is_none()));
+    }
+}
@@ -1,5 +1,20 @@
+pub mod api;
+pub mod ask;
 pub mod config;
+pub mod daemon;
 pub mod database;
 pub mod file_management;
+pub mod filters;
+pub mod indexer;
+pub mod journal;
+pub mod lexical;
+pub mod llm;
+pub mod mcp;
+pub mod output;
+pub mod progress;
+pub mod report;
+pub mod roots;
+pub mod store;
 pub mod summary;
 pub mod utils;
+pub mod watch;
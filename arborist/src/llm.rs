@@ -0,0 +1,357 @@
+//! Language model backends used to summarize files.
+//!
+//! Everything that talks to an LLM goes through [`Summarizer`], so the same
+//! summarization code runs against Ollama, any OpenAI-compatible server, or
+//! the offline [`StubSummarizer`].
+
+use crate::config::{LlmBackend, ScanConfig};
+use anyhow::{anyhow, Context, Result};
+use async_trait::async_trait;
+use base64::Engine;
+use log::warn;
+use ollama_rs::{
+    generation::{
+        completion::{request::GenerationRequest, GenerationResponse},
+        images::Image,
+    },
+    Ollama,
+};
+use reqwest::header::{HeaderMap, HeaderValue, AUTHORIZATION};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+#[async_trait]
+pub trait Summarizer: Send + Sync {
+    /// Generate a completion for `prompt` under the given system prompt
+    async fn generate(&self, system: &str, prompt: &str) -> Result<String>;
+
+    /// Describe an image with the vision model
+    async fn describe_image(&self, prompt: &str, image: &[u8], mime_type: &str) -> Result<String>;
+
+    /// Tokens used by the requests so far, zero for backends that do not report it
+    fn token_usage(&self) -> TokenUsage {
+        TokenUsage::default()
+    }
+}
+
+/// Tokens consumed by LLM requests
+#[derive(Serialize, Deserialize, Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct TokenUsage {
+    pub prompt_tokens: u64,
+    pub completion_tokens: u64,
+}
+
+impl TokenUsage {
+    /// Tokens used since an earlier reading of the same summarizer
+    pub fn since(self, earlier: TokenUsage) -> TokenUsage {
+        TokenUsage {
+            prompt_tokens: self.prompt_tokens.saturating_sub(earlier.prompt_tokens),
+            completion_tokens: self
+                .completion_tokens
+                .saturating_sub(earlier.completion_tokens),
+        }
+    }
+}
+
+/// Running token count shared by concurrent requests
+#[derive(Debug, Default)]
+struct UsageCounter {
+    prompt_tokens: AtomicU64,
+    completion_tokens: AtomicU64,
+}
+
+impl UsageCounter {
+    fn add(&self, prompt_tokens: u64, completion_tokens: u64) {
+        self.prompt_tokens
+            .fetch_add(prompt_tokens, Ordering::Relaxed);
+        self.completion_tokens
+            .fetch_add(completion_tokens, Ordering::Relaxed);
+    }
+
+    fn total(&self) -> TokenUsage {
+        TokenUsage {
+            prompt_tokens: self.prompt_tokens.load(Ordering::Relaxed),
+            completion_tokens: self.completion_tokens.load(Ordering::Relaxed),
+        }
+    }
+}
+
+/// Build the summarizer selected in the scan configuration
+pub fn from_config(config: &ScanConfig) -> Result<Arc<dyn Summarizer>> {
+    match config.llm_backend {
+        LlmBackend::Ollama => Ok(Arc::new(OllamaSummarizer::new(config)?)),
+        LlmBackend::OpenAi => Ok(Arc::new(OpenAiSummarizer::new(config)?)),
+        LlmBackend::Stub => Ok(Arc::new(StubSummarizer)),
+    }
+}
+
+/// Read the API key from the configured environment variable, if any
+fn api_key(config: &ScanConfig) -> Option<String> {
+    let var = config.llm_api_key_env.as_deref()?;
+    match std::env::var(var) {
+        Ok(key) => Some(key),
+        Err(_) => {
+            warn!("API key variable '{}' is not set", var);
+            None
+        }
+    }
+}
+
+/// HTTP client with the configured timeout and bearer token
+fn http_client(config: &ScanConfig) -> Result<reqwest::Client> {
+    let mut headers = HeaderMap::new();
+    if let Some(key) = api_key(config) {
+        let mut value = HeaderValue::from_str(&format!("Bearer {}", key))
+            .context("API key is not a valid header value")?;
+        value.set_sensitive(true);
+        headers.insert(AUTHORIZATION, value);
+    }
+
+    reqwest::Client::builder()
+        .timeout(Duration::from_secs(config.llm_timeout_secs))
+        .default_headers(headers)
+        .build()
+        .context("Failed to build HTTP client")
+}
+
+/// Port Ollama listens on unless `llm_port` says otherwise
+const OLLAMA_PORT: u16 = 11434;
+
+/// Summarizer backed by an Ollama server
+pub struct OllamaSummarizer {
+    ollama: Ollama,
+    model: String,
+    vision_model: String,
+    usage: UsageCounter,
+}
+
+impl OllamaSummarizer {
+    pub fn new(config: &ScanConfig) -> Result<Self> {
+        // Ollama::new_with_client panics on a bad URL, so validate it first
+        reqwest::Url::parse(&config.llm_host)
+            .with_context(|| format!("Invalid LLM host: {}", config.llm_host))?;
+
+        Ok(Self {
+            ollama: Ollama::new_with_client(
+                config.llm_host.clone(),
+                config.llm_port.unwrap_or(OLLAMA_PORT),
+                http_client(config)?,
+            ),
+            model: config.model_name.clone(),
+            vision_model: config.vision_model_name.clone(),
+            usage: UsageCounter::default(),
+        })
+    }
+
+    /// Count the tokens Ollama reports with the final response
+    fn record_usage(&self, response: &GenerationResponse) {
+        if let Some(data) = &response.final_data {
+            self.usage.add(
+                u64::from(data.prompt_eval_count),
+                u64::from(data.eval_count),
+            );
+        }
+    }
+}
+
+#[async_trait]
+impl Summarizer for OllamaSummarizer {
+    async fn generate(&self, system: &str, prompt: &str) -> Result<String> {
+        let res = self
+            .ollama
+            .generate(
+                GenerationRequest::new(self.model.clone(), prompt.to_string())
+                    .system(system.to_string()),
+            )
+            .await?;
+        self.record_usage(&res);
+
+        Ok(res.response)
+    }
+
+    async fn describe_image(&self, prompt: &str, image: &[u8], _mime_type: &str) -> Result<String> {
+        let base64_image = base64::engine::general_purpose::STANDARD.encode(image);
+        let request = GenerationRequest::new(self.vision_model.clone(), prompt.to_string())
+            .add_image(Image::from_base64(&base64_image));
+
+        let res = self.ollama.generate(request).await?;
+        self.record_usage(&res);
+
+        Ok(res.response)
+    }
+
+    fn token_usage(&self) -> TokenUsage {
+        self.usage.total()
+    }
+}
+
+/// Chat completions URL below the configured host.
+///
+/// A port in the host URL wins, `llm_port` only fills in a missing one, so
+/// hosted endpoints keep their default HTTPS port.
+fn chat_completions_endpoint(config: &ScanConfig) -> Result<String> {
+    let mut base = reqwest::Url::parse(&config.llm_host)
+        .with_context(|| format!("Invalid LLM host: {}", config.llm_host))?;
+    if let (None, Some(port)) = (base.port(), config.llm_port) {
+        base.set_port(Some(port))
+            .map_err(|_| anyhow!("LLM host cannot have a port: {}", config.llm_host))?;
+    }
+    Ok(format!(
+        "{}/v1/chat/completions",
+        base.as_str().trim_end_matches('/')
+    ))
+}
+
+/// Summarizer for servers exposing the OpenAI chat completions API
+/// (vLLM, llama.cpp, LocalAI, OpenAI itself, ...)
+pub struct OpenAiSummarizer {
+    client: reqwest::Client,
+    endpoint: String,
+    model: String,
+    vision_model: String,
+    usage: UsageCounter,
+}
+
+impl OpenAiSummarizer {
+    pub fn new(config: &ScanConfig) -> Result<Self> {
+        Ok(Self {
+            client: http_client(config)?,
+            endpoint: chat_completions_endpoint(config)?,
+            model: config.model_name.clone(),
+            vision_model: config.vision_model_name.clone(),
+            usage: UsageCounter::default(),
+        })
+    }
+
+    async fn chat(&self, body: serde_json::Value) -> Result<String> {
+        let response = self
+            .client
+            .post(&self.endpoint)
+            .json(&body)
+            .send()
+            .await
+            .with_context(|| format!("Failed to reach {}", self.endpoint))?;
+
+        let status = response.status();
+        let body: serde_json::Value = response
+            .json()
+            .await
+            .context("Failed to decode completion response")?;
+        if !status.is_success() {
+            return Err(anyhow!("Completion request failed ({}): {}", status, body));
+        }
+
+        // Servers that do not count tokens leave out the usage object
+        let usage = &body["usage"];
+        self.usage.add(
+            usage["prompt_tokens"].as_u64().unwrap_or_default(),
+            usage["completion_tokens"].as_u64().unwrap_or_default(),
+        );
+
+        body["choices"][0]["message"]["content"]
+            .as_str()
+            .map(str::to_string)
+            .ok_or_else(|| anyhow!("Completion response has no message content"))
+    }
+}
+
+#[async_trait]
+impl Summarizer for OpenAiSummarizer {
+    async fn generate(&self, system: &str, prompt: &str) -> Result<String> {
+        self.chat(json!({
+            "model": self.model,
+            "messages": [
+                { "role": "system", "content": system },
+                { "role": "user", "content": prompt },
+            ],
+        }))
+        .await
+    }
+
+    async fn describe_image(&self, prompt: &str, image: &[u8], mime_type: &str) -> Result<String> {
+        let base64_image = base64::engine::general_purpose::STANDARD.encode(image);
+        self.chat(json!({
+            "model": self.vision_model,
+            "messages": [{
+                "role": "user",
+                "content": [
+                    { "type": "text", "text": prompt },
+                    {
+                        "type": "image_url",
+                        "image_url": { "url": format!("data:{};base64,{}", mime_type, base64_image) },
+                    },
+                ],
+            }],
+        }))
+        .await
+    }
+
+    fn token_usage(&self) -> TokenUsage {
+        self.usage.total()
+    }
+}
+
+/// Offline summarizer returning a deterministic digest of its input.
+///
+/// Useful for tests and for exercising the indexing pipeline without a model server.
+pub struct StubSummarizer;
+
+/// Number of characters of the prompt echoed back by the stub
+const STUB_EXCERPT_CHARS: usize = 200;
+
+#[async_trait]
+impl Summarizer for StubSummarizer {
+    async fn generate(&self, _system: &str, prompt: &str) -> Result<String> {
+        let excerpt: String = prompt.chars().take(STUB_EXCERPT_CHARS).collect();
+        Ok(format!(
+            "Stub summary ({} characters): {}",
+            prompt.chars().count(),
+            excerpt
+        ))
+    }
+
+    async fn describe_image(&self, _prompt: &str, image: &[u8], mime_type: &str) -> Result<String> {
+        Ok(format!(
+            "Stub description of a {} byte {} image",
+            image.len(),
+            mime_type
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn endpoint(host: &str, port: Option<u16>) -> String {
+        let config = ScanConfig {
+            llm_host: host.to_string(),
+            llm_port: port,
+            ..ScanConfig::default()
+        };
+        chat_completions_endpoint(&config).unwrap()
+    }
+
+    #[test]
+    fn hosted_endpoints_keep_their_url() {
+        assert_eq!(
+            endpoint("https://api.openai.com", None),
+            "https://api.openai.com/v1/chat/completions"
+        );
+        assert_eq!(
+            endpoint("http://gpu-box:8000/", Some(9000)),
+            "http://gpu-box:8000/v1/chat/completions"
+        );
+    }
+
+    #[test]
+    fn the_configured_port_fills_in_a_missing_one() {
+        assert_eq!(
+            endpoint("http://gpu-box", Some(8000)),
+            "http://gpu-box:8000/v1/chat/completions"
+        );
+    }
+}
@@ -1,11 +1,25 @@
+use anyhow::{bail, Result};
 use clap::{Parser, Subcommand};
 use log::{debug, info};
-use qdrant_client::Qdrant;
-use std::path::PathBuf;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
 
-use arborist::config::Config;
-use arborist::database::{self, chunk_string};
-use arborist::utils::{setup_fastembed, DirScanConfig};
+use arborist::api;
+use arborist::ask;
+use arborist::config::{Config, ScanConfig};
+use arborist::daemon::{self, Request, Response};
+use arborist::database;
+use arborist::filters::QueryFilters;
+use arborist::indexer::Indexer;
+use arborist::llm;
+use arborist::mcp;
+use arborist::output::{self, OutputFormat};
+use arborist::progress::ScanProgress;
+use arborist::report::{ReportFormat, ScanReport};
+use arborist::store;
+use arborist::utils::{setup_fastembed, setup_reranker, DirScanConfig, WalkArgs};
+use arborist::watch;
 
 #[derive(Debug, clap::Parser)]
 #[clap(
@@ -27,13 +41,83 @@ enum Commands {
         // file path to scan
         #[arg()]
         path: PathBuf,
+
+        // how the report at the end of the scan is printed
+        #[arg(long, value_enum, default_value_t = ReportFormat::Text)]
+        report: ReportFormat,
+
+        #[command(flatten)]
+        walk: WalkArgs,
     },
 
     Query {
         // query string from user
         #[arg()]
         query: String,
+
+        // how the results are printed
+        #[arg(long, value_enum, default_value_t = OutputFormat::Table)]
+        format: OutputFormat,
+
+        #[command(flatten)]
+        filters: QueryFilters,
+    },
+
+    // find the indexed files most like a given file
+    Similar {
+        // file to compare with, indexed or not
+        #[arg()]
+        file: PathBuf,
+
+        // how the results are printed
+        #[arg(long, value_enum, default_value_t = OutputFormat::Table)]
+        format: OutputFormat,
+
+        #[command(flatten)]
+        filters: QueryFilters,
+    },
+
+    // answer a question from the indexed files, citing them
+    Ask {
+        // question from user
+        #[arg()]
+        question: String,
+
+        #[command(flatten)]
+        filters: QueryFilters,
+    },
+
+    // scan a directory, then keep its index in sync with changes until Ctrl-C
+    Watch {
+        // directory to watch
+        #[arg()]
+        path: PathBuf,
+
+        #[command(flatten)]
+        walk: WalkArgs,
+    },
+
+    Prune {
+        // directory whose deleted files are removed from the index
+        #[arg()]
+        path: PathBuf,
     },
+
+    // re-embed the index after switching embedding models
+    Migrate,
+
+    // keep the models loaded and answer scan, query and status over a local socket
+    Serve {
+        // also serve the HTTP API, on 127.0.0.1:7431 unless an address is given
+        #[arg(long, num_args = 0..=1, default_missing_value = "127.0.0.1:7431")]
+        http: Option<SocketAddr>,
+    },
+
+    // show what the daemon is doing
+    Status,
+
+    // answer Model Context Protocol requests on stdin and stdout
+    Mcp,
 }
 
 #[tokio::main]
@@ -45,47 +129,212 @@ async fn main() -> anyhow::Result<()> {
     let cli = Cli::parse();
 
     // Load the configuration
-    let config = Config::load(cli.config)?;
+    let config = Arc::new(Config::load(cli.config)?);
     info!("Loaded config: {:#?}", config);
 
-    // Initialize Qdrant client
-    let client = Qdrant::from_url(&config.db_url).build()?;
-    database::create_hybrid_collection(&client, &config.collection_name).await?;
+    // A running daemon answers with its models already loaded
+    if let Some(request) = daemon_request(&cli.command)? {
+        if let Some(mut client) = daemon::Client::connect(&config).await {
+            debug!("Sending {:?} to the daemon", request);
+            return print_response(&cli.command, client.send(&request).await?);
+        }
+    }
+    if let Commands::Status = cli.command {
+        println!(
+            "No daemon is serving collection '{}'. Start one with `arborist serve`.",
+            config.collection_name
+        );
+        return Ok(());
+    }
+
+    // Open the configured vector store
+    let store = store::connect(&config)?;
+    // Migrating is how a model mismatch gets resolved, so it skips the checks
+    let migrating = matches!(cli.command, Commands::Migrate);
+    if !migrating {
+        database::check_interrupted_migration(store.as_ref(), &config).await?;
+    }
+    database::create_hybrid_collection(store.as_ref(), &config).await?;
+    if !migrating {
+        database::check_embedding_model(store.as_ref(), &config).await?;
+    }
+
+    // Pruning only needs the store and the walk
+    if let Commands::Prune { path } = &cli.command {
+        let path = path.canonicalize()?;
+        let scan_config = DirScanConfig::from_config(path.clone(), &config.scan);
+        let scan_result = scan_config.scan_dir().await?;
+
+        let summary =
+            database::prune_index(store.as_ref(), &config.collection_name, &path, &scan_result)
+                .await?;
+        print_prune_summary(&summary);
+        return Ok(());
+    }
 
     //Get embedding models
-    let (model, sparse_model) = setup_fastembed()?;
+    let (model, sparse_model) = setup_fastembed(&config.embedding)?;
+    let (model, sparse_model) = (Arc::new(model), Arc::new(sparse_model));
+
+    let indexer = Indexer::new(
+        config.clone(),
+        store.clone(),
+        llm::from_config(&config.scan)?,
+        model.clone(),
+        sparse_model.clone(),
+    )
+    .with_reranker(setup_reranker(&config.query)?);
 
     match &cli.command {
-        Commands::Scan { path } => {
-            let scan_config = DirScanConfig::new(path.to_path_buf());
-            let scan_result = scan_config.scan_dir().await?;
+        Commands::Scan { path, report, walk } => {
+            let (path, walk_options) = scan_target(&config, path, walk)?;
+            let scan_report = indexer
+                .scan(&path, &walk_options, &ScanProgress::new())
+                .await?;
+            print_scan_report(&scan_report, *report)?;
+        }
 
-            // Process and upload files with the new function
-            database::process_and_upload_files(
-                &client,
-                &config,
-                &scan_result.file_metadata_list,
-                Some(false), // Set to true if you want to force regenerate summaries
+        Commands::Watch { path, walk } => {
+            let (path, walk_options) = scan_target(&config, path, walk)?;
+            watch::watch(&indexer, &path, &walk_options).await?;
+        }
+
+        Commands::Serve { http } => match http {
+            Some(addr) => {
+                tokio::try_join!(daemon::serve(indexer.clone()), api::serve(indexer, *addr))?;
+            }
+            None => daemon::serve(indexer).await?,
+        },
+
+        Commands::Mcp => {
+            mcp::serve(
+                &indexer,
+                tokio::io::BufReader::new(tokio::io::stdin()),
+                tokio::io::stdout(),
             )
-            .await?;
+            .await?
         }
 
-        Commands::Query { query } => {
-            let sparse_query_vector = sparse_model.embed([query].to_vec(), None)?;
-            let query_vector = model.embed([query].to_vec(), None)?[0].clone();
-            debug!("Query Vector: {:?}", query_vector);
-            debug!("Sparse Query Vector: {:?}", sparse_query_vector);
-
-            database::query_and_print_file_paths(
-                &client,
-                &config.collection_name,
-                query_vector,
-                config.query.top_k_results,
-                false,
+        // Answered by the daemon or before the models were loaded
+        Commands::Status | Commands::Prune { .. } => {}
+
+        Commands::Query {
+            query,
+            format,
+            filters,
+        } => {
+            let hits = indexer.search(query, &filters.to_filter()?).await?;
+            output::print_hits(&hits, *format)?;
+        }
+
+        Commands::Similar {
+            file,
+            format,
+            filters,
+        } => {
+            let hits = indexer.similar(file, &filters.to_filter()?).await?;
+            output::print_hits(&hits, *format)?;
+        }
+
+        Commands::Ask { question, filters } => {
+            let answer = ask::answer(&indexer, question, &filters.to_filter()?).await?;
+            print!("{}", answer);
+        }
+
+        Commands::Migrate => {
+            let summary = database::migrate_collection(
+                store.as_ref(),
+                &config,
+                model.clone(),
+                sparse_model.clone(),
             )
             .await?;
+            if let Some(backup_path) = &summary.backup_path {
+                println!(
+                    "Migrated {} points to {} ({} without text dropped), payload backup at {}",
+                    summary.migrated_points,
+                    config.embedding.dense_model,
+                    summary.dropped_points,
+                    backup_path.display()
+                );
+            }
         }
     }
 
     Ok(())
 }
+
+fn print_prune_summary(summary: &database::PruneSummary) {
+    println!(
+        "Pruned {} deleted files ({} points), relocated {} moved files.",
+        summary.deleted_files, summary.deleted_points, summary.relocated_files
+    );
+}
+
+/// Directory and walk options of a scan, the flags override the configured options
+fn scan_target(config: &Config, path: &Path, walk: &WalkArgs) -> Result<(PathBuf, ScanConfig)> {
+    let path = path.canonicalize()?;
+    let mut walk_options = config.scan.clone();
+    walk.apply(&mut walk_options);
+    Ok((path, walk_options))
+}
+
+fn print_scan_report(report: &ScanReport, format: ReportFormat) -> Result<()> {
+    report.print(format)?;
+    if report.interrupted {
+        eprintln!("Run the scan again to resume.");
+    }
+    Ok(())
+}
+
+/// The request a running daemon answers in place of `command`, if any
+fn daemon_request(command: &Commands) -> Result<Option<Request>> {
+    Ok(match command {
+        // The daemon walks with its own settings and the flags given here
+        Commands::Scan { path, walk, .. } => Some(Request::Scan {
+            path: path.canonicalize()?,
+            walk: walk.clone(),
+        }),
+        // Filters are resolved here, `--under` is relative to this directory
+        Commands::Query { query, filters, .. } => Some(Request::Query {
+            query: query.clone(),
+            filter: filters.to_filter()?,
+        }),
+        Commands::Similar { file, filters, .. } => Some(Request::Similar {
+            path: file.canonicalize().unwrap_or_else(|_| file.clone()),
+            filter: filters.to_filter()?,
+        }),
+        Commands::Ask { question, filters } => Some(Request::Ask {
+            question: question.clone(),
+            filter: filters.to_filter()?,
+        }),
+        Commands::Status => Some(Request::Status),
+        _ => None,
+    })
+}
+
+/// Print a daemon response the way the command prints its own results
+fn print_response(command: &Commands, response: Response) -> Result<()> {
+    match (command, response) {
+        (Commands::Query { format, .. }, Response::Hits { hits })
+        | (Commands::Similar { format, .. }, Response::Hits { hits }) => {
+            output::print_hits(&hits, *format)
+        }
+        (Commands::Ask { .. }, Response::Answer { answer }) => {
+            print!("{}", answer);
+            Ok(())
+        }
+        (
+            Commands::Scan { report, .. },
+            Response::Report {
+                report: scan_report,
+            },
+        ) => print_scan_report(&scan_report, *report),
+        (Commands::Status, Response::Status { status }) => {
+            print!("{}", status);
+            Ok(())
+        }
+        (_, Response::Error { message }) => bail!("Daemon: {}", message),
+        (_, response) => bail!("Unexpected daemon response: {:?}", response),
+    }
+}
@@ -0,0 +1,321 @@
+//! Model Context Protocol server, so agents and editor assistants can use the index.
+//!
+//! `arborist mcp` speaks JSON-RPC 2.0 over stdio, one message per line. The
+//! tools search the index, read the stored summary of a file, list the indexed
+//! directories and index a path again. Anything else printed goes to stderr,
+//! stdout belongs to the protocol.
+
+use crate::database::{self, FileDetail};
+use crate::filters::QueryFilters;
+use crate::indexer::Indexer;
+use crate::output::QueryHit;
+use crate::progress::ScanProgress;
+use crate::roots::{self, IndexedRoot};
+use crate::utils::DirScanConfig;
+use anyhow::{anyhow, Context, Result};
+use async_trait::async_trait;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::path::{Path, PathBuf};
+use tokio::io::{AsyncBufRead, AsyncBufReadExt, AsyncWrite, AsyncWriteExt};
+
+/// MCP revision implemented by the server
+pub const PROTOCOL_VERSION: &str = "2024-11-05";
+
+// JSON-RPC error codes
+const PARSE_ERROR: i64 = -32700;
+const INVALID_REQUEST: i64 = -32600;
+const METHOD_NOT_FOUND: i64 = -32601;
+const INVALID_PARAMS: i64 = -32602;
+
+/// What the MCP tools do, implemented by [`Indexer`]
+#[async_trait]
+pub trait IndexTools: Send + Sync {
+    async fn search(&self, query: &str, filters: &QueryFilters) -> Result<Vec<QueryHit>>;
+
+    /// The stored summary and metadata of a file, `None` when it is not indexed
+    async fn file_summary(&self, path: &str) -> Result<Option<FileDetail>>;
+
+    async fn indexed_roots(&self) -> Result<Vec<IndexedRoot>>;
+
+    /// Index a directory or a single file again, returning what was done
+    async fn reindex(&self, path: &Path) -> Result<Value>;
+}
+
+#[async_trait]
+impl IndexTools for Indexer {
+    async fn search(&self, query: &str, filters: &QueryFilters) -> Result<Vec<QueryHit>> {
+        Indexer::search(self, query, &filters.to_filter()?).await
+    }
+
+    async fn file_summary(&self, path: &str) -> Result<Option<FileDetail>> {
+        // Paths are indexed canonicalized, a deleted file can only be found as given
+        let path = Path::new(path)
+            .canonicalize()
+            .map_or_else(|_| path.to_string(), |path| path.display().to_string());
+        database::file_detail(self.store.as_ref(), &self.config.collection_name, &path).await
+    }
+
+    async fn indexed_roots(&self) -> Result<Vec<IndexedRoot>> {
+        roots::load(&self.config.collection_name)
+    }
+
+    async fn reindex(&self, path: &Path) -> Result<Value> {
+        let path = path
+            .canonicalize()
+            .with_context(|| format!("Invalid path: {}", path.display()))?;
+        if path.is_dir() {
+            let report = self
+                .scan(&path, &self.config.scan, &ScanProgress::hidden())
+                .await?;
+            return Ok(serde_json::to_value(report)?);
+        }
+
+        // A single file is indexed with the walk rules of its folder
+        let folder = path
+            .parent()
+            .ok_or_else(|| anyhow!("{} has no parent folder", path.display()))?;
+        let scan_config = DirScanConfig::from_config(folder.to_path_buf(), &self.config.scan);
+        let stats = self.index_paths(&scan_config, vec![path.clone()]).await?;
+        Ok(json!({
+            "path": path.display().to_string(),
+            "indexed_files": stats.indexed_files,
+            "indexed_points": stats.indexed_points,
+            "unchanged_files": stats.unchanged_files,
+            "refreshed_files": stats.refreshed_files,
+            "moved_files": stats.moved_files,
+            "failures": stats.failed,
+        }))
+    }
+}
+
+/// Answer the messages read from `input` until it is closed
+pub async fn serve(
+    tools: &dyn IndexTools,
+    input: impl AsyncBufRead + Unpin,
+    mut output: impl AsyncWrite + Unpin,
+) -> Result<()> {
+    let mut lines = input.lines();
+    while let Some(line) = lines.next_line().await? {
+        if line.trim().is_empty() {
+            continue;
+        }
+        if let Some(response) = handle_message(tools, &line).await {
+            let mut message = serde_json::to_vec(&response)?;
+            message.push(b'\n');
+            output.write_all(&message).await?;
+            output.flush().await?;
+        }
+    }
+    Ok(())
+}
+
+/// A JSON-RPC error
+struct RpcError {
+    code: i64,
+    message: String,
+}
+
+impl RpcError {
+    fn new(code: i64, message: impl Into<String>) -> Self {
+        RpcError {
+            code,
+            message: message.into(),
+        }
+    }
+}
+
+fn error_response(id: Value, error: RpcError) -> Value {
+    json!({
+        "jsonrpc": "2.0",
+        "id": id,
+        "error": { "code": error.code, "message": error.message },
+    })
+}
+
+/// The response to a message, `None` for notifications
+async fn handle_message(tools: &dyn IndexTools, line: &str) -> Option<Value> {
+    let message: Value = match serde_json::from_str(line) {
+        Ok(message) => message,
+        Err(e) => {
+            return Some(error_response(
+                Value::Null,
+                RpcError::new(PARSE_ERROR, format!("Parse error: {}", e)),
+            ))
+        }
+    };
+
+    let id = message.get("id").cloned();
+    let Some(method) = message.get("method").and_then(Value::as_str) else {
+        // Responses to requests the server never sends are ignored
+        return id.map(|id| error_response(id, RpcError::new(INVALID_REQUEST, "Invalid request")));
+    };
+    let params = message.get("params").cloned().unwrap_or(Value::Null);
+
+    let result = dispatch(tools, method, params).await;
+    let id = id?;
+    Some(match result {
+        Ok(result) => json!({ "jsonrpc": "2.0", "id": id, "result": result }),
+        Err(error) => error_response(id, error),
+    })
+}
+
+async fn dispatch(
+    tools: &dyn IndexTools,
+    method: &str,
+    params: Value,
+) -> std::result::Result<Value, RpcError> {
+    match method {
+        "initialize" => Ok(json!({
+            "protocolVersion": PROTOCOL_VERSION,
+            "capabilities": { "tools": {} },
+            "serverInfo": { "name": "arborist", "version": env!("CARGO_PKG_VERSION") },
+        })),
+        "ping" => Ok(json!({})),
+        "tools/list" => Ok(json!({ "tools": tool_definitions() })),
+        "tools/call" => {
+            let call: ToolCall = parse_params(params)?;
+            call_tool(tools, &call.name, call.arguments).await
+        }
+        method if method.starts_with("notifications/") => Ok(Value::Null),
+        method => Err(RpcError::new(
+            METHOD_NOT_FOUND,
+            format!("Method not found: {}", method),
+        )),
+    }
+}
+
+fn parse_params<T: DeserializeOwned>(params: Value) -> std::result::Result<T, RpcError> {
+    serde_json::from_value(params)
+        .map_err(|e| RpcError::new(INVALID_PARAMS, format!("Invalid params: {}", e)))
+}
+
+#[derive(Deserialize, Debug)]
+struct ToolCall {
+    name: String,
+    #[serde(default)]
+    arguments: Value,
+}
+
+#[derive(Deserialize, Debug)]
+struct SearchArgs {
+    query: String,
+    #[serde(flatten)]
+    filters: QueryFilters,
+}
+
+#[derive(Deserialize, Debug)]
+struct PathArgs {
+    path: String,
+}
+
+async fn call_tool(
+    tools: &dyn IndexTools,
+    name: &str,
+    arguments: Value,
+) -> std::result::Result<Value, RpcError> {
+    // No arguments at all is the same as an empty set of them
+    let arguments = match arguments {
+        Value::Null => json!({}),
+        arguments => arguments,
+    };
+
+    let outcome = match name {
+        "search" => {
+            let args: SearchArgs = parse_params(arguments)?;
+            tools
+                .search(&args.query, &args.filters)
+                .await
+                .and_then(|hits| to_text(&hits))
+        }
+        "get_summary" => {
+            let args: PathArgs = parse_params(arguments)?;
+            match tools.file_summary(&args.path).await {
+                Ok(Some(detail)) => to_text(&detail),
+                Ok(None) => Err(anyhow!("{} is not indexed", args.path)),
+                Err(e) => Err(e),
+            }
+        }
+        "list_roots" => tools
+            .indexed_roots()
+            .await
+            .and_then(|roots| to_text(&roots)),
+        "reindex" => {
+            let args: PathArgs = parse_params(arguments)?;
+            tools
+                .reindex(&PathBuf::from(&args.path))
+                .await
+                .and_then(|outcome| to_text(&outcome))
+        }
+        name => {
+            return Err(RpcError::new(
+                INVALID_PARAMS,
+                format!("Unknown tool: {}", name),
+            ))
+        }
+    };
+
+    // Failed tool calls are results the model can read, not protocol errors
+    Ok(match outcome {
+        Ok(text) => json!({ "content": [{ "type": "text", "text": text }], "isError": false }),
+        Err(e) => json!({
+            "content": [{ "type": "text", "text": format!("{:#}", e) }],
+            "isError": true,
+        }),
+    })
+}
+
+fn to_text(value: &impl Serialize) -> Result<String> {
+    Ok(serde_json::to_string_pretty(value)?)
+}
+
+fn tool_definitions() -> Value {
+    let path_schema = json!({
+        "type": "object",
+        "properties": {
+            "path": { "type": "string", "description": "Absolute path" }
+        },
+        "required": ["path"],
+    });
+
+    json!([
+        {
+            "name": "search",
+            "description": "Semantic search over the summaries of the indexed files. Returns the best matching files with their score, path, metadata and summary.",
+            "inputSchema": {
+                "type": "object",
+                "properties": {
+                    "query": { "type": "string", "description": "What to look for, in natural language" },
+                    "types": {
+                        "type": "array",
+                        "items": { "type": "string", "enum": ["document", "image", "audio", "video", "archive", "other"] },
+                        "description": "Only files of these types",
+                    },
+                    "extensions": { "type": "array", "items": { "type": "string" }, "description": "Only files with these extensions" },
+                    "min_size": { "type": "integer", "description": "Minimum file size in bytes" },
+                    "max_size": { "type": "integer", "description": "Maximum file size in bytes" },
+                    "modified_after": { "type": "integer", "description": "Only files modified at or after this Unix timestamp" },
+                    "modified_before": { "type": "integer", "description": "Only files modified before this Unix timestamp" },
+                    "under": { "type": "string", "description": "Only files somewhere below this directory" },
+                },
+                "required": ["query"],
+            },
+        },
+        {
+            "name": "get_summary",
+            "description": "The stored summary and metadata of an indexed file.",
+            "inputSchema": path_schema,
+        },
+        {
+            "name": "list_roots",
+            "description": "The directories scanned into the index, with the time of their last scan.",
+            "inputSchema": { "type": "object", "properties": {} },
+        },
+        {
+            "name": "reindex",
+            "description": "Index a directory or file again, picking up new, changed, moved and deleted files. Can take a while for large directories.",
+            "inputSchema": path_schema,
+        },
+    ])
+}
@@ -0,0 +1,343 @@
+//! Formatting of query results for people and for scripts.
+
+use crate::database::{FileMatch, Passage};
+use anyhow::Result;
+use chrono::{DateTime, Local};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::io::{self, Write};
+
+/// How query results are printed
+#[derive(clap::ValueEnum, Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum OutputFormat {
+    /// Aligned columns with a summary line per hit
+    #[default]
+    Table,
+    /// A single JSON array
+    Json,
+    /// One JSON object per line
+    Ndjson,
+    /// Only the file paths, one per line
+    Paths,
+}
+
+/// A matching file as shown to the user
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct QueryHit {
+    /// Semantic similarity to the query, 0 for files only matched lexically
+    pub score: f32,
+    pub path: String,
+    pub name: String,
+    pub size: Option<u64>,
+    pub filetype: Option<String>,
+    /// Unix timestamp in seconds
+    pub modified_at: Option<i64>,
+    pub summary: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub passage: Option<Passage>,
+    /// Match of the query terms against the file name, path and identifiers, from 0 to 1
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub lexical_score: Option<f32>,
+    /// Reciprocal rank score the hits were ordered by, when lexical matches were blended in
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub blended_score: Option<f32>,
+    /// Cross-encoder score the hits were reordered by, when reranking is on
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub rerank_score: Option<f32>,
+}
+
+impl From<FileMatch> for QueryHit {
+    fn from(file_match: FileMatch) -> Self {
+        let payload = &file_match.payload;
+        let string = |key: &str| payload.get(key).and_then(Value::as_str).map(str::to_string);
+
+        QueryHit {
+            score: file_match.score,
+            path: string("file_path").unwrap_or_default(),
+            name: string("file_name").unwrap_or_default(),
+            size: payload.get("file_size").and_then(Value::as_u64),
+            filetype: string("filetype"),
+            modified_at: payload.get("modified_at").and_then(Value::as_i64),
+            summary: string("summary"),
+            passage: file_match.passage,
+            lexical_score: file_match.lexical_score,
+            blended_score: file_match.blended_score,
+            rerank_score: None,
+        }
+    }
+}
+
+/// Longest summary or passage excerpt shown in the table, in characters
+const TABLE_EXCERPT_CHARS: usize = 100;
+
+/// Print the hits to stdout in the requested format
+pub fn print_hits(hits: &[QueryHit], format: OutputFormat) -> Result<()> {
+    let mut stdout = io::stdout().lock();
+    let result = write_hits(&mut stdout, hits, format);
+
+    // The reader going away (e.g. `| head`) is not an error
+    match result {
+        Err(e) if e.kind() == io::ErrorKind::BrokenPipe => Ok(()),
+        result => Ok(result?),
+    }
+}
+
+fn write_hits(out: &mut impl Write, hits: &[QueryHit], format: OutputFormat) -> io::Result<()> {
+    match format {
+        OutputFormat::Json => {
+            serde_json::to_writer_pretty(&mut *out, hits)?;
+            writeln!(out)?;
+        }
+        OutputFormat::Ndjson => {
+            for hit in hits {
+                serde_json::to_writer(&mut *out, hit)?;
+                writeln!(out)?;
+            }
+        }
+        OutputFormat::Paths => {
+            for hit in hits {
+                writeln!(out, "{}", hit.path)?;
+            }
+        }
+        OutputFormat::Table => {
+            // Reranked hits show the reranker score next to the search score,
+            // lexically matched ones their lexical score
+            let reranked = hits.iter().any(|hit| hit.rerank_score.is_some());
+            let lexical = hits.iter().any(|hit| hit.lexical_score.is_some());
+            if reranked {
+                write!(out, "{:<8}", "RERANK")?;
+            }
+            write!(out, "{:<8}", "SCORE")?;
+            if lexical {
+                write!(out, "{:<8}", "LEXICAL")?;
+            }
+            writeln!(
+                out,
+                "{:>9}  {:<16}  {:<8}  PATH",
+                "SIZE", "MODIFIED", "TYPE"
+            )?;
+            let column = |score: Option<f32>| {
+                score
+                    .map(|score| format!("{:.4}", score))
+                    .unwrap_or_default()
+            };
+            for hit in hits {
+                if reranked {
+                    write!(out, "{:<8}", column(hit.rerank_score))?;
+                }
+                write!(out, "{:<8.4}", hit.score)?;
+                if lexical {
+                    write!(out, "{:<8}", column(hit.lexical_score))?;
+                }
+                writeln!(
+                    out,
+                    "{:>9}  {:<16}  {:<8}  {}",
+                    hit.size.map(human_size).unwrap_or_default(),
+                    hit.modified_at.map(local_time).unwrap_or_default(),
+                    hit.filetype.as_deref().unwrap_or(""),
+                    hit.path
+                )?;
+
+                let text = match &hit.passage {
+                    Some(passage) => Some(passage.text.as_str()),
+                    None => hit.summary.as_deref(),
+                };
+                if let Some(text) = text {
+                    let indent = 8 * (1 + usize::from(reranked) + usize::from(lexical));
+                    writeln!(out, "{:indent$}{}", "", excerpt(text))?;
+                }
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// First characters of a text on a single line
+fn excerpt(text: &str) -> String {
+    let line = text.split_whitespace().collect::<Vec<_>>().join(" ");
+    if line.chars().count() <= TABLE_EXCERPT_CHARS {
+        return line;
+    }
+    let mut short: String = line.chars().take(TABLE_EXCERPT_CHARS - 3).collect();
+    short.push_str("...");
+    short
+}
+
+fn human_size(bytes: u64) -> String {
+    const UNITS: [&str; 5] = ["B", "KiB", "MiB", "GiB", "TiB"];
+
+    let mut size = bytes as f64;
+    let mut unit = 0;
+    while size >= 1024.0 && unit < UNITS.len() - 1 {
+        size /= 1024.0;
+        unit += 1;
+    }
+
+    if unit == 0 {
+        format!("{} {}", bytes, UNITS[0])
+    } else {
+        format!("{:.1} {}", size, UNITS[unit])
+    }
+}
+
+fn local_time(timestamp: i64) -> String {
+    DateTime::from_timestamp(timestamp, 0)
+        .map(|time| {
+            time.with_timezone(&Local)
+                .format("%Y-%m-%d %H:%M")
+                .to_string()
+        })
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn hit(path: &str) -> QueryHit {
+        QueryHit {
+            score: 0.5,
+            path: path.to_string(),
+            name: path.rsplit('/').next().unwrap().to_string(),
+            size: Some(2048),
+            filetype: Some("document".to_string()),
+            modified_at: None,
+            summary: Some("A short   summary\nover two lines".to_string()),
+            passage: None,
+            lexical_score: None,
+            blended_score: None,
+            rerank_score: None,
+        }
+    }
+
+    fn written(hits: &[QueryHit], format: OutputFormat) -> String {
+        let mut out = Vec::new();
+        write_hits(&mut out, hits, format).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    fn header(hits: &[QueryHit]) -> String {
+        let table = written(hits, OutputFormat::Table);
+        table
+            .lines()
+            .next()
+            .unwrap()
+            .split_whitespace()
+            .collect::<Vec<_>>()
+            .join(" ")
+    }
+
+    #[test]
+    fn table_columns_follow_the_scores_present() {
+        let plain = hit("/a.txt");
+        assert_eq!(
+            header(std::slice::from_ref(&plain)),
+            "SCORE SIZE MODIFIED TYPE PATH"
+        );
+
+        let lexical = QueryHit {
+            lexical_score: Some(0.8),
+            ..plain.clone()
+        };
+        assert_eq!(
+            header(&[plain.clone(), lexical.clone()]),
+            "SCORE LEXICAL SIZE MODIFIED TYPE PATH"
+        );
+
+        let reranked = QueryHit {
+            rerank_score: Some(0.9),
+            ..lexical
+        };
+        assert_eq!(
+            header(&[reranked]),
+            "RERANK SCORE LEXICAL SIZE MODIFIED TYPE PATH"
+        );
+    }
+
+    #[test]
+    fn table_rows_show_the_hit_and_an_excerpt() {
+        let table = written(&[hit("/docs/a.txt")], OutputFormat::Table);
+        let lines: Vec<&str> = table.lines().collect();
+        assert_eq!(lines.len(), 3);
+        assert!(lines[1].starts_with("0.5000"));
+        assert!(lines[1].contains("2.0 KiB"));
+        assert!(lines[1].ends_with("document  /docs/a.txt"));
+        assert_eq!(lines[2], "        A short summary over two lines");
+    }
+
+    #[test]
+    fn excerpts_are_cut_on_characters() {
+        let text = "é".repeat(TABLE_EXCERPT_CHARS + 1);
+        let short = excerpt(&text);
+        assert_eq!(short.chars().count(), TABLE_EXCERPT_CHARS);
+        assert!(short.ends_with("é..."));
+
+        let fits = "ü".repeat(TABLE_EXCERPT_CHARS);
+        assert_eq!(excerpt(&fits), fits);
+    }
+
+    #[test]
+    fn sizes_use_binary_units() {
+        assert_eq!(human_size(0), "0 B");
+        assert_eq!(human_size(1023), "1023 B");
+        assert_eq!(human_size(1536), "1.5 KiB");
+        assert_eq!(human_size(5 << 20), "5.0 MiB");
+        assert_eq!(human_size(3 << 40), "3.0 TiB");
+        assert_eq!(human_size(2048 << 40), "2048.0 TiB");
+    }
+
+    #[test]
+    fn json_hits_keep_their_field_names() {
+        let hits = [
+            hit("/a.txt"),
+            QueryHit {
+                lexical_score: Some(0.25),
+                blended_score: Some(0.5),
+                rerank_score: Some(0.75),
+                passage: Some(Passage {
+                    chunk_index: 1,
+                    start: 10,
+                    end: 20,
+                    text: "the passage".to_string(),
+                }),
+                ..hit("/b.txt")
+            },
+        ];
+
+        let json: Value = serde_json::from_str(&written(&hits, OutputFormat::Json)).unwrap();
+        assert_eq!(
+            json[0],
+            json!({
+                "score": 0.5,
+                "path": "/a.txt",
+                "name": "a.txt",
+                "size": 2048,
+                "filetype": "document",
+                "modified_at": null,
+                "summary": "A short   summary\nover two lines",
+            })
+        );
+        assert_eq!(json[1]["lexical_score"], 0.25);
+        assert_eq!(json[1]["blended_score"], 0.5);
+        assert_eq!(json[1]["rerank_score"], 0.75);
+        assert_eq!(
+            json[1]["passage"],
+            json!({ "chunk_index": 1, "start": 10, "end": 20, "text": "the passage" })
+        );
+
+        let ndjson = written(&hits, OutputFormat::Ndjson);
+        let lines: Vec<Value> = ndjson
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        assert_eq!(lines, json.as_array().unwrap().clone());
+    }
+
+    #[test]
+    fn paths_are_written_one_per_line() {
+        let hits = [hit("/a.txt"), hit("/b c.txt")];
+        assert_eq!(written(&hits, OutputFormat::Paths), "/a.txt\n/b c.txt\n");
+    }
+}
@@ -0,0 +1,142 @@
+//! Live progress display of a scan.
+//!
+//! One line per phase with counts, rate, ETA and the file being worked on,
+//! drawn on stderr. Nothing is drawn unless both stdout and stderr are
+//! terminals, so piped output and CI logs stay clean.
+
+use indicatif::{MultiProgress, ProgressBar, ProgressDrawTarget, ProgressStyle};
+use std::io::{self, IsTerminal};
+use std::time::Duration;
+
+/// Progress bars of the phases of a scan, cheap to clone into tasks
+#[derive(Clone)]
+pub struct ScanProgress {
+    multi: MultiProgress,
+    pub walk: ProgressBar,
+    pub extract: ProgressBar,
+    pub summarize: ProgressBar,
+    pub embed: ProgressBar,
+    /// Files written to the index or given up on
+    pub upload: ProgressBar,
+}
+
+impl ScanProgress {
+    /// Progress display, drawn only when attached to a terminal
+    pub fn new() -> Self {
+        Self::with_display(io::stdout().is_terminal() && io::stderr().is_terminal())
+    }
+
+    /// Progress tracking that never draws anything
+    pub fn hidden() -> Self {
+        Self::with_display(false)
+    }
+
+    fn with_display(display: bool) -> Self {
+        let target = if display {
+            ProgressDrawTarget::stderr()
+        } else {
+            ProgressDrawTarget::hidden()
+        };
+        let multi = MultiProgress::with_draw_target(target);
+
+        let walk = multi.add(
+            ProgressBar::new_spinner()
+                .with_prefix("walk")
+                .with_style(walk_style()),
+        );
+        walk.enable_steady_tick(Duration::from_millis(100));
+
+        // The stage bars only show up once the pipeline starts
+        let stage_bar = |prefix: &'static str| {
+            ProgressBar::with_draw_target(Some(0), ProgressDrawTarget::hidden())
+                .with_prefix(prefix)
+                .with_style(stage_style())
+        };
+
+        ScanProgress {
+            extract: stage_bar("extract"),
+            summarize: stage_bar("summarize"),
+            embed: stage_bar("embed"),
+            upload: stage_bar("upload"),
+            multi,
+            walk,
+        }
+    }
+
+    /// Finish the walk and show the pipeline stages
+    pub fn start_pipeline(&self) {
+        self.walk.finish_with_message("done");
+        for bar in self.stage_bars() {
+            self.multi.add(bar.clone());
+        }
+    }
+
+    /// Account for a file queued for indexing
+    pub fn queued(&self) {
+        for bar in self.stage_bars() {
+            bar.inc_length(1);
+        }
+    }
+
+    /// Print a line above the bars
+    pub fn println(&self, message: impl AsRef<str>) {
+        self.multi.suspend(|| eprintln!("{}", message.as_ref()));
+    }
+
+    /// Remove the bars so the report starts on a clean screen
+    pub fn finish(&self) {
+        self.walk.finish_and_clear();
+        for bar in self.stage_bars() {
+            bar.finish_and_clear();
+        }
+    }
+
+    fn stage_bars(&self) -> [&ProgressBar; 4] {
+        [&self.extract, &self.summarize, &self.embed, &self.upload]
+    }
+}
+
+impl Default for ScanProgress {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+fn walk_style() -> ProgressStyle {
+    ProgressStyle::with_template("{prefix:>9.bold} {spinner} {pos} files {per_sec:>10} {wide_msg}")
+        .expect("valid progress template")
+}
+
+fn stage_style() -> ProgressStyle {
+    ProgressStyle::with_template(
+        "{prefix:>9.bold} [{bar:25}] {pos}/{len} {per_sec:>10} eta {eta:>3} {wide_msg}",
+    )
+    .expect("valid progress template")
+    .progress_chars("=> ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn queued_files_count_on_every_stage() {
+        let progress = ScanProgress::hidden();
+        progress.walk.inc(3);
+        progress.start_pipeline();
+        assert!(progress.walk.is_finished());
+
+        for _ in 0..3 {
+            progress.queued();
+        }
+        progress.extract.inc(2);
+        for bar in progress.stage_bars() {
+            assert_eq!(bar.length(), Some(3));
+        }
+        assert_eq!(progress.extract.position(), 2);
+        assert_eq!(progress.upload.position(), 0);
+
+        progress.finish();
+        assert!(progress.stage_bars().iter().all(|bar| bar.is_finished()));
+    }
+}
@@ -0,0 +1,399 @@
+//! Summary report printed at the end of a scan.
+
+use crate::database::{FailedFile, PruneSummary, ScanStats};
+use crate::llm::TokenUsage;
+use crate::utils::DirScanResult;
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::path::Path;
+use std::time::Duration;
+
+/// How the scan report is printed
+#[derive(clap::ValueEnum, Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum ReportFormat {
+    /// Readable summary
+    #[default]
+    Text,
+    /// A single JSON object, e.g. for CI to archive
+    Json,
+}
+
+/// Extensions listed in the text report, the JSON report has all of them
+const TEXT_EXTENSIONS: usize = 20;
+
+/// Width of the longest bar of the extension histogram
+const HISTOGRAM_WIDTH: usize = 30;
+
+/// Files of one type found and processed by a scan
+#[derive(Serialize, Deserialize, Debug, Default, Clone)]
+pub struct TypeCounts {
+    pub scanned: usize,
+    pub indexed: usize,
+    pub failed: usize,
+}
+
+/// Number of scanned files with an extension
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ExtensionCount {
+    pub extension: String,
+    pub count: usize,
+}
+
+/// Seconds spent in each phase.
+///
+/// Pipeline stages add up the time of their concurrent tasks, so they can
+/// exceed the elapsed time of the scan.
+#[derive(Serialize, Deserialize, Debug, Default, Clone)]
+pub struct PhaseSeconds {
+    pub walk: f64,
+    pub extract: f64,
+    pub summarize: f64,
+    pub embed: f64,
+    pub upload: f64,
+}
+
+/// Everything a scan did, for people or for CI to archive
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ScanReport {
+    pub root: String,
+    /// Whether the scan was stopped before every file was processed
+    pub interrupted: bool,
+    pub scanned_files: usize,
+    pub scanned_folders: usize,
+    pub indexed_files: usize,
+    pub indexed_points: usize,
+    pub unchanged_files: usize,
+    pub refreshed_files: usize,
+    pub moved_files: usize,
+    pub resumed_files: usize,
+    pub retried_files: usize,
+    pub failed_files: usize,
+    pub files_by_type: BTreeMap<String, TypeCounts>,
+    /// Extensions of the scanned files, most common first
+    pub extensions: Vec<ExtensionCount>,
+    pub failures: Vec<FailedFile>,
+    pub tokens: TokenUsage,
+    pub phase_seconds: PhaseSeconds,
+    pub elapsed_seconds: f64,
+    /// Files dropped from the index because they no longer exist
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub pruned: Option<PruneSummary>,
+}
+
+impl ScanReport {
+    pub fn new(
+        root: &Path,
+        scan_result: &DirScanResult,
+        stats: &ScanStats,
+        tokens: TokenUsage,
+        pruned: Option<PruneSummary>,
+        elapsed: Duration,
+    ) -> Self {
+        // Break the files down by type, failures are only known by path
+        let mut files_by_type: BTreeMap<String, TypeCounts> = BTreeMap::new();
+        let mut types_by_path = HashMap::new();
+        for file in &scan_result.file_metadata_list {
+            let filetype = file.filetype.as_str();
+            types_by_path.insert(file.path.as_str(), filetype);
+            files_by_type
+                .entry(filetype.to_string())
+                .or_default()
+                .scanned += 1;
+        }
+        for (filetype, count) in &stats.indexed_by_type {
+            files_by_type.entry(filetype.clone()).or_default().indexed += count;
+        }
+        for failure in &stats.failed {
+            if let Some(filetype) = types_by_path.get(failure.path.as_str()) {
+                files_by_type
+                    .entry(filetype.to_string())
+                    .or_default()
+                    .failed += 1;
+            }
+        }
+
+        ScanReport {
+            root: root.display().to_string(),
+            interrupted: stats.interrupted,
+            scanned_files: scan_result.file_metadata_list.len(),
+            scanned_folders: scan_result.folder_list.len(),
+            indexed_files: stats.indexed_files,
+            indexed_points: stats.indexed_points,
+            unchanged_files: stats.unchanged_files,
+            refreshed_files: stats.refreshed_files,
+            moved_files: stats.moved_files,
+            resumed_files: stats.resumed_files,
+            retried_files: stats.retried_files,
+            failed_files: stats.failed.len(),
+            files_by_type,
+            extensions: scan_result
+                .extension_count
+                .iter()
+                .map(|(extension, count)| ExtensionCount {
+                    extension: extension.clone(),
+                    count: *count,
+                })
+                .collect(),
+            failures: stats.failed.clone(),
+            tokens,
+            phase_seconds: PhaseSeconds {
+                walk: scan_result.elapsed_time.as_secs_f64(),
+                extract: stats.phase_times.extract.as_secs_f64(),
+                summarize: stats.phase_times.summarize.as_secs_f64(),
+                embed: stats.phase_times.embed.as_secs_f64(),
+                upload: stats.phase_times.upload.as_secs_f64(),
+            },
+            elapsed_seconds: elapsed.as_secs_f64(),
+            pruned,
+        }
+    }
+
+    /// Print the report to stdout
+    pub fn print(&self, format: ReportFormat) -> Result<()> {
+        match format {
+            ReportFormat::Text => print!("{}", self),
+            ReportFormat::Json => println!("{}", serde_json::to_string_pretty(self)?),
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for ScanReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let status = if self.interrupted {
+            " (interrupted)"
+        } else {
+            ""
+        };
+        writeln!(f, "Scan of {}{}", self.root, status)?;
+        writeln!(f)?;
+
+        writeln!(
+            f,
+            "Files    {} scanned in {} folders",
+            self.scanned_files, self.scanned_folders
+        )?;
+        writeln!(
+            f,
+            "         {} indexed ({} points), {} unchanged, {} refreshed, {} moved, {} resumed, {} retried, {} failed",
+            self.indexed_files,
+            self.indexed_points,
+            self.unchanged_files,
+            self.refreshed_files,
+            self.moved_files,
+            self.resumed_files,
+            self.retried_files,
+            self.failed_files
+        )?;
+        if let Some(pruned) = &self.pruned {
+            writeln!(
+                f,
+                "Pruned   {} deleted files ({} points), relocated {} moved files",
+                pruned.deleted_files, pruned.deleted_points, pruned.relocated_files
+            )?;
+        }
+        writeln!(
+            f,
+            "Tokens   {} prompt, {} completion",
+            self.tokens.prompt_tokens, self.tokens.completion_tokens
+        )?;
+        let phases = &self.phase_seconds;
+        writeln!(
+            f,
+            "Time     {:.1}s total: walk {:.1}s, extract {:.1}s, summarize {:.1}s, embed {:.1}s, upload {:.1}s",
+            self.elapsed_seconds,
+            phases.walk,
+            phases.extract,
+            phases.summarize,
+            phases.embed,
+            phases.upload
+        )?;
+
+        if !self.files_by_type.is_empty() {
+            writeln!(f)?;
+            writeln!(
+                f,
+                "{:<10} {:>8} {:>8} {:>8}",
+                "TYPE", "SCANNED", "INDEXED", "FAILED"
+            )?;
+            for (filetype, counts) in &self.files_by_type {
+                writeln!(
+                    f,
+                    "{:<10} {:>8} {:>8} {:>8}",
+                    filetype, counts.scanned, counts.indexed, counts.failed
+                )?;
+            }
+        }
+
+        if !self.extensions.is_empty() {
+            writeln!(f)?;
+            writeln!(f, "Extensions")?;
+            // Sorted most common first, so the first count sets the scale
+            let max_count = self.extensions[0].count.max(1);
+            for extension in self.extensions.iter().take(TEXT_EXTENSIONS) {
+                let width = (extension.count * HISTOGRAM_WIDTH).div_ceil(max_count);
+                writeln!(
+                    f,
+                    "  .{:<10} {:>7}  {}",
+                    extension.extension,
+                    extension.count,
+                    "#".repeat(width)
+                )?;
+            }
+            if self.extensions.len() > TEXT_EXTENSIONS {
+                writeln!(
+                    f,
+                    "  ... and {} more",
+                    self.extensions.len() - TEXT_EXTENSIONS
+                )?;
+            }
+        }
+
+        if !self.failures.is_empty() {
+            writeln!(f)?;
+            writeln!(f, "Failures")?;
+            for failure in &self.failures {
+                writeln!(f, "  {}: {}", failure.path, failure.reason)?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::file_management::{FileMetadata, FileType};
+    use serde_json::json;
+    use std::time::SystemTime;
+
+    fn file(path: &str, size: u64) -> FileMetadata {
+        FileMetadata {
+            name: path.rsplit('/').next().unwrap().to_string(),
+            filetype: FileType::from_path(path),
+            path: path.to_string(),
+            size,
+            created_at: SystemTime::UNIX_EPOCH,
+            modified_at: SystemTime::UNIX_EPOCH,
+            content_hash: String::new(),
+            summary: String::new(),
+        }
+    }
+
+    fn report() -> ScanReport {
+        let files = vec![
+            file("/docs/a.txt", 10),
+            file("/docs/b.txt", 20),
+            file("/docs/photo.jpg", 30),
+        ];
+        let scan_result = DirScanResult {
+            file_count: files.len(),
+            folder_count: 1,
+            extension_count: vec![("txt".to_string(), 2), ("jpg".to_string(), 1)],
+            elapsed_time: Duration::from_millis(1500),
+            file_list: files.iter().map(|file| file.path.clone()).collect(),
+            folder_list: vec!["/docs".to_string()],
+            file_metadata_list: files,
+            folder_metadata_list: Vec::new(),
+        };
+        let stats = ScanStats {
+            indexed_files: 1,
+            indexed_points: 4,
+            indexed_by_type: BTreeMap::from([("document".to_string(), 1)]),
+            unchanged_files: 1,
+            failed: vec![FailedFile {
+                path: "/docs/photo.jpg".to_string(),
+                reason: "no vision model".to_string(),
+            }],
+            ..ScanStats::default()
+        };
+        let pruned = PruneSummary {
+            deleted_files: 2,
+            deleted_points: 5,
+            relocated_files: 0,
+        };
+        ScanReport::new(
+            Path::new("/docs"),
+            &scan_result,
+            &stats,
+            TokenUsage {
+                prompt_tokens: 120,
+                completion_tokens: 30,
+            },
+            Some(pruned),
+            Duration::from_secs(3),
+        )
+    }
+
+    #[test]
+    fn files_are_counted_by_type() {
+        let report = report();
+        assert_eq!(report.scanned_files, 3);
+        assert_eq!(report.failed_files, 1);
+        let counts: Vec<(&str, usize, usize, usize)> = report
+            .files_by_type
+            .iter()
+            .map(|(filetype, counts)| {
+                (
+                    filetype.as_str(),
+                    counts.scanned,
+                    counts.indexed,
+                    counts.failed,
+                )
+            })
+            .collect();
+        assert_eq!(counts, [("document", 2, 1, 0), ("image", 1, 0, 1)]);
+    }
+
+    #[test]
+    fn json_report_keeps_its_field_names() {
+        let value = serde_json::to_value(report()).unwrap();
+        assert_eq!(value["root"], "/docs");
+        assert_eq!(value["interrupted"], false);
+        assert_eq!(value["scanned_files"], 3);
+        assert_eq!(value["scanned_folders"], 1);
+        assert_eq!(value["indexed_files"], 1);
+        assert_eq!(value["indexed_points"], 4);
+        assert_eq!(value["unchanged_files"], 1);
+        assert_eq!(value["failed_files"], 1);
+        assert_eq!(
+            value["files_by_type"]["image"],
+            json!({ "scanned": 1, "indexed": 0, "failed": 1 })
+        );
+        assert_eq!(
+            value["extensions"],
+            json!([{ "extension": "txt", "count": 2 }, { "extension": "jpg", "count": 1 }])
+        );
+        assert_eq!(
+            value["failures"],
+            json!([{ "path": "/docs/photo.jpg", "reason": "no vision model" }])
+        );
+        assert_eq!(
+            value["tokens"],
+            json!({ "prompt_tokens": 120, "completion_tokens": 30 })
+        );
+        assert_eq!(value["phase_seconds"]["walk"], 1.5);
+        assert_eq!(value["elapsed_seconds"], 3.0);
+        assert_eq!(
+            value["pruned"],
+            json!({ "deleted_files": 2, "deleted_points": 5, "relocated_files": 0 })
+        );
+
+        let mut unpruned = report();
+        unpruned.pruned = None;
+        let value = serde_json::to_value(unpruned).unwrap();
+        assert!(value.get("pruned").is_none());
+    }
+
+    #[test]
+    fn text_report_lists_types_and_failures() {
+        let text = report().to_string();
+        assert!(text.starts_with("Scan of /docs\n"));
+        assert!(text.contains("Pruned   2 deleted files (5 points)"));
+        assert!(text.contains("image             1        0        1"));
+        assert!(text.contains("  /docs/photo.jpg: no vision model"));
+    }
+}
@@ -0,0 +1,51 @@
+//! Record of the directories scanned into each collection.
+//!
+//! Kept as a JSON file per collection in the data directory and updated after
+//! every completed scan, so tools can tell which parts of the disk are indexed.
+
+use crate::config::data_dir;
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// A directory that has been scanned into the collection
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct IndexedRoot {
+    pub path: String,
+    /// Unix timestamp in seconds of the last completed scan
+    pub scanned_at: i64,
+    /// Files found by the last completed scan
+    pub files: usize,
+}
+
+fn roots_path(collection_name: &str) -> PathBuf {
+    data_dir()
+        .join("roots")
+        .join(format!("{}.json", collection_name))
+}
+
+/// Directories scanned into the collection, by path
+pub fn load(collection_name: &str) -> Result<Vec<IndexedRoot>> {
+    let path = roots_path(collection_name);
+    if !path.exists() {
+        return Ok(Vec::new());
+    }
+    let contents = fs::read_to_string(&path)
+        .with_context(|| format!("Failed to read indexed roots: {}", path.display()))?;
+    serde_json::from_str(&contents)
+        .with_context(|| format!("Invalid indexed roots: {}", path.display()))
+}
+
+/// Add or update the record of a scanned directory
+pub fn record(collection_name: &str, root: IndexedRoot) -> Result<()> {
+    let mut roots = load(collection_name)?;
+    roots.retain(|known| known.path != root.path);
+    roots.push(root);
+    roots.sort_by(|a, b| a.path.cmp(&b.path));
+
+    let path = roots_path(collection_name);
+    fs::create_dir_all(path.parent().unwrap_or(Path::new(".")))?;
+    fs::write(&path, serde_json::to_vec_pretty(&roots)?)
+        .with_context(|| format!("Failed to write indexed roots: {}", path.display()))
+}
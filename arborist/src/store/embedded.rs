@@ -0,0 +1,665 @@
+use super::{
+    reciprocal_rank_fusion, weighted_fusion, CollectionMetadata, CollectionSchema, Filter, Fusion,
+    HybridQuery, IndexPoint, Payload, PayloadFieldType, ScoredPoint, SparseVector, StoredPoint,
+    VectorStore,
+};
+use anyhow::{anyhow, Context, Result};
+use async_trait::async_trait;
+use log::error;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::fs::{self, File, OpenOptions, TryLockError};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, Instant};
+
+#[derive(Serialize, Deserialize, Debug, Default)]
+struct EmbeddedCollection {
+    schema: Option<CollectionSchema>,
+    #[serde(default)]
+    metadata: Option<CollectionMetadata>,
+    points: BTreeMap<String, IndexPoint>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Default)]
+struct EmbeddedData {
+    collections: HashMap<String, EmbeddedCollection>,
+}
+
+/// Vector store kept in memory and persisted to a single JSON file.
+///
+/// Queries are exact brute-force scans, which is fine for the personal-sized
+/// indexes this backend is meant for. Changes to points are written at most
+/// every `PERSIST_INTERVAL` and on `flush`, changes to collections right away.
+///
+/// One process at a time has the file open, the others would overwrite its
+/// points with their own copy.
+pub struct EmbeddedStore {
+    path: PathBuf,
+    /// Exclusive lock on the `.lock` file next to the index, held while the store is open
+    _lock: File,
+    data: Arc<RwLock<EmbeddedData>>,
+    /// Number of changes made so far
+    changes: Arc<AtomicU64>,
+    /// Number of changes on disk and when they were written
+    written: tokio::sync::Mutex<(u64, Instant)>,
+}
+
+/// Longest a change to points stays in memory only, unless the store is flushed
+const PERSIST_INTERVAL: Duration = Duration::from_secs(2);
+
+impl EmbeddedStore {
+    /// Open the store at `path`, starting empty if the file does not exist yet.
+    ///
+    /// Fails while another process has the same index open.
+    pub fn open(path: PathBuf) -> Result<Self> {
+        let lock = lock_index(&path)?;
+        let data = if path.exists() {
+            let contents = fs::read_to_string(&path)
+                .with_context(|| format!("Failed to read index file: {}", path.display()))?;
+            serde_json::from_str(&contents)
+                .with_context(|| format!("Failed to parse index file: {}", path.display()))?
+        } else {
+            EmbeddedData::default()
+        };
+
+        Ok(Self {
+            path,
+            _lock: lock,
+            data: Arc::new(RwLock::new(data)),
+            changes: Arc::default(),
+            written: tokio::sync::Mutex::new((0, Instant::now())),
+        })
+    }
+
+    /// Write the store to disk if it changed since the last write
+    async fn persist(&self) -> Result<()> {
+        let mut written = self.written.lock().await;
+        if written.0 == self.changes.load(Ordering::SeqCst) {
+            return Ok(());
+        }
+
+        // Serializing the whole store takes a while, keep it off the runtime
+        let (path, data, changes) = (self.path.clone(), self.data.clone(), self.changes.clone());
+        let persisted =
+            tokio::task::spawn_blocking(move || write_snapshot(&path, &data, &changes)).await??;
+        *written = (persisted, Instant::now());
+        Ok(())
+    }
+
+    /// Persist changes to points once the last write is old enough
+    async fn persist_if_due(&self) -> Result<()> {
+        if self.written.lock().await.1.elapsed() < PERSIST_INTERVAL {
+            return Ok(());
+        }
+        self.persist().await
+    }
+
+    /// Apply a change to one collection in memory, failing when it does not exist
+    fn change<F>(&self, collection_name: &str, change: F) -> Result<()>
+    where
+        F: FnOnce(&mut EmbeddedCollection),
+    {
+        let mut data = self
+            .data
+            .write()
+            .map_err(|_| anyhow!("Embedded store lock poisoned"))?;
+        let collection = data
+            .collections
+            .get_mut(collection_name)
+            .ok_or_else(|| anyhow!("Collection '{}' does not exist", collection_name))?;
+        change(collection);
+        self.changes.fetch_add(1, Ordering::SeqCst);
+        Ok(())
+    }
+
+    /// Apply a change to the points of one collection, persisted when due
+    async fn update<F>(&self, collection_name: &str, update: F) -> Result<()>
+    where
+        F: FnOnce(&mut EmbeddedCollection),
+    {
+        self.change(collection_name, update)?;
+        self.persist_if_due().await
+    }
+
+    /// Run a read-only closure against one collection
+    fn read<T, F>(&self, collection_name: &str, read: F) -> Result<T>
+    where
+        F: FnOnce(&EmbeddedCollection) -> T,
+    {
+        let data = self
+            .data
+            .read()
+            .map_err(|_| anyhow!("Embedded store lock poisoned"))?;
+        let collection = data
+            .collections
+            .get(collection_name)
+            .ok_or_else(|| anyhow!("Collection '{}' does not exist", collection_name))?;
+        Ok(read(collection))
+    }
+}
+
+impl Drop for EmbeddedStore {
+    fn drop(&mut self) {
+        // Changes not flushed yet are written on the way out
+        if self.written.get_mut().0 != self.changes.load(Ordering::SeqCst) {
+            if let Err(e) = write_snapshot(&self.path, &self.data, &self.changes) {
+                error!("{:#}", e);
+            }
+        }
+    }
+}
+
+/// Take the lock of an index file without waiting for it
+fn lock_index(path: &Path) -> Result<File> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    let lock_path = path.with_extension("lock");
+    let lock = OpenOptions::new()
+        .create(true)
+        .truncate(false)
+        .write(true)
+        .open(&lock_path)
+        .with_context(|| format!("Failed to open index lock: {}", lock_path.display()))?;
+    match lock.try_lock() {
+        Ok(()) => Ok(lock),
+        Err(TryLockError::WouldBlock) => Err(anyhow!(
+            "{} is in use by another arborist process, such as `arborist serve`. \
+             While a daemon runs, only the commands it answers can use the index",
+            path.display()
+        )),
+        Err(TryLockError::Error(e)) => {
+            Err(e).with_context(|| format!("Failed to lock index file: {}", path.display()))
+        }
+    }
+}
+
+/// Write the whole store to disk, going through a temporary file so a crash
+/// never leaves a truncated index behind. Returns the number of changes written.
+fn write_snapshot(path: &Path, data: &RwLock<EmbeddedData>, changes: &AtomicU64) -> Result<u64> {
+    let (contents, persisted) = {
+        let data = data
+            .read()
+            .map_err(|_| anyhow!("Embedded store lock poisoned"))?;
+        // Changes are counted under the write lock, so this count matches the snapshot
+        (serde_json::to_vec(&*data)?, changes.load(Ordering::SeqCst))
+    };
+
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    let tmp_path = path.with_extension("json.tmp");
+    fs::write(&tmp_path, contents)
+        .with_context(|| format!("Failed to write index file: {}", tmp_path.display()))?;
+    fs::rename(&tmp_path, path)
+        .with_context(|| format!("Failed to replace index file: {}", path.display()))?;
+    Ok(persisted)
+}
+
+fn cosine_similarity(a: &[f32], b: &[f32]) -> f32 {
+    let dot: f32 = a.iter().zip(b).map(|(x, y)| x * y).sum();
+    let norm_a: f32 = a.iter().map(|x| x * x).sum::<f32>().sqrt();
+    let norm_b: f32 = b.iter().map(|x| x * x).sum::<f32>().sqrt();
+    if norm_a == 0.0 || norm_b == 0.0 {
+        return 0.0;
+    }
+    dot / (norm_a * norm_b)
+}
+
+fn sparse_dot_product(a: &SparseVector, b: &SparseVector) -> f32 {
+    let weights: HashMap<u32, f32> = a
+        .indices
+        .iter()
+        .copied()
+        .zip(a.values.iter().copied())
+        .collect();
+    b.indices
+        .iter()
+        .zip(&b.values)
+        .filter_map(|(index, value)| weights.get(index).map(|weight| weight * value))
+        .sum()
+}
+
+/// Score every point matching the filter and keep the best `limit`
+fn rank_points<F>(
+    collection: &EmbeddedCollection,
+    filter: &Filter,
+    limit: usize,
+    score: F,
+) -> Vec<ScoredPoint>
+where
+    F: Fn(&IndexPoint) -> Option<f32>,
+{
+    let mut scored: Vec<ScoredPoint> = collection
+        .points
+        .values()
+        .filter(|point| filter.matches(&point.payload))
+        .filter_map(|point| {
+            score(point).map(|score| ScoredPoint {
+                id: point.id.clone(),
+                score,
+                payload: point.payload.clone(),
+            })
+        })
+        .collect();
+    scored.sort_by(|a, b| b.score.total_cmp(&a.score));
+    scored.truncate(limit);
+    scored
+}
+
+#[async_trait]
+impl VectorStore for EmbeddedStore {
+    fn location(&self) -> String {
+        format!("embedded {}", self.path.display())
+    }
+
+    async fn create_collection(
+        &self,
+        collection_name: &str,
+        schema: &CollectionSchema,
+    ) -> Result<bool> {
+        {
+            let mut data = self
+                .data
+                .write()
+                .map_err(|_| anyhow!("Embedded store lock poisoned"))?;
+            if data.collections.contains_key(collection_name) {
+                return Ok(false);
+            }
+            data.collections.insert(
+                collection_name.to_string(),
+                EmbeddedCollection {
+                    schema: Some(schema.clone()),
+                    metadata: None,
+                    points: BTreeMap::new(),
+                },
+            );
+            self.changes.fetch_add(1, Ordering::SeqCst);
+        }
+        self.persist().await?;
+        Ok(true)
+    }
+
+    async fn delete_collection(&self, collection_name: &str) -> Result<()> {
+        {
+            let mut data = self
+                .data
+                .write()
+                .map_err(|_| anyhow!("Embedded store lock poisoned"))?;
+            data.collections.remove(collection_name);
+            self.changes.fetch_add(1, Ordering::SeqCst);
+        }
+        self.persist().await
+    }
+
+    async fn flush(&self) -> Result<()> {
+        self.persist().await
+    }
+
+    async fn collection_exists(&self, collection_name: &str) -> Result<bool> {
+        let data = self
+            .data
+            .read()
+            .map_err(|_| anyhow!("Embedded store lock poisoned"))?;
+        Ok(data.collections.contains_key(collection_name))
+    }
+
+    async fn collection_metadata(
+        &self,
+        collection_name: &str,
+    ) -> Result<Option<CollectionMetadata>> {
+        self.read(collection_name, |collection| collection.metadata.clone())
+    }
+
+    async fn set_collection_metadata(
+        &self,
+        collection_name: &str,
+        metadata: &CollectionMetadata,
+    ) -> Result<()> {
+        self.change(collection_name, |collection| {
+            collection.metadata = Some(metadata.clone());
+        })?;
+        self.persist().await
+    }
+
+    async fn create_payload_index(
+        &self,
+        _collection_name: &str,
+        _field_name: &str,
+        _field_type: PayloadFieldType,
+    ) -> Result<()> {
+        // Filters are evaluated during the brute-force scan, there is nothing to index
+        Ok(())
+    }
+
+    async fn upsert(&self, collection_name: &str, points: Vec<IndexPoint>) -> Result<()> {
+        self.update(collection_name, |collection| {
+            for point in points {
+                collection.points.insert(point.id.clone(), point);
+            }
+        })
+        .await
+    }
+
+    async fn delete(&self, collection_name: &str, ids: Vec<String>) -> Result<()> {
+        self.update(collection_name, |collection| {
+            for id in ids {
+                collection.points.remove(&id);
+            }
+        })
+        .await
+    }
+
+    async fn set_payload(
+        &self,
+        collection_name: &str,
+        ids: Vec<String>,
+        payload: Payload,
+    ) -> Result<()> {
+        self.update(collection_name, |collection| {
+            for id in ids {
+                if let Some(point) = collection.points.get_mut(&id) {
+                    point
+                        .payload
+                        .extend(payload.iter().map(|(k, v)| (k.clone(), v.clone())));
+                }
+            }
+        })
+        .await
+    }
+
+    async fn query_filter(
+        &self,
+        collection_name: &str,
+        filter: &Filter,
+        limit: Option<usize>,
+    ) -> Result<Vec<StoredPoint>> {
+        self.read(collection_name, |collection| {
+            collection
+                .points
+                .values()
+                .filter(|point| filter.matches(&point.payload))
+                .take(limit.unwrap_or(usize::MAX))
+                .map(|point| StoredPoint {
+                    id: point.id.clone(),
+                    payload: point.payload.clone(),
+                })
+                .collect()
+        })
+    }
+
+    async fn query_vectors(
+        &self,
+        collection_name: &str,
+        filter: &Filter,
+        limit: Option<usize>,
+    ) -> Result<Vec<IndexPoint>> {
+        self.read(collection_name, |collection| {
+            collection
+                .points
+                .values()
+                .filter(|point| filter.matches(&point.payload))
+                .take(limit.unwrap_or(usize::MAX))
+                .cloned()
+                .collect()
+        })
+    }
+
+    async fn scroll_vectors(
+        &self,
+        collection_name: &str,
+        offset: Option<String>,
+        limit: usize,
+    ) -> Result<(Vec<IndexPoint>, Option<String>)> {
+        self.read(collection_name, |collection| {
+            let mut points: Vec<IndexPoint> = match offset {
+                Some(offset) => collection.points.range(offset..),
+                None => collection.points.range::<String, _>(..),
+            }
+            .take(limit.saturating_add(1))
+            .map(|(_, point)| point.clone())
+            .collect();
+            // The point after the page is where the next one starts
+            let next = if points.len() > limit {
+                points.pop().map(|point| point.id)
+            } else {
+                None
+            };
+            (points, next)
+        })
+    }
+
+    async fn query_hybrid(
+        &self,
+        collection_name: &str,
+        query: &HybridQuery,
+    ) -> Result<Vec<ScoredPoint>> {
+        // Each vector only needs the final limit when there is nothing to fuse
+        let candidates = if query.dense.is_some() && query.sparse.is_some() {
+            query.prefetch_limit()
+        } else {
+            query.limit
+        };
+
+        self.read(collection_name, |collection| {
+            let dense_results = query.dense.as_ref().map(|(name, vector)| {
+                rank_points(collection, &query.filter, candidates, |point| {
+                    point
+                        .dense
+                        .get(name)
+                        .map(|stored| cosine_similarity(stored, vector))
+                })
+            });
+            let sparse_results = query.sparse.as_ref().map(|(name, vector)| {
+                rank_points(collection, &query.filter, candidates, |point| {
+                    point
+                        .sparse
+                        .get(name)
+                        .map(|stored| sparse_dot_product(stored, vector))
+                })
+            });
+
+            match (dense_results, sparse_results) {
+                (Some(dense), Some(sparse)) => match query.fusion {
+                    Fusion::Rrf => reciprocal_rank_fusion(vec![dense, sparse], query.limit),
+                    Fusion::Weighted {
+                        dense: dense_weight,
+                        sparse: sparse_weight,
+                    } => weighted_fusion(dense, sparse, dense_weight, sparse_weight, query.limit),
+                },
+                (Some(results), None) | (None, Some(results)) => results,
+                (None, None) => Vec::new(),
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::store::Condition;
+    use serde_json::json;
+
+    const COLLECTION: &str = "files";
+
+    fn index_path(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!(
+            "arborist-embedded-{}-{}.json",
+            std::process::id(),
+            name
+        ))
+    }
+
+    fn schema() -> CollectionSchema {
+        CollectionSchema {
+            dense_vector: "dense".to_string(),
+            dimension: 2,
+            sparse_vector: "sparse".to_string(),
+        }
+    }
+
+    fn point(id: &str, dense: [f32; 2], filetype: &str) -> IndexPoint {
+        IndexPoint {
+            id: id.to_string(),
+            dense: HashMap::from([("dense".to_string(), dense.to_vec())]),
+            sparse: HashMap::from([(
+                "sparse".to_string(),
+                SparseVector {
+                    indices: vec![1, 7],
+                    values: vec![0.5, 1.5],
+                },
+            )]),
+            payload: json!({ "file_path": format!("/{}.txt", id), "filetype": filetype })
+                .as_object()
+                .unwrap()
+                .clone(),
+        }
+    }
+
+    async fn ids(store: &EmbeddedStore) -> Vec<String> {
+        let points = store
+            .query_filter(COLLECTION, &Filter::default(), None)
+            .await
+            .unwrap();
+        points.into_iter().map(|point| point.id).collect()
+    }
+
+    #[tokio::test]
+    async fn changes_survive_a_reopen() -> Result<()> {
+        let path = index_path("reopen");
+        let metadata = CollectionMetadata {
+            dense_model: "dense-model".to_string(),
+            sparse_model: "sparse-model".to_string(),
+            dimension: 2,
+        };
+        {
+            let store = EmbeddedStore::open(path.clone())?;
+            assert!(store.create_collection(COLLECTION, &schema()).await?);
+            assert!(!store.create_collection(COLLECTION, &schema()).await?);
+            store.set_collection_metadata(COLLECTION, &metadata).await?;
+            let points = vec![
+                point("a", [1.0, 0.0], "document"),
+                point("b", [0.0, 1.0], "image"),
+                point("c", [1.0, 1.0], "document"),
+            ];
+            store.upsert(COLLECTION, points).await?;
+            store.delete(COLLECTION, vec!["b".to_string()]).await?;
+            let summary = json!({ "summary": "updated" }).as_object().unwrap().clone();
+            store
+                .set_payload(COLLECTION, vec!["a".to_string()], summary)
+                .await?;
+            store.flush().await?;
+        }
+
+        let store = EmbeddedStore::open(path.clone())?;
+        assert_eq!(store.collection_metadata(COLLECTION).await?, Some(metadata));
+        assert_eq!(ids(&store).await, ["a", "c"]);
+        let points = store
+            .query_vectors(
+                COLLECTION,
+                &Filter::must([Condition::keyword("file_path", "/a.txt")]),
+                None,
+            )
+            .await?;
+        assert_eq!(points[0].payload["summary"], "updated");
+        assert_eq!(points[0].payload["filetype"], "document");
+        assert_eq!(points[0].sparse["sparse"].indices, [1, 7]);
+
+        fs::remove_file(path)?;
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn unflushed_changes_are_written_on_drop() -> Result<()> {
+        let path = index_path("drop");
+        {
+            let store = EmbeddedStore::open(path.clone())?;
+            store.create_collection(COLLECTION, &schema()).await?;
+            store
+                .upsert(COLLECTION, vec![point("a", [1.0, 0.0], "document")])
+                .await?;
+        }
+
+        let store = EmbeddedStore::open(path.clone())?;
+        assert_eq!(ids(&store).await, ["a"]);
+
+        fs::remove_file(path)?;
+        Ok(())
+    }
+
+    #[test]
+    fn a_second_open_of_the_same_index_is_refused() -> Result<()> {
+        let path = index_path("locked");
+        let store = EmbeddedStore::open(path.clone())?;
+        let error = EmbeddedStore::open(path.clone()).err().unwrap();
+        assert!(error.to_string().contains("in use"), "{:#}", error);
+
+        drop(store);
+        EmbeddedStore::open(path)?;
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn scrolling_pages_through_every_point() -> Result<()> {
+        let path = index_path("scroll");
+        let store = EmbeddedStore::open(path.clone())?;
+        store.create_collection(COLLECTION, &schema()).await?;
+        let points = ["e", "a", "d", "b", "c"]
+            .into_iter()
+            .map(|id| point(id, [1.0, 0.0], "document"))
+            .collect();
+        store.upsert(COLLECTION, points).await?;
+
+        let mut pages = Vec::new();
+        let mut offset = None;
+        loop {
+            let (page, next) = store.scroll_vectors(COLLECTION, offset, 2).await?;
+            pages.push(page.into_iter().map(|point| point.id).collect::<Vec<_>>());
+            match next {
+                Some(next) => offset = Some(next),
+                None => break,
+            }
+        }
+        assert_eq!(pages, [vec!["a", "b"], vec!["c", "d"], vec!["e"]]);
+
+        drop(store);
+        fs::remove_file(path)?;
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn hybrid_queries_rank_the_filtered_points() -> Result<()> {
+        let path = index_path("query");
+        let store = EmbeddedStore::open(path.clone())?;
+        store.create_collection(COLLECTION, &schema()).await?;
+        let points = vec![
+            point("a", [1.0, 0.0], "document"),
+            point("b", [0.9, 0.1], "image"),
+            point("c", [0.5, 0.5], "document"),
+            point("d", [0.0, 1.0], "document"),
+        ];
+        store.upsert(COLLECTION, points).await?;
+
+        let query = HybridQuery {
+            dense: Some(("dense".to_string(), vec![1.0, 0.0])),
+            sparse: None,
+            filter: Filter::must([Condition::keyword("filetype", "document")]),
+            limit: 2,
+            fusion: Fusion::Rrf,
+        };
+        let found = store.query_hybrid(COLLECTION, &query).await?;
+        let found: Vec<&str> = found.iter().map(|point| point.id.as_str()).collect();
+        assert_eq!(found, ["a", "c"]);
+
+        assert!(store
+            .query_filter("missing", &Filter::default(), None)
+            .await
+            .is_err());
+
+        drop(store);
+        fs::remove_file(path)?;
+        Ok(())
+    }
+}
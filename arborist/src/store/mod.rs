@@ -0,0 +1,478 @@
+//! Storage backends for file vectors and their payloads.
+//!
+//! [`VectorStore`] is the interface the rest of arborist talks to. The Qdrant
+//! backend needs a running server, the embedded backend keeps everything in a
+//! single file under the arborist data directory.
+
+mod embedded;
+mod qdrant;
+
+pub use embedded::EmbeddedStore;
+pub use qdrant::QdrantStore;
+
+use crate::config::{Config, StoreBackend};
+use anyhow::Result;
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::sync::Arc;
+
+/// Payload attached to every point, keyed by field name
+pub type Payload = serde_json::Map<String, serde_json::Value>;
+
+/// Sparse vector as produced by SPLADE style models
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct SparseVector {
+    pub indices: Vec<u32>,
+    pub values: Vec<f32>,
+}
+
+/// Named vectors a collection is created with
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct CollectionSchema {
+    pub dense_vector: String,
+    pub dimension: usize,
+    pub sparse_vector: String,
+}
+
+/// Records which models produced the vectors of a collection
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct CollectionMetadata {
+    pub dense_model: String,
+    pub sparse_model: String,
+    pub dimension: usize,
+}
+
+/// A point to be written to a collection
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct IndexPoint {
+    pub id: String,
+    pub dense: HashMap<String, Vec<f32>>,
+    pub sparse: HashMap<String, SparseVector>,
+    pub payload: Payload,
+}
+
+/// A point returned by a filter query
+#[derive(Debug, Clone)]
+pub struct StoredPoint {
+    pub id: String,
+    pub payload: Payload,
+}
+
+/// A point returned by a similarity query
+#[derive(Debug, Clone)]
+pub struct ScoredPoint {
+    pub id: String,
+    pub score: f32,
+    pub payload: Payload,
+}
+
+/// A single payload condition
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub enum Condition {
+    /// The field equals the keyword, or contains it if the field is an array
+    Keyword { key: String, value: String },
+    /// The field equals, or contains, any of the keywords
+    AnyKeyword { key: String, values: Vec<String> },
+    /// The numeric field lies within the bounds, both inclusive
+    Range {
+        key: String,
+        gte: Option<f64>,
+        lte: Option<f64>,
+    },
+}
+
+impl Condition {
+    pub fn keyword(key: impl Into<String>, value: impl Into<String>) -> Self {
+        Condition::Keyword {
+            key: key.into(),
+            value: value.into(),
+        }
+    }
+
+    pub fn any_keyword(key: impl Into<String>, values: Vec<String>) -> Self {
+        Condition::AnyKeyword {
+            key: key.into(),
+            values,
+        }
+    }
+
+    pub fn range(key: impl Into<String>, gte: Option<f64>, lte: Option<f64>) -> Self {
+        Condition::Range {
+            key: key.into(),
+            gte,
+            lte,
+        }
+    }
+
+    /// Evaluate the condition against a payload, used by backends without native filtering
+    pub fn matches(&self, payload: &Payload) -> bool {
+        fn keyword_matches(
+            field: Option<&serde_json::Value>,
+            accept: impl Fn(&str) -> bool,
+        ) -> bool {
+            match field {
+                Some(serde_json::Value::String(s)) => accept(s),
+                Some(serde_json::Value::Array(items)) => {
+                    items.iter().filter_map(|item| item.as_str()).any(accept)
+                }
+                _ => false,
+            }
+        }
+
+        match self {
+            Condition::Keyword { key, value } => keyword_matches(payload.get(key), |s| s == value),
+            Condition::AnyKeyword { key, values } => {
+                keyword_matches(payload.get(key), |s| values.iter().any(|value| s == value))
+            }
+            Condition::Range { key, gte, lte } => {
+                match payload.get(key).and_then(serde_json::Value::as_f64) {
+                    Some(number) => {
+                        gte.is_none_or(|gte| number >= gte) && lte.is_none_or(|lte| number <= lte)
+                    }
+                    None => false,
+                }
+            }
+        }
+    }
+}
+
+/// Conditions a point has to satisfy, an empty filter matches everything
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct Filter {
+    pub must: Vec<Condition>,
+    pub must_not: Vec<Condition>,
+}
+
+impl Filter {
+    pub fn must(conditions: impl IntoIterator<Item = Condition>) -> Self {
+        Filter {
+            must: conditions.into_iter().collect(),
+            must_not: Vec::new(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.must.is_empty() && self.must_not.is_empty()
+    }
+
+    pub fn matches(&self, payload: &Payload) -> bool {
+        self.must.iter().all(|c| c.matches(payload))
+            && !self.must_not.iter().any(|c| c.matches(payload))
+    }
+}
+
+/// Value type of an indexed payload field
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PayloadFieldType {
+    Keyword,
+    Integer,
+}
+
+/// How the dense and sparse result lists of a hybrid query are combined
+#[derive(Debug, Clone, Copy)]
+pub enum Fusion {
+    /// Reciprocal Rank Fusion, only looks at ranks
+    Rrf,
+    /// Weighted sum of min-max normalized scores
+    Weighted { dense: f32, sparse: f32 },
+}
+
+/// A similarity query over the dense vector, the sparse vector, or both.
+///
+/// When both are given the two result lists are combined according to `fusion`.
+#[derive(Debug, Clone)]
+pub struct HybridQuery {
+    pub dense: Option<(String, Vec<f32>)>,
+    pub sparse: Option<(String, SparseVector)>,
+    pub filter: Filter,
+    pub limit: usize,
+    pub fusion: Fusion,
+}
+
+impl HybridQuery {
+    /// Number of candidates fetched from each vector before fusion
+    pub fn prefetch_limit(&self) -> usize {
+        self.limit * PREFETCH_MULTIPLIER
+    }
+}
+
+/// Over-fetch factor for the per-vector candidate lists of a hybrid query
+const PREFETCH_MULTIPLIER: usize = 4;
+
+#[async_trait]
+pub trait VectorStore: Send + Sync {
+    /// Where the collections are kept, telling apart collections of the same name
+    fn location(&self) -> String;
+
+    /// Create the collection, returns `false` if it already existed
+    async fn create_collection(
+        &self,
+        collection_name: &str,
+        schema: &CollectionSchema,
+    ) -> Result<bool>;
+
+    /// Drop the collection and all of its points
+    async fn delete_collection(&self, collection_name: &str) -> Result<()>;
+
+    /// Make the changes made so far durable, for backends that write them lazily
+    async fn flush(&self) -> Result<()> {
+        Ok(())
+    }
+
+    async fn collection_exists(&self, collection_name: &str) -> Result<bool>;
+
+    /// Read the metadata recorded for the collection, if any
+    async fn collection_metadata(
+        &self,
+        collection_name: &str,
+    ) -> Result<Option<CollectionMetadata>>;
+
+    /// Record metadata for the collection, replacing what was there
+    async fn set_collection_metadata(
+        &self,
+        collection_name: &str,
+        metadata: &CollectionMetadata,
+    ) -> Result<()>;
+
+    /// Index a payload field so filters on it stay fast, does nothing if it
+    /// is indexed already or the backend filters without indexes
+    async fn create_payload_index(
+        &self,
+        collection_name: &str,
+        field_name: &str,
+        field_type: PayloadFieldType,
+    ) -> Result<()>;
+
+    /// Insert points, replacing any point with the same id
+    async fn upsert(&self, collection_name: &str, points: Vec<IndexPoint>) -> Result<()>;
+
+    /// Delete points by id
+    async fn delete(&self, collection_name: &str, ids: Vec<String>) -> Result<()>;
+
+    /// Merge the given fields into the payload of the points
+    async fn set_payload(
+        &self,
+        collection_name: &str,
+        ids: Vec<String>,
+        payload: Payload,
+    ) -> Result<()>;
+
+    /// Return the points matching the filter, all of them if `limit` is `None`
+    async fn query_filter(
+        &self,
+        collection_name: &str,
+        filter: &Filter,
+        limit: Option<usize>,
+    ) -> Result<Vec<StoredPoint>>;
+
+    /// Return the points matching the filter with their vectors
+    async fn query_vectors(
+        &self,
+        collection_name: &str,
+        filter: &Filter,
+        limit: Option<usize>,
+    ) -> Result<Vec<IndexPoint>>;
+
+    /// Return a page of up to `limit` points with their vectors, in id order
+    /// starting at `offset`, and the id the next page starts at
+    async fn scroll_vectors(
+        &self,
+        collection_name: &str,
+        offset: Option<String>,
+        limit: usize,
+    ) -> Result<(Vec<IndexPoint>, Option<String>)>;
+
+    /// Return the points closest to the query vectors, best first
+    async fn query_hybrid(
+        &self,
+        collection_name: &str,
+        query: &HybridQuery,
+    ) -> Result<Vec<ScoredPoint>>;
+}
+
+/// Open the backend selected in the configuration
+pub fn connect(config: &Config) -> Result<Arc<dyn VectorStore>> {
+    match config.store_backend {
+        StoreBackend::Qdrant => Ok(Arc::new(QdrantStore::new(&config.db_url)?)),
+        StoreBackend::Embedded => Ok(Arc::new(EmbeddedStore::open(config.embedded_path())?)),
+    }
+}
+
+/// Merge ranked result lists with Reciprocal Rank Fusion
+pub fn reciprocal_rank_fusion(lists: Vec<Vec<ScoredPoint>>, limit: usize) -> Vec<ScoredPoint> {
+    // Constant from the original RRF paper, also used by Qdrant
+    const K: f32 = 60.0;
+
+    let mut fused: HashMap<String, ScoredPoint> = HashMap::new();
+    for list in lists {
+        for (rank, point) in list.into_iter().enumerate() {
+            let score = 1.0 / (K + rank as f32 + 1.0);
+            fused
+                .entry(point.id.clone())
+                .and_modify(|existing| existing.score += score)
+                .or_insert(ScoredPoint { score, ..point });
+        }
+    }
+
+    let mut fused: Vec<ScoredPoint> = fused.into_values().collect();
+    fused.sort_by(|a, b| b.score.total_cmp(&a.score));
+    fused.truncate(limit);
+    fused
+}
+
+/// Merge a dense and a sparse result list with a weighted sum of their
+/// min-max normalized scores, so both scales contribute comparably
+pub fn weighted_fusion(
+    dense: Vec<ScoredPoint>,
+    sparse: Vec<ScoredPoint>,
+    dense_weight: f32,
+    sparse_weight: f32,
+    limit: usize,
+) -> Vec<ScoredPoint> {
+    fn normalized(list: Vec<ScoredPoint>) -> Vec<ScoredPoint> {
+        let max = list.iter().map(|p| p.score).fold(f32::MIN, f32::max);
+        let min = list.iter().map(|p| p.score).fold(f32::MAX, f32::min);
+        let range = max - min;
+        list.into_iter()
+            .map(|p| ScoredPoint {
+                score: if range > 0.0 {
+                    (p.score - min) / range
+                } else {
+                    1.0
+                },
+                ..p
+            })
+            .collect()
+    }
+
+    let mut fused: HashMap<String, ScoredPoint> = HashMap::new();
+    for (list, weight) in [
+        (normalized(dense), dense_weight),
+        (normalized(sparse), sparse_weight),
+    ] {
+        for point in list {
+            let score = point.score * weight;
+            fused
+                .entry(point.id.clone())
+                .and_modify(|existing| existing.score += score)
+                .or_insert(ScoredPoint { score, ..point });
+        }
+    }
+
+    let mut fused: Vec<ScoredPoint> = fused.into_values().collect();
+    fused.sort_by(|a, b| b.score.total_cmp(&a.score));
+    fused.truncate(limit);
+    fused
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn payload(value: serde_json::Value) -> Payload {
+        value.as_object().unwrap().clone()
+    }
+
+    fn scored(id: &str, score: f32) -> ScoredPoint {
+        ScoredPoint {
+            id: id.to_string(),
+            score,
+            payload: Payload::new(),
+        }
+    }
+
+    fn ids(points: &[ScoredPoint]) -> Vec<&str> {
+        points.iter().map(|point| point.id.as_str()).collect()
+    }
+
+    #[test]
+    fn keyword_conditions_match_strings_and_arrays() {
+        let file = payload(json!({
+            "filetype": "document",
+            "parent_dirs": ["/home", "/home/user"],
+            "file_size": 2048,
+        }));
+
+        assert!(Condition::keyword("filetype", "document").matches(&file));
+        assert!(!Condition::keyword("filetype", "image").matches(&file));
+        assert!(Condition::keyword("parent_dirs", "/home/user").matches(&file));
+        assert!(!Condition::keyword("parent_dirs", "/home/other").matches(&file));
+        // Numbers are not keywords, missing fields match nothing
+        assert!(!Condition::keyword("file_size", "2048").matches(&file));
+        assert!(!Condition::keyword("missing", "document").matches(&file));
+
+        let types = vec!["image".to_string(), "document".to_string()];
+        assert!(Condition::any_keyword("filetype", types).matches(&file));
+        let dirs = vec!["/tmp".to_string(), "/home".to_string()];
+        assert!(Condition::any_keyword("parent_dirs", dirs).matches(&file));
+        assert!(!Condition::any_keyword("filetype", Vec::new()).matches(&file));
+    }
+
+    #[test]
+    fn range_conditions_include_their_bounds() {
+        let file = payload(json!({ "file_size": 2048, "file_name": "a.txt" }));
+
+        assert!(Condition::range("file_size", Some(2048.0), Some(2048.0)).matches(&file));
+        assert!(Condition::range("file_size", Some(1000.0), None).matches(&file));
+        assert!(Condition::range("file_size", None, Some(4096.0)).matches(&file));
+        assert!(!Condition::range("file_size", Some(2049.0), None).matches(&file));
+        assert!(!Condition::range("file_size", None, Some(2047.0)).matches(&file));
+        assert!(!Condition::range("file_name", None, None).matches(&file));
+    }
+
+    #[test]
+    fn filters_need_every_must_and_no_must_not() {
+        let file = payload(json!({ "filetype": "document", "file_size": 2048 }));
+
+        assert!(Filter::default().matches(&file));
+        let mut filter = Filter::must([
+            Condition::keyword("filetype", "document"),
+            Condition::range("file_size", Some(1000.0), None),
+        ]);
+        assert!(filter.matches(&file));
+
+        filter
+            .must_not
+            .push(Condition::range("file_size", Some(2000.0), None));
+        assert!(!filter.matches(&file));
+
+        let filter = Filter {
+            must: Vec::new(),
+            must_not: vec![Condition::keyword("filetype", "image")],
+        };
+        assert!(filter.matches(&file));
+    }
+
+    #[test]
+    fn reciprocal_rank_fusion_adds_up_ranks() {
+        let dense = vec![scored("a", 0.9), scored("b", 0.5), scored("c", 0.1)];
+        let sparse = vec![scored("c", 12.0), scored("b", 3.0)];
+
+        let fused = reciprocal_rank_fusion(vec![dense.clone(), sparse.clone()], 10);
+        // Found first and third beats found second twice, scores play no part
+        assert_eq!(ids(&fused), ["c", "b", "a"]);
+        assert!((fused[2].score - 1.0 / 61.0).abs() < 1e-6);
+
+        let fused = reciprocal_rank_fusion(vec![dense, sparse], 2);
+        assert_eq!(ids(&fused), ["c", "b"]);
+    }
+
+    #[test]
+    fn weighted_fusion_normalizes_both_lists() {
+        let dense = vec![scored("a", 0.9), scored("b", 0.5), scored("c", 0.1)];
+        let sparse = vec![scored("c", 12.0), scored("b", 2.0)];
+
+        let fused = weighted_fusion(dense.clone(), sparse.clone(), 0.7, 0.3, 10);
+        assert_eq!(ids(&fused), ["a", "b", "c"]);
+        let scores: Vec<f32> = fused.iter().map(|point| point.score).collect();
+        for (score, expected) in scores.iter().zip([0.7, 0.35, 0.3]) {
+            assert!((score - expected).abs() < 1e-6, "{:?}", scores);
+        }
+
+        // The sparse scale would win if the scores were added as they are
+        let fused = weighted_fusion(dense, sparse, 0.3, 0.7, 1);
+        assert_eq!(ids(&fused), ["c"]);
+    }
+}
@@ -0,0 +1,590 @@
+use super::{
+    weighted_fusion, CollectionMetadata, CollectionSchema, Condition, Filter, Fusion, HybridQuery,
+    IndexPoint, Payload, PayloadFieldType, ScoredPoint, SparseVector, StoredPoint, VectorStore,
+};
+use anyhow::{Context, Result};
+use async_trait::async_trait;
+use log::info;
+use qdrant_client::qdrant::point_id::PointIdOptions;
+use qdrant_client::qdrant::r#match::MatchValue;
+use qdrant_client::qdrant::vectors_output::VectorsOptions;
+use qdrant_client::qdrant::{
+    self, CreateCollectionBuilder, CreateFieldIndexCollectionBuilder, DeletePointsBuilder,
+    Distance, FieldType, GetPointsBuilder, NamedVectors, PointId, PointStruct, PointsIdsList,
+    PrefetchQueryBuilder, Query, QueryPointsBuilder, RetrievedPoint, ScrollPointsBuilder,
+    SearchParamsBuilder, SetPayloadPointsBuilder, SparseVectorParamsBuilder,
+    SparseVectorsConfigBuilder, UpsertPointsBuilder, Vector, VectorInput, VectorParamsBuilder,
+    VectorsConfigBuilder, VectorsOutput,
+};
+use qdrant_client::Qdrant;
+use std::collections::HashMap;
+
+/// Page size used when scrolling through a collection
+const SCROLL_PAGE_SIZE: u32 = 256;
+
+/// Qdrant collections cannot carry arbitrary metadata, so it is kept in the
+/// payload of a reserved point without vectors. Similarity searches cannot
+/// return it, and every scroll leaves it out.
+const METADATA_POINT_ID: &str = "00000000-0000-0000-0000-000000000000";
+
+/// Payload field of the metadata point holding the serialized metadata
+const METADATA_FIELD: &str = "arborist_metadata";
+
+/// Vector store backed by a Qdrant server
+pub struct QdrantStore {
+    client: Qdrant,
+    url: String,
+}
+
+impl QdrantStore {
+    pub fn new(url: &str) -> Result<Self> {
+        let client = Qdrant::from_url(url).build()?;
+        Ok(Self {
+            client,
+            url: url.to_string(),
+        })
+    }
+
+    /// Start a query with the options shared by every similarity search
+    fn query_request(
+        &self,
+        collection_name: &str,
+        filter: &Filter,
+        limit: usize,
+    ) -> QueryPointsBuilder {
+        let mut request = QueryPointsBuilder::new(collection_name)
+            .limit(limit as u64)
+            .with_payload(true)
+            .params(SearchParamsBuilder::default().hnsw_ef(128).exact(false));
+        if !filter.is_empty() {
+            request = request.filter(to_qdrant_filter(filter));
+        }
+        request
+    }
+
+    /// Page through the points matching the filter
+    async fn scroll(
+        &self,
+        collection_name: &str,
+        filter: &Filter,
+        limit: Option<usize>,
+        with_vectors: bool,
+    ) -> Result<Vec<RetrievedPoint>> {
+        let mut points = Vec::new();
+        let mut offset: Option<PointId> = None;
+
+        loop {
+            let (page, next) = self
+                .scroll_page(
+                    collection_name,
+                    filter,
+                    offset.take(),
+                    SCROLL_PAGE_SIZE,
+                    with_vectors,
+                )
+                .await?;
+            points.extend(page);
+
+            if let Some(limit) = limit {
+                if points.len() >= limit {
+                    points.truncate(limit);
+                    break;
+                }
+            }
+            match next {
+                Some(next) => offset = Some(next),
+                None => break,
+            }
+        }
+
+        Ok(points)
+    }
+
+    /// One page of a scroll and the offset of the next page, if any
+    async fn scroll_page(
+        &self,
+        collection_name: &str,
+        filter: &Filter,
+        offset: Option<PointId>,
+        limit: u32,
+        with_vectors: bool,
+    ) -> Result<(Vec<RetrievedPoint>, Option<PointId>)> {
+        let mut request = ScrollPointsBuilder::new(collection_name)
+            .limit(limit)
+            .with_payload(true)
+            .with_vectors(with_vectors)
+            .filter(without_metadata_point(filter));
+        if let Some(offset) = offset {
+            request = request.offset(offset);
+        }
+
+        let scroll_result = self
+            .client
+            .scroll(request)
+            .await
+            .context("Failed to scroll points")?;
+        Ok((scroll_result.result, scroll_result.next_page_offset))
+    }
+
+    async fn run_query(&self, request: QueryPointsBuilder) -> Result<Vec<ScoredPoint>> {
+        let query_result = self
+            .client
+            .query(request)
+            .await
+            .context("Failed to query points")?;
+
+        Ok(query_result
+            .result
+            .into_iter()
+            .map(|point| ScoredPoint {
+                id: point
+                    .id
+                    .as_ref()
+                    .map(point_id_to_string)
+                    .unwrap_or_default(),
+                score: point.score,
+                payload: to_json_payload(point.payload),
+            })
+            .collect())
+    }
+}
+
+fn dense_query(vector: &[f32]) -> Query {
+    Query::new_nearest(vector.to_vec())
+}
+
+fn sparse_query(vector: &SparseVector) -> Query {
+    Query::new_nearest(VectorInput::new_sparse(
+        vector.indices.clone(),
+        vector.values.clone(),
+    ))
+}
+
+fn point_id_to_string(id: &PointId) -> String {
+    match &id.point_id_options {
+        Some(PointIdOptions::Uuid(uuid)) => uuid.clone(),
+        Some(PointIdOptions::Num(num)) => num.to_string(),
+        None => String::new(),
+    }
+}
+
+fn to_index_point(point: RetrievedPoint) -> IndexPoint {
+    let (dense, sparse) = from_vectors_output(point.vectors);
+    IndexPoint {
+        id: point
+            .id
+            .as_ref()
+            .map(point_id_to_string)
+            .unwrap_or_default(),
+        dense,
+        sparse,
+        payload: to_json_payload(point.payload),
+    }
+}
+
+fn to_point_ids(ids: Vec<String>) -> Vec<PointId> {
+    ids.into_iter().map(PointId::from).collect()
+}
+
+fn to_json_payload(payload: std::collections::HashMap<String, qdrant::Value>) -> Payload {
+    payload
+        .into_iter()
+        .map(|(key, value)| (key, value.into_json()))
+        .collect()
+}
+
+fn to_qdrant_condition(condition: &Condition) -> qdrant::Condition {
+    match condition {
+        // Strings with whitespace would turn into a full-text match, so be explicit
+        Condition::Keyword { key, value } => {
+            qdrant::Condition::matches(key.clone(), MatchValue::Keyword(value.clone()))
+        }
+        Condition::AnyKeyword { key, values } => {
+            qdrant::Condition::matches(key.clone(), values.clone())
+        }
+        Condition::Range { key, gte, lte } => qdrant::Condition::range(
+            key.clone(),
+            qdrant::Range {
+                gte: *gte,
+                lte: *lte,
+                ..Default::default()
+            },
+        ),
+    }
+}
+
+fn to_qdrant_filter(filter: &Filter) -> qdrant::Filter {
+    qdrant::Filter {
+        must: filter.must.iter().map(to_qdrant_condition).collect(),
+        must_not: filter.must_not.iter().map(to_qdrant_condition).collect(),
+        ..Default::default()
+    }
+}
+
+/// The filter, also excluding the metadata point
+fn without_metadata_point(filter: &Filter) -> qdrant::Filter {
+    let mut qdrant_filter = to_qdrant_filter(filter);
+    qdrant_filter
+        .must_not
+        .push(qdrant::Condition::has_id([PointId::from(
+            METADATA_POINT_ID,
+        )]));
+    qdrant_filter
+}
+
+/// Split the named vectors of a stored point into dense and sparse ones
+fn from_vectors_output(
+    vectors: Option<VectorsOutput>,
+) -> (HashMap<String, Vec<f32>>, HashMap<String, SparseVector>) {
+    let mut dense = HashMap::new();
+    let mut sparse = HashMap::new();
+    if let Some(VectorsOptions::Vectors(named)) = vectors.and_then(|v| v.vectors_options) {
+        for (name, vector) in named.vectors {
+            match vector.indices {
+                Some(indices) => {
+                    sparse.insert(
+                        name,
+                        SparseVector {
+                            indices: indices.data,
+                            values: vector.data,
+                        },
+                    );
+                }
+                None => {
+                    dense.insert(name, vector.data);
+                }
+            }
+        }
+    }
+    (dense, sparse)
+}
+
+fn to_point_struct(point: IndexPoint) -> PointStruct {
+    let mut vectors = NamedVectors::default();
+    for (name, dense) in point.dense {
+        vectors = vectors.add_vector(name, Vector::new_dense(dense));
+    }
+    for (name, sparse) in point.sparse {
+        vectors = vectors.add_vector(name, Vector::new_sparse(sparse.indices, sparse.values));
+    }
+
+    PointStruct::new(
+        point.id,
+        vectors,
+        qdrant_client::Payload::from(point.payload),
+    )
+}
+
+#[async_trait]
+impl VectorStore for QdrantStore {
+    fn location(&self) -> String {
+        format!("qdrant {}", self.url)
+    }
+
+    async fn create_collection(
+        &self,
+        collection_name: &str,
+        schema: &CollectionSchema,
+    ) -> Result<bool> {
+        // Check if the collection already exists
+        if self.client.collection_exists(collection_name).await? {
+            info!(
+                "Collection '{}' already exists. Skipping creation.",
+                collection_name
+            );
+            return Ok(false);
+        }
+        // Configure sparse vectors using builder
+        let mut sparse_vector_config = SparseVectorsConfigBuilder::default();
+        sparse_vector_config
+            .add_named_vector_params(&schema.sparse_vector, SparseVectorParamsBuilder::default());
+
+        // Configure dense vectors using builder
+        let mut dense_vector_config = VectorsConfigBuilder::default();
+        dense_vector_config.add_named_vector_params(
+            &schema.dense_vector,
+            VectorParamsBuilder::new(schema.dimension as u64, Distance::Cosine),
+        );
+
+        // Create collection using builders
+        self.client
+            .create_collection(
+                CreateCollectionBuilder::new(collection_name)
+                    .sparse_vectors_config(sparse_vector_config)
+                    .vectors_config(dense_vector_config),
+            )
+            .await?;
+
+        Ok(true)
+    }
+
+    async fn delete_collection(&self, collection_name: &str) -> Result<()> {
+        self.client
+            .delete_collection(collection_name)
+            .await
+            .context("Failed to delete collection")?;
+        Ok(())
+    }
+
+    async fn collection_exists(&self, collection_name: &str) -> Result<bool> {
+        self.client
+            .collection_exists(collection_name)
+            .await
+            .context("Failed to look up collection")
+    }
+
+    async fn collection_metadata(
+        &self,
+        collection_name: &str,
+    ) -> Result<Option<CollectionMetadata>> {
+        let response = self
+            .client
+            .get_points(
+                GetPointsBuilder::new(collection_name, vec![PointId::from(METADATA_POINT_ID)])
+                    .with_payload(true),
+            )
+            .await
+            .context("Failed to read collection metadata")?;
+
+        let Some(point) = response.result.into_iter().next() else {
+            return Ok(None);
+        };
+        let Some(metadata) = to_json_payload(point.payload).remove(METADATA_FIELD) else {
+            return Ok(None);
+        };
+
+        Ok(Some(
+            serde_json::from_value(metadata).context("Invalid collection metadata")?,
+        ))
+    }
+
+    async fn set_collection_metadata(
+        &self,
+        collection_name: &str,
+        metadata: &CollectionMetadata,
+    ) -> Result<()> {
+        let mut payload = Payload::new();
+        payload.insert(METADATA_FIELD.to_string(), serde_json::to_value(metadata)?);
+
+        let point = PointStruct::new(
+            METADATA_POINT_ID,
+            NamedVectors::default(),
+            qdrant_client::Payload::from(payload),
+        );
+        self.client
+            .upsert_points(UpsertPointsBuilder::new(collection_name, vec![point]).wait(true))
+            .await
+            .context("Failed to write collection metadata")?;
+        Ok(())
+    }
+
+    async fn create_payload_index(
+        &self,
+        collection_name: &str,
+        field_name: &str,
+        field_type: PayloadFieldType,
+    ) -> Result<()> {
+        let info = self
+            .client
+            .collection_info(collection_name)
+            .await
+            .with_context(|| format!("Failed to read collection '{}'", collection_name))?;
+        if info
+            .result
+            .is_some_and(|info| info.payload_schema.contains_key(field_name))
+        {
+            return Ok(());
+        }
+
+        let field_type = match field_type {
+            PayloadFieldType::Keyword => FieldType::Keyword,
+            PayloadFieldType::Integer => FieldType::Integer,
+        };
+        self.client
+            .create_field_index(
+                CreateFieldIndexCollectionBuilder::new(collection_name, field_name, field_type)
+                    .wait(true),
+            )
+            .await
+            .with_context(|| format!("Failed to index payload field '{}'", field_name))?;
+        Ok(())
+    }
+
+    async fn upsert(&self, collection_name: &str, points: Vec<IndexPoint>) -> Result<()> {
+        let points: Vec<PointStruct> = points.into_iter().map(to_point_struct).collect();
+        self.client
+            .upsert_points(UpsertPointsBuilder::new(collection_name, points).wait(true))
+            .await
+            .context("Failed to upsert points")?;
+        Ok(())
+    }
+
+    async fn delete(&self, collection_name: &str, ids: Vec<String>) -> Result<()> {
+        self.client
+            .delete_points(
+                DeletePointsBuilder::new(collection_name)
+                    .points(PointsIdsList {
+                        ids: to_point_ids(ids),
+                    })
+                    .wait(true),
+            )
+            .await
+            .context("Failed to delete points")?;
+        Ok(())
+    }
+
+    async fn set_payload(
+        &self,
+        collection_name: &str,
+        ids: Vec<String>,
+        payload: Payload,
+    ) -> Result<()> {
+        self.client
+            .set_payload(
+                SetPayloadPointsBuilder::new(
+                    collection_name,
+                    qdrant_client::Payload::from(payload),
+                )
+                .points_selector(PointsIdsList {
+                    ids: to_point_ids(ids),
+                })
+                .wait(true),
+            )
+            .await
+            .context("Failed to set payload")?;
+        Ok(())
+    }
+
+    async fn query_filter(
+        &self,
+        collection_name: &str,
+        filter: &Filter,
+        limit: Option<usize>,
+    ) -> Result<Vec<StoredPoint>> {
+        let points = self.scroll(collection_name, filter, limit, false).await?;
+        Ok(points
+            .into_iter()
+            .map(|point| StoredPoint {
+                id: point
+                    .id
+                    .as_ref()
+                    .map(point_id_to_string)
+                    .unwrap_or_default(),
+                payload: to_json_payload(point.payload),
+            })
+            .collect())
+    }
+
+    async fn query_vectors(
+        &self,
+        collection_name: &str,
+        filter: &Filter,
+        limit: Option<usize>,
+    ) -> Result<Vec<IndexPoint>> {
+        let points = self.scroll(collection_name, filter, limit, true).await?;
+        Ok(points.into_iter().map(to_index_point).collect())
+    }
+
+    async fn scroll_vectors(
+        &self,
+        collection_name: &str,
+        offset: Option<String>,
+        limit: usize,
+    ) -> Result<(Vec<IndexPoint>, Option<String>)> {
+        let limit = u32::try_from(limit).unwrap_or(u32::MAX);
+        let (points, next) = self
+            .scroll_page(
+                collection_name,
+                &Filter::default(),
+                offset.map(PointId::from),
+                limit,
+                true,
+            )
+            .await?;
+        Ok((
+            points.into_iter().map(to_index_point).collect(),
+            next.as_ref().map(point_id_to_string),
+        ))
+    }
+
+    async fn query_hybrid(
+        &self,
+        collection_name: &str,
+        query: &HybridQuery,
+    ) -> Result<Vec<ScoredPoint>> {
+        match (&query.dense, &query.sparse, query.fusion) {
+            (Some((dense_name, dense)), Some((sparse_name, sparse)), Fusion::Rrf) => {
+                // Fetch candidates from both vectors and let Qdrant fuse the rankings
+                let mut dense_prefetch = PrefetchQueryBuilder::default()
+                    .query(dense_query(dense))
+                    .using(dense_name)
+                    .limit(query.prefetch_limit() as u64);
+                let mut sparse_prefetch = PrefetchQueryBuilder::default()
+                    .query(sparse_query(sparse))
+                    .using(sparse_name)
+                    .limit(query.prefetch_limit() as u64);
+                if !query.filter.is_empty() {
+                    dense_prefetch = dense_prefetch.filter(to_qdrant_filter(&query.filter));
+                    sparse_prefetch = sparse_prefetch.filter(to_qdrant_filter(&query.filter));
+                }
+
+                let request = self
+                    .query_request(collection_name, &query.filter, query.limit)
+                    .add_prefetch(dense_prefetch)
+                    .add_prefetch(sparse_prefetch)
+                    .query(Query::new_fusion(qdrant::Fusion::Rrf));
+                self.run_query(request).await
+            }
+            (
+                Some((dense_name, dense)),
+                Some((sparse_name, sparse)),
+                Fusion::Weighted {
+                    dense: dense_weight,
+                    sparse: sparse_weight,
+                },
+            ) => {
+                // Qdrant has no weighted fusion, so combine the two rankings here
+                let limit = query.prefetch_limit();
+                let dense_results = self
+                    .run_query(
+                        self.query_request(collection_name, &query.filter, limit)
+                            .query(dense_query(dense))
+                            .using(dense_name),
+                    )
+                    .await?;
+                let sparse_results = self
+                    .run_query(
+                        self.query_request(collection_name, &query.filter, limit)
+                            .query(sparse_query(sparse))
+                            .using(sparse_name),
+                    )
+                    .await?;
+
+                Ok(weighted_fusion(
+                    dense_results,
+                    sparse_results,
+                    dense_weight,
+                    sparse_weight,
+                    query.limit,
+                ))
+            }
+            (Some((dense_name, dense)), None, _) => {
+                self.run_query(
+                    self.query_request(collection_name, &query.filter, query.limit)
+                        .query(dense_query(dense))
+                        .using(dense_name),
+                )
+                .await
+            }
+            (None, Some((sparse_name, sparse)), _) => {
+                self.run_query(
+                    self.query_request(collection_name, &query.filter, query.limit)
+                        .query(sparse_query(sparse))
+                        .using(sparse_name),
+                )
+                .await
+            }
+            (None, None, _) => Ok(Vec::new()),
+        }
+    }
+}
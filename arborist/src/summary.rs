@@ -1,63 +1,86 @@
 use crate::file_management::{FileMetadata, FileType, FolderMetadata};
+use crate::llm::Summarizer;
 use anyhow::{Context, Result};
-use base64::Engine;
 use calamine::{open_workbook, Reader, Xlsx};
 use dotext::{pptx::Pptx, MsDoc};
 use log::info;
-use ollama_rs::{
-    generation::{completion::request::GenerationRequest, images::Image},
-    Ollama,
-};
 use pandoc::InputFormat;
 use pdf_extract::extract_text;
+use std::fs;
 use std::io::Read;
 use std::process::Command;
 use std::{fs::File, path::Path};
-use tokio::fs::read;
 
-pub async fn generate_file_summary(model: &str, file_metadata: &FileMetadata) -> Result<String> {
-    info!("Processing: {}", file_metadata.path.clone());
+/// What is sent to the LLM to summarize a file
+pub enum FileContent {
+    Text(String),
+    Image { bytes: Vec<u8>, mime_type: String },
+}
+
+/// Read what will be summarized from a file.
+///
+/// This does blocking IO and may run external converters, so callers on the
+/// async runtime should run it through `spawn_blocking`.
+pub fn extract_content(file_metadata: &FileMetadata) -> Result<FileContent> {
+    info!("Processing: {}", file_metadata.path);
     let content = match file_metadata.filetype {
-        FileType::Document => read_document(file_metadata.path.clone()).await?,
-        FileType::Image => return generate_image_summary(file_metadata.path.clone()).await,
-        FileType::Audio => transcribe_audio(model, file_metadata.path.clone()).await?,
-        FileType::Video => transcribe_video(model, file_metadata.path.clone()).await?,
-        FileType::Archive => summarize_archive(file_metadata.path.clone()).await?,
+        FileType::Document => read_document(&file_metadata.path)?,
+        FileType::Image => return read_image(&file_metadata.path),
+        FileType::Audio => transcribe_audio(&file_metadata.path)?,
+        FileType::Video => transcribe_video(&file_metadata.path)?,
+        FileType::Archive => summarize_archive(&file_metadata.path)?,
         FileType::Other => "Summary not available for this file type.".to_string(),
     };
 
-    let prompt = format!("Summarize the contents of file: {}", content);
-    let system = "You are a helpful assistant who summarizes file contents.".to_string();
+    Ok(FileContent::Text(content))
+}
 
-    let ollama = Ollama::default();
-    let res = ollama
-        .generate(GenerationRequest::new(model.to_string(), prompt).system(system))
-        .await?;
+/// Ask the LLM for a summary of extracted file contents
+pub async fn summarize_content(
+    summarizer: &dyn Summarizer,
+    content: &FileContent,
+) -> Result<String> {
+    match content {
+        FileContent::Text(text) => {
+            let prompt = format!("Summarize the contents of file: {}", text);
+            let system = "You are a helpful assistant who summarizes file contents.";
 
-    Ok(res.response)
+            summarizer.generate(system, &prompt).await
+        }
+        FileContent::Image { bytes, mime_type } => {
+            // Define the prompt
+            let prompt = "Describe this image.";
+
+            // Send the image to the vision model
+            summarizer.describe_image(prompt, bytes, mime_type).await
+        }
+    }
+}
+
+pub async fn generate_file_summary(
+    summarizer: &dyn Summarizer,
+    file_metadata: &FileMetadata,
+) -> Result<String> {
+    let content = extract_content(file_metadata)?;
+    summarize_content(summarizer, &content).await
 }
 
 pub async fn generate_folder_summary(
-    model: &str,
+    summarizer: &dyn Summarizer,
     folder_metadata: &FolderMetadata,
 ) -> Result<String> {
     let mut folder_content = String::new();
     // Summarize each file in the folder and aggregate the summaries
     for file in &folder_metadata.files {
-        let file_summary = generate_file_summary(model, file).await?;
+        let file_summary = generate_file_summary(summarizer, file).await?;
         folder_content.push_str(&file_summary);
         folder_content.push('\n');
     }
 
     let prompt = format!("Summarize the contents of folder: {}", folder_content);
-    let system = "You are a helpful assistant who summarizes folder contents.".to_string();
+    let system = "You are a helpful assistant who summarizes folder contents.";
 
-    let ollama = Ollama::default();
-    let res = ollama
-        .generate(GenerationRequest::new(model.to_string(), prompt).system(system))
-        .await?;
-
-    Ok(res.response)
+    summarizer.generate(system, &prompt).await
 }
 
 fn detect_input_format(file_path: &str) -> InputFormat {
@@ -163,15 +186,15 @@ fn read_pdf(file_path: &str) -> Result<String> {
 }
 
 // Updated read_document function with exhaustive pattern matching
-pub async fn read_document(file_path: String) -> Result<String> {
+pub fn read_document(file_path: &str) -> Result<String> {
     info!("Processing Document: {file_path}");
-    let input_format = detect_input_format(&file_path);
+    let input_format = detect_input_format(file_path);
 
     match input_format {
         // Handle PDF files separately
-        InputFormat::Other(format) if format == "pdf" => read_pdf(&file_path),
-        InputFormat::Other(format) if format == "xlsx" => read_xlsx(&file_path),
-        InputFormat::Other(format) if format == "pptx" => read_pptx(&file_path),
+        InputFormat::Other(format) if format == "pdf" => read_pdf(file_path),
+        InputFormat::Other(format) if format == "xlsx" => read_xlsx(file_path),
+        InputFormat::Other(format) if format == "pptx" => read_pptx(file_path),
         // For supported formats, use pandoc
         InputFormat::Markdown
         | InputFormat::Docx
@@ -189,7 +212,7 @@ pub async fn read_document(file_path: String) -> Result<String> {
                 .arg(input_format.to_string())
                 .arg("-t")
                 .arg("plain")
-                .arg(&file_path)
+                .arg(file_path)
                 .output()
                 .with_context(|| "Failed to execute pandoc command")?;
 
@@ -204,46 +227,43 @@ pub async fn read_document(file_path: String) -> Result<String> {
             }
         }
         // For other unsupported formats and future variants, fall back to plain text
-        _ => read_plain_text(&file_path),
+        _ => read_plain_text(file_path),
     }
 }
 
-pub async fn generate_image_summary(image_path: String) -> Result<String> {
+fn read_image(image_path: &str) -> Result<FileContent> {
     // Read the image file
-    let bytes = read(&image_path).await?;
-
-    // Encode the image bytes to base64
-    let base64_image = base64::engine::general_purpose::STANDARD.encode(&bytes);
+    let bytes =
+        fs::read(image_path).with_context(|| format!("Failed to read image: {}", image_path))?;
 
-    // Create an Image from the base64 string
-    let image = Image::from_base64(&base64_image);
+    // Guess the MIME type some backends need alongside the image
+    let mime_type = mime_guess::from_path(image_path).first_or_octet_stream();
 
-    // Define the prompt
-    let prompt = "Describe this image.";
-
-    // Create a GenerationRequest with the model, prompt, and image
-    let request =
-        GenerationRequest::new("minicpm-v".to_string(), prompt.to_string()).add_image(image);
-
-    // Send the request to Ollama
-    let ollama = Ollama::default();
-    let response = ollama.generate(request).await?;
+    Ok(FileContent::Image {
+        bytes,
+        mime_type: mime_type.essence_str().to_string(),
+    })
+}
 
-    // Return the response
-    Ok(response.response)
+pub async fn generate_image_summary(
+    summarizer: &dyn Summarizer,
+    image_path: String,
+) -> Result<String> {
+    let content = read_image(&image_path)?;
+    summarize_content(summarizer, &content).await
 }
 
-async fn transcribe_audio(_model: &str, file_path: String) -> Result<String> {
+fn transcribe_audio(file_path: &str) -> Result<String> {
     // Placeholder implementation for transcribing audio
     Ok(format!("Audio transcription for: {}", file_path))
 }
 
-async fn transcribe_video(_model: &str, file_path: String) -> Result<String> {
+fn transcribe_video(file_path: &str) -> Result<String> {
     // Placeholder implementation for transcribing video
     Ok(format!("Video transcription for: {}", file_path))
 }
 
-async fn summarize_archive(file_path: String) -> Result<String> {
+fn summarize_archive(file_path: &str) -> Result<String> {
     // Placeholder implementation for summarizing archive
     Ok(format!("Archive summary for: {}", file_path))
 }
@@ -1,167 +1,221 @@
+use crate::config::{Config, EmbeddingConfig, QueryConfig, ScanConfig};
+use crate::database::DENSE_VECTOR_NAME;
 use crate::file_management::{FileMetadata, FileType, FolderMetadata};
-use anyhow::Result;
-use fastembed::{EmbeddingModel, InitOptions, SparseTextEmbedding, TextEmbedding};
-use ollama_rs::generation::completion::request::GenerationRequest;
-use ollama_rs::Ollama;
-use qdrant_client::qdrant::SearchPoints;
-use qdrant_client::Qdrant;
+use crate::filters::parse_size;
+use crate::llm::Summarizer;
+use crate::store::{Filter, Fusion, HybridQuery, ScoredPoint, VectorStore};
+use anyhow::{anyhow, Context, Result};
+use clap::ArgAction;
+use fastembed::{
+    EmbeddingModel, InitOptions, ModelInfo, RerankInitOptions, SparseInitOptions, SparseModel,
+    SparseTextEmbedding, TextEmbedding, TextRerank,
+};
+use globset::{Glob, GlobSet, GlobSetBuilder};
+use ignore::overrides::OverrideBuilder;
+use ignore::{WalkBuilder, WalkState};
+use indicatif::ProgressBar;
 use serde::{Deserialize, Serialize};
-use std::fmt;
 use std::path::Path;
+use std::sync::mpsc;
 use std::time::Instant;
 use std::{collections::HashMap, path::PathBuf};
-use tokio::fs::metadata;
-use walkdir::{DirEntry, WalkDir};
-
-// Helper function to calculate folder size
-async fn calculate_folder_size(path: &Path) -> Result<u64> {
-    let mut total_size = 0;
-    for entry in WalkDir::new(path) {
-        let entry = entry?;
-        if entry.file_type().is_file() {
-            let meta = metadata(entry.path()).await?;
-            total_size += meta.len();
-        }
-    }
-    Ok(total_size)
-}
-
-// Helper function to count files in a folder
-fn count_files_in_folder(path: &Path) -> u32 {
-    WalkDir::new(path)
-        .into_iter()
-        .filter_map(|e| e.ok())
-        .filter(|e| e.file_type().is_file())
-        .count() as u32
-}
 
-// Helper function to count folders in a folder
-fn count_folders_in_folder(path: &Path) -> u32 {
-    WalkDir::new(path)
-        .into_iter()
-        .filter_map(|e| e.ok())
-        .filter(|e| e.file_type().is_dir())
-        .count() as u32
-}
+/// Per-directory ignore file read in addition to `.gitignore` and `.ignore`
+pub const IGNORE_FILE_NAME: &str = ".arboristignore";
 
 // DirScanConfig struct and its implementation
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct DirScanConfig {
     path: PathBuf,
+    /// Threads walking the tree, 0 picks a number from the available cores
+    walk_threads: usize,
+    max_depth: usize,
     skip_hidden: bool,
-    paths_to_skip: Option<Vec<String>>,
+    follow_symlinks: bool,
+    same_file_system: bool,
+    paths_to_skip: Vec<String>,
+    min_file_size: Option<u64>,
+    max_file_size: Option<u64>,
+    /// Globs of the files to scan, relative to `path`; every file when empty
+    include: Vec<String>,
+    /// Gitignore-style patterns of files and folders to leave out
+    exclude: Vec<String>,
+    /// Files and folders below `path` the walk is narrowed to; everything when empty
+    #[serde(skip)]
+    focus: Vec<PathBuf>,
 }
 
 impl Default for DirScanConfig {
     fn default() -> Self {
-        DirScanConfig {
-            path: ".".into(),
-            skip_hidden: true,
-            paths_to_skip: Some(vec![
-                "node_modules".to_string(),
-                "downloaded-torrents".to_string(),
-                "target".to_string(),
-                "build".to_string(),
-                "dist".to_string(),
-                "downloaded-torrents".to_string(),
-                ".git".to_string(),
-            ]),
-        }
+        DirScanConfig::new(".".into())
     }
 }
 
 impl DirScanConfig {
     pub fn new(path: PathBuf) -> Self {
+        DirScanConfig::from_config(path, &ScanConfig::default())
+    }
+
+    /// Scan settings for `path` taken from the walk options of the configuration
+    pub fn from_config(path: PathBuf, config: &ScanConfig) -> Self {
         DirScanConfig {
             path,
-            ..Default::default()
+            walk_threads: config.walk_threads,
+            max_depth: config.max_depth,
+            skip_hidden: config.skip_hidden,
+            follow_symlinks: config.follow_symlinks,
+            same_file_system: config.same_file_system,
+            paths_to_skip: config.paths_to_skip.clone(),
+            min_file_size: config.min_file_size,
+            max_file_size: config.max_file_size,
+            include: config.include.clone(),
+            exclude: config.exclude.clone(),
+            focus: Vec::new(),
+        }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Narrow the walk to `paths` and the folders leading to them.
+    ///
+    /// The rest of the walk rules still apply, so an ignored file stays out
+    /// of the result when it is asked for.
+    pub fn focused_on(mut self, paths: Vec<PathBuf>) -> Self {
+        self.focus = paths;
+        self
+    }
+
+    /// Walker honouring the ignore files, the exclude patterns and the skip list
+    fn walker(&self) -> Result<ignore::WalkParallel> {
+        // Overrides starting with `!` leave out what they match and let everything else through
+        let mut overrides = OverrideBuilder::new(&self.path);
+        for pattern in &self.exclude {
+            overrides
+                .add(&format!("!{}", pattern))
+                .with_context(|| format!("Invalid exclude pattern: {}", pattern))?;
         }
+
+        let paths_to_skip = self.paths_to_skip.clone();
+        let focus = self.focus.clone();
+        Ok(WalkBuilder::new(&self.path)
+            .threads(self.walk_threads)
+            .max_depth(Some(self.max_depth))
+            .hidden(self.skip_hidden)
+            .follow_links(self.follow_symlinks)
+            .same_file_system(self.same_file_system)
+            // Generated files are listed in .gitignore whether or not the tree is a repository
+            .require_git(false)
+            .add_custom_ignore_filename(IGNORE_FILE_NAME)
+            .overrides(overrides.build().context("Invalid exclude patterns")?)
+            .filter_entry(move |entry| {
+                !should_skip(entry, &paths_to_skip) && in_focus(entry.path(), &focus)
+            })
+            .build_parallel())
     }
 
     pub async fn scan_dir(&self) -> Result<DirScanResult> {
-        let mut file_count = 0;
-        let mut folder_count = 0;
+        self.scan_dir_with_progress(&ProgressBar::hidden()).await
+    }
+
+    /// Walk the directory, counting files and showing the current path on `progress`.
+    ///
+    /// The walk, including hashing, runs on a pool of threads on the blocking
+    /// thread pool, see [`DirScanConfig::walk`].
+    pub async fn scan_dir_with_progress(&self, progress: &ProgressBar) -> Result<DirScanResult> {
+        let (config, progress) = (self.clone(), progress.clone());
+        tokio::task::spawn_blocking(move || config.walk(&progress))
+            .await
+            .context("Directory scan failed")?
+    }
+
+    /// Walk the directory with a work-stealing pool of threads.
+    ///
+    /// Entries are sorted by path once the walk is done, so the result does not
+    /// depend on the order the threads finished in. Folder sizes and counts are
+    /// then summed up from the scanned files, deepest folders first.
+    pub fn walk(&self, progress: &ProgressBar) -> Result<DirScanResult> {
+        let start_time = Instant::now();
+        let include = include_globs(&self.include)?;
+
+        let (entry_tx, entry_rx) = mpsc::channel();
+        self.walker()?.run(|| {
+            let entry_tx = entry_tx.clone();
+            let include = include.as_ref();
+            Box::new(move |entry| {
+                let entry = match entry {
+                    Ok(entry) => entry,
+                    Err(e) => {
+                        progress.suspend(|| eprintln!("error reading entry: {:?}", e));
+                        return WalkState::Continue;
+                    }
+                };
+                if let Some(entry) = self.read_entry(&entry, include, progress) {
+                    if entry_tx.send(entry).is_err() {
+                        return WalkState::Quit;
+                    }
+                }
+                WalkState::Continue
+            })
+        });
+        drop(entry_tx);
+
+        // Sorting by path puts every folder right before its contents
+        let mut entries: Vec<WalkedEntry> = entry_rx.into_iter().collect();
+        entries.sort_by(|a, b| a.path().cmp(b.path()));
+
         let mut extension_map: HashMap<String, usize> = HashMap::new();
         let mut file_list = Vec::new();
         let mut folder_list = Vec::new();
         let mut file_metadata_list = Vec::new();
-        let mut folder_metadata_list = Vec::new();
+        let mut folder_metadata_list: Vec<FolderMetadata> = Vec::new();
+        // Index of the parent of every folder, and of every folder by path
+        let mut folder_parents: Vec<Option<usize>> = Vec::new();
+        let mut folder_index: HashMap<PathBuf, usize> = HashMap::new();
 
-        let start_time = Instant::now();
+        for entry in entries {
+            let parent = entry
+                .path()
+                .parent()
+                .and_then(|parent| folder_index.get(parent).copied());
 
-        for entry in WalkDir::new(&self.path)
-            .max_depth(10)
-            .into_iter()
-            .filter_entry(|e| {
-                (!self.skip_hidden || !is_hidden(e)) && !should_skip(e, &self.paths_to_skip)
-            })
-        {
             match entry {
-                Ok(entry) => {
-                    if entry.file_type().is_dir() {
-                        folder_count += 1;
-                        folder_list.push(entry.path().to_string_lossy().into_owned());
-
-                        // Collect folder metadata
-                        let meta = metadata(&entry.path()).await?;
-                        let folder_size = calculate_folder_size(entry.path()).await?;
-                        let created_at = meta.created()?;
-                        let modified_at = meta.modified()?;
-                        let file_count_folder = count_files_in_folder(entry.path());
-                        let folder_count_folder = count_folders_in_folder(entry.path());
-
-                        folder_metadata_list.push(FolderMetadata {
-                            name: entry.file_name().to_string_lossy().into_owned(),
-                            path: entry.path().to_string_lossy().into_owned(),
-                            size: folder_size,
-                            created_at,
-                            modified_at,
-                            file_count: file_count_folder,
-                            files: file_metadata_list.clone(),
-                            folder_count: folder_count_folder,
-                            summary: String::new(), // To be filled later
-                        });
-                    } else if entry.file_type().is_file() {
-                        file_count += 1;
-                        file_list.push(entry.path().to_string_lossy().into_owned());
-
-                        // Update extension map
-                        if let Some(extension) = entry.path().extension() {
-                            let extension_str = extension.to_string_lossy().to_string();
-                            *extension_map.entry(extension_str).or_insert(0) += 1;
-                        }
-
-                        // Collect file metadata
-                        let file_name = entry.file_name().to_string_lossy().into_owned();
-                        let file_size = metadata(&entry.path()).await?.len();
-                        let file_type = FileType::from_path(&entry.path().to_string_lossy());
-                        let created_at = metadata(&entry.path()).await?.created()?;
-                        let modified_at = metadata(&entry.path()).await?.modified()?;
-
-                        file_metadata_list.push(FileMetadata {
-                            name: file_name,
-                            path: entry.path().to_string_lossy().into_owned(),
-                            size: file_size,
-                            filetype: file_type,
-                            created_at,
-                            modified_at,
-                            summary: String::new(),
-                        });
+                WalkedEntry::Folder(path, folder) => {
+                    folder_index.insert(path, folder_metadata_list.len());
+                    folder_parents.push(parent);
+                    folder_list.push(folder.path.clone());
+                    folder_metadata_list.push(folder);
+                }
+                WalkedEntry::File(path, file) => {
+                    // Update extension map
+                    if let Some(extension) = path.extension() {
+                        let extension_str = extension.to_string_lossy().to_string();
+                        *extension_map.entry(extension_str).or_insert(0) += 1;
+                    }
+
+                    if let Some(parent) = parent {
+                        let folder = &mut folder_metadata_list[parent];
+                        folder.size += file.size;
+                        folder.file_count += 1;
+                        folder.files.push(file.clone());
                     }
+                    file_list.push(file.path.clone());
+                    file_metadata_list.push(file);
                 }
-                Err(e) => eprintln!("error reading entry: {:?}", e),
             }
         }
 
+        add_subfolder_totals(&mut folder_metadata_list, &folder_parents);
         let elapsed_time = start_time.elapsed();
 
-        // Sort extensions by count in descending order
+        // Sort extensions by count in descending order, then by name
         let mut extension_count: Vec<(String, usize)> = extension_map.into_iter().collect();
-        extension_count.sort_by(|a, b| b.1.cmp(&a.1));
+        extension_count.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
 
         Ok(DirScanResult {
-            file_count,
-            folder_count,
+            file_count: file_list.len(),
+            folder_count: folder_list.len(),
             extension_count,
             elapsed_time,
             file_list,
@@ -170,13 +224,130 @@ impl DirScanConfig {
             folder_metadata_list,
         })
     }
+
+    /// Read the metadata of a walked file or folder, `None` when it is left out
+    fn read_entry(
+        &self,
+        entry: &ignore::DirEntry,
+        include: Option<&GlobSet>,
+        progress: &ProgressBar,
+    ) -> Option<WalkedEntry> {
+        let file_type = entry.file_type()?;
+        if !file_type.is_dir() && !file_type.is_file() {
+            return None;
+        }
+
+        if file_type.is_file() {
+            let relative_path = entry
+                .path()
+                .strip_prefix(&self.path)
+                .unwrap_or(entry.path());
+            if include.is_some_and(|include| !include.is_match(relative_path)) {
+                return None;
+            }
+        }
+
+        let meta = match entry.metadata() {
+            Ok(meta) => meta,
+            Err(e) => {
+                progress.suspend(|| eprintln!("error reading metadata: {:?}", e));
+                return None;
+            }
+        };
+        let path = entry.path().to_string_lossy().into_owned();
+        let name = entry.file_name().to_string_lossy().into_owned();
+        let modified_at = match meta.modified() {
+            Ok(modified_at) => modified_at,
+            Err(e) => {
+                progress.suspend(|| eprintln!("error reading modification time: {:?}", e));
+                return None;
+            }
+        };
+        // Not every file system records a creation time
+        let created_at = meta.created().unwrap_or(modified_at);
+
+        if file_type.is_dir() {
+            return Some(WalkedEntry::Folder(
+                entry.path().to_path_buf(),
+                FolderMetadata {
+                    name,
+                    path,
+                    size: 0,
+                    created_at,
+                    modified_at,
+                    file_count: 0,
+                    files: Vec::new(),
+                    folder_count: 0,
+                    summary: String::new(), // To be filled later
+                },
+            ));
+        }
+
+        let file_size = meta.len();
+        if self.min_file_size.is_some_and(|min| file_size < min)
+            || self.max_file_size.is_some_and(|max| file_size > max)
+        {
+            return None;
+        }
+        progress.inc(1);
+        progress.set_message(path.clone());
+
+        Some(WalkedEntry::File(
+            entry.path().to_path_buf(),
+            FileMetadata {
+                name,
+                filetype: FileType::from_path(&path),
+                path,
+                size: file_size,
+                created_at,
+                modified_at,
+                // Hashed when planning, only for files that may have changed
+                content_hash: String::new(),
+                summary: String::new(),
+            },
+        ))
+    }
+}
+
+/// A file or folder found by the walk, with its path
+enum WalkedEntry {
+    Folder(PathBuf, FolderMetadata),
+    File(PathBuf, FileMetadata),
+}
+
+impl WalkedEntry {
+    fn path(&self) -> &Path {
+        match self {
+            WalkedEntry::Folder(path, _) | WalkedEntry::File(path, _) => path,
+        }
+    }
+}
+
+/// Add the size and counts of every folder to its parent.
+///
+/// Folders come after their parent in path order, so going through them
+/// backwards finishes each folder before it is added to its parent.
+fn add_subfolder_totals(folders: &mut [FolderMetadata], parents: &[Option<usize>]) {
+    for index in (0..folders.len()).rev() {
+        let Some(parent) = parents[index] else {
+            continue;
+        };
+        let (size, file_count, folder_count) = {
+            let folder = &folders[index];
+            (folder.size, folder.file_count, folder.folder_count)
+        };
+        let parent = &mut folders[parent];
+        parent.size += size;
+        parent.file_count += file_count;
+        parent.folder_count += folder_count + 1;
+    }
 }
 
 // DirScanResult struct and its implementation
 #[derive(Serialize, Deserialize, Debug)]
 pub struct DirScanResult {
-    pub file_count: u16,
-    pub folder_count: u16,
+    pub file_count: usize,
+    pub folder_count: usize,
     pub extension_count: Vec<(String, usize)>,
     pub elapsed_time: std::time::Duration,
     pub file_list: Vec<String>,
@@ -185,85 +356,345 @@ pub struct DirScanResult {
     pub folder_metadata_list: Vec<FolderMetadata>,
 }
 
-impl fmt::Display for DirScanResult {
-    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        writeln!(f, "Directory Scan Results:")?;
-        writeln!(f, "-----------------------")?;
-        writeln!(f, "Total Files: {}", self.file_count)?;
-        writeln!(f, "Total Folders: {}", self.folder_count)?;
-        writeln!(f, "File Extensions (sorted by count):")?;
-
-        for (ext, count) in &self.extension_count {
-            writeln!(f, "  - .{}: {}", ext, count)?;
-        }
+// Function to generate folder summary using LLM
+pub async fn gen_folder_summary(
+    summarizer: &dyn Summarizer,
+    folder_metadata: &FolderMetadata,
+) -> Result<String> {
+    let prompt = format!("Summarize the contents of folder: {}", folder_metadata.path);
+    let system = "You are a helpful assistant who summarizes folder contents.";
 
-        writeln!(f, "Scan Took: {:?}", self.elapsed_time)?;
+    summarizer.generate(system, &prompt).await
+}
 
-        Ok(())
-    }
+// Function to search summaries in the configured collection
+pub async fn search_summaries(
+    store: &dyn VectorStore,
+    config: &Config,
+    model: &TextEmbedding,
+    query: &str,
+) -> Result<Vec<ScoredPoint>> {
+    let query_embedding = model.embed(vec![query], None)?.remove(0);
+
+    store
+        .query_hybrid(
+            &config.collection_name,
+            &HybridQuery {
+                dense: Some((DENSE_VECTOR_NAME.to_string(), query_embedding)),
+                sparse: None,
+                filter: Filter::default(),
+                limit: config.query.top_k_results,
+                fusion: Fusion::Rrf,
+            },
+        )
+        .await
 }
 
-// Function to generate folder summary using LLM
-pub async fn gen_folder_summary(model: String, folder_metadata: &FolderMetadata) -> Result<String> {
-    let prompt = format!("Summarize the contents of folder: {}", folder_metadata.path);
-    let system = "You are a helpful assistant who summarizes folder contents.".to_string();
+/// Match set of the include globs, `None` when every file is included
+fn include_globs(patterns: &[String]) -> Result<Option<GlobSet>> {
+    if patterns.is_empty() {
+        return Ok(None);
+    }
 
-    let ollama = Ollama::default();
-    let res = ollama
-        .generate(GenerationRequest::new(model, prompt).system(system))
-        .await?;
+    let mut builder = GlobSetBuilder::new();
+    for pattern in patterns {
+        builder.add(
+            Glob::new(pattern).with_context(|| format!("Invalid include pattern: {}", pattern))?,
+        );
+    }
+    Ok(Some(builder.build().context("Invalid include patterns")?))
+}
 
-    Ok(res.response)
+/// Whether `path` is one of the focused paths, below one, or on the way to one
+fn in_focus(path: &Path, focus: &[PathBuf]) -> bool {
+    focus.is_empty()
+        || focus
+            .iter()
+            .any(|focused| path.starts_with(focused) || focused.starts_with(path))
 }
 
-// Placeholder function for embedding generation
-pub fn gen_embedding(_summary: &str) -> Vec<f32> {
-    // Placeholder for embedding generation logic
-    vec![0.0; 1536]
+fn should_skip(entry: &ignore::DirEntry, paths_to_skip: &[String]) -> bool {
+    // The scanned directory itself is never skipped
+    if entry.depth() == 0 {
+        return false;
+    }
+    match entry.file_name().to_str() {
+        Some(name) => paths_to_skip.iter().any(|p| name == p),
+        None => false,
+    }
 }
 
-// Function to search summaries in Qdrant
-pub async fn search_summaries(
-    client: &Qdrant,
-    query: &str,
-) -> Result<Vec<qdrant_client::qdrant::ScoredPoint>> {
-    let query_embedding = gen_embedding(query);
+/// Command line overrides of the walk options under `[scan]`
+#[derive(clap::Args, Serialize, Deserialize, Debug, Default, Clone)]
+pub struct WalkArgs {
+    /// Threads walking the tree, more help on network file systems
+    #[arg(long, value_name = "N")]
+    pub walk_threads: Option<usize>,
 
-    let search_params = SearchPoints {
-        collection_name: "summaries".to_string(),
-        vector: query_embedding,
-        limit: 5,
-        with_payload: Some(true.into()),
-        ..Default::default()
-    };
+    /// Deepest folder level to descend into below the scanned directory
+    #[arg(long)]
+    pub max_depth: Option<usize>,
 
-    let search_result = client.search_points(search_params).await?;
+    /// Also scan hidden files and folders, `--hidden=false` leaves them out
+    #[arg(long, value_name = "BOOL", num_args = 0..=1, require_equals = true, default_missing_value = "true", action = ArgAction::Set)]
+    pub hidden: Option<bool>,
 
-    Ok(search_result.result)
-}
+    /// Follow symbolic links, `--follow-symlinks=false` does not
+    #[arg(long, value_name = "BOOL", num_args = 0..=1, require_equals = true, default_missing_value = "true", action = ArgAction::Set)]
+    pub follow_symlinks: Option<bool>,
+
+    /// Do not cross into other file systems, `--same-file-system=false` does
+    #[arg(long, value_name = "BOOL", num_args = 0..=1, require_equals = true, default_missing_value = "true", action = ArgAction::Set)]
+    pub same_file_system: Option<bool>,
 
-fn is_hidden(entry: &DirEntry) -> bool {
-    entry
-        .file_name()
-        .to_str()
-        .map(|s| s.starts_with('.'))
-        .unwrap_or(false)
+    /// Skip files and folders with this name, on top of the configured skip list
+    #[arg(long, value_name = "NAME")]
+    pub skip: Vec<String>,
+
+    /// Leave out files smaller than this, e.g. 500, 10K or 2M
+    #[arg(long, value_parser = parse_size)]
+    pub min_size: Option<u64>,
+
+    /// Leave out files larger than this, e.g. 500, 10K or 2M
+    #[arg(long, value_parser = parse_size)]
+    pub max_size: Option<u64>,
+
+    /// Only scan files matching these globs, on top of the configured ones
+    #[arg(long, value_name = "GLOB")]
+    pub include: Vec<String>,
+
+    /// Leave out files and folders matching these gitignore-style patterns
+    #[arg(long, value_name = "PATTERN")]
+    pub exclude: Vec<String>,
 }
 
-fn should_skip(entry: &DirEntry, paths_to_skip: &Option<Vec<String>>) -> bool {
-    if let Some(paths) = paths_to_skip {
-        if let Some(name) = entry.file_name().to_str() {
-            return paths.iter().any(|p| name == p);
+impl WalkArgs {
+    /// Apply the flags given on the command line to the configured walk options
+    pub fn apply(&self, config: &mut ScanConfig) {
+        if let Some(walk_threads) = self.walk_threads {
+            config.walk_threads = walk_threads;
+        }
+        if let Some(max_depth) = self.max_depth {
+            config.max_depth = max_depth;
+        }
+        if let Some(hidden) = self.hidden {
+            config.skip_hidden = !hidden;
+        }
+        if let Some(follow_symlinks) = self.follow_symlinks {
+            config.follow_symlinks = follow_symlinks;
+        }
+        if let Some(same_file_system) = self.same_file_system {
+            config.same_file_system = same_file_system;
         }
+        config.paths_to_skip.extend(self.skip.iter().cloned());
+        if self.min_size.is_some() {
+            config.min_file_size = self.min_size;
+        }
+        if self.max_size.is_some() {
+            config.max_file_size = self.max_size;
+        }
+        config.include.extend(self.include.iter().cloned());
+        config.exclude.extend(self.exclude.iter().cloned());
     }
-    false
 }
 
-pub fn setup_fastembed() -> anyhow::Result<(TextEmbedding, SparseTextEmbedding)> {
+/// Look up the configured dense model among the ones fastembed supports
+pub fn dense_model_info(config: &EmbeddingConfig) -> Result<ModelInfo<EmbeddingModel>> {
+    TextEmbedding::list_supported_models()
+        .into_iter()
+        .find(|info| info.model_code.eq_ignore_ascii_case(&config.dense_model))
+        .ok_or_else(|| anyhow!("Unsupported dense embedding model: {}", config.dense_model))
+}
+
+/// Look up the configured sparse model among the ones fastembed supports
+pub fn sparse_model_info(config: &EmbeddingConfig) -> Result<ModelInfo<SparseModel>> {
+    SparseTextEmbedding::list_supported_models()
+        .into_iter()
+        .find(|info| info.model_code.eq_ignore_ascii_case(&config.sparse_model))
+        .ok_or_else(|| {
+            anyhow!(
+                "Unsupported sparse embedding model: {}",
+                config.sparse_model
+            )
+        })
+}
+
+pub fn setup_fastembed(
+    config: &EmbeddingConfig,
+) -> anyhow::Result<(TextEmbedding, SparseTextEmbedding)> {
     let model = TextEmbedding::try_new(
-        InitOptions::new(EmbeddingModel::NomicEmbedTextV15).with_show_download_progress(true),
+        InitOptions::new(dense_model_info(config)?.model).with_show_download_progress(true),
+    )?;
+    let sparse_model = SparseTextEmbedding::try_new(
+        SparseInitOptions::new(sparse_model_info(config)?.model).with_show_download_progress(true),
     )?;
-    let sparse_model = SparseTextEmbedding::try_new(Default::default())?;
 
     Ok((model, sparse_model))
 }
+
+/// Load the configured reranker, `None` when reranking is turned off
+pub fn setup_reranker(config: &QueryConfig) -> Result<Option<TextRerank>> {
+    if !config.rerank {
+        return Ok(None);
+    }
+    let info = TextRerank::list_supported_models()
+        .into_iter()
+        .find(|info| info.model_code.eq_ignore_ascii_case(&config.rerank_model))
+        .ok_or_else(|| anyhow!("Unsupported reranker model: {}", config.rerank_model))?;
+    let reranker =
+        TextRerank::try_new(RerankInitOptions::new(info.model).with_show_download_progress(true))?;
+
+    Ok(Some(reranker))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    /// A small tree with files at three levels and an empty folder
+    fn sample_tree(name: &str) -> PathBuf {
+        let root =
+            std::env::temp_dir().join(format!("arborist-walk-{}-{}", std::process::id(), name));
+        let _ = fs::remove_dir_all(&root);
+        for (path, contents) in [
+            ("a.txt", "abc"),
+            ("z.rs", "fn"),
+            ("docs/c.md", "hello"),
+            ("docs/deep/e.txt", "1234567"),
+            ("docs/deep/f.txt", "12"),
+        ] {
+            let path = root.join(path);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, contents).unwrap();
+        }
+        fs::create_dir_all(root.join("empty")).unwrap();
+        root
+    }
+
+    fn walk(root: &Path) -> DirScanResult {
+        let config = ScanConfig {
+            walk_threads: 4,
+            ..ScanConfig::default()
+        };
+        DirScanConfig::from_config(root.to_path_buf(), &config)
+            .walk(&ProgressBar::hidden())
+            .unwrap()
+    }
+
+    fn folder<'a>(result: &'a DirScanResult, path: &Path) -> &'a FolderMetadata {
+        let path = path.to_string_lossy();
+        result
+            .folder_metadata_list
+            .iter()
+            .find(|folder| folder.path == path)
+            .unwrap()
+    }
+
+    fn file_names(folder: &FolderMetadata) -> Vec<&str> {
+        folder.files.iter().map(|file| file.name.as_str()).collect()
+    }
+
+    #[test]
+    fn walk_is_sorted_by_path_and_repeatable() {
+        let root = sample_tree("sorted");
+        let result = walk(&root);
+
+        let expected: Vec<String> = [
+            "a.txt",
+            "docs/c.md",
+            "docs/deep/e.txt",
+            "docs/deep/f.txt",
+            "z.rs",
+        ]
+        .iter()
+        .map(|path| root.join(path).to_string_lossy().into_owned())
+        .collect();
+        assert_eq!(result.file_list, expected);
+        assert_eq!(result.file_count, 5);
+        assert_eq!(
+            result.folder_list,
+            ["", "docs", "docs/deep", "empty"]
+                .iter()
+                .map(|path| root.join(path).to_string_lossy().into_owned())
+                .map(|path| path.trim_end_matches('/').to_string())
+                .collect::<Vec<_>>()
+        );
+        assert_eq!(
+            result.extension_count,
+            vec![
+                ("txt".to_string(), 3),
+                ("md".to_string(), 1),
+                ("rs".to_string(), 1)
+            ]
+        );
+
+        for _ in 0..5 {
+            let again = walk(&root);
+            assert_eq!(again.file_list, result.file_list);
+            assert_eq!(again.folder_list, result.folder_list);
+        }
+        fs::remove_dir_all(root).unwrap();
+    }
+
+    #[test]
+    fn folders_list_only_their_own_files() {
+        let root = sample_tree("files");
+        let result = walk(&root);
+
+        assert_eq!(file_names(folder(&result, &root)), ["a.txt", "z.rs"]);
+        assert_eq!(file_names(folder(&result, &root.join("docs"))), ["c.md"]);
+        assert_eq!(
+            file_names(folder(&result, &root.join("docs/deep"))),
+            ["e.txt", "f.txt"]
+        );
+        assert!(folder(&result, &root.join("empty")).files.is_empty());
+        fs::remove_dir_all(root).unwrap();
+    }
+
+    #[test]
+    fn folders_total_their_subfolders() {
+        let root = sample_tree("totals");
+        let result = walk(&root);
+
+        let totals = |path: &Path| {
+            let folder = folder(&result, path);
+            (folder.size, folder.file_count, folder.folder_count)
+        };
+        assert_eq!(totals(&root), (19, 5, 3));
+        assert_eq!(totals(&root.join("docs")), (14, 3, 1));
+        assert_eq!(totals(&root.join("docs/deep")), (9, 2, 0));
+        assert_eq!(totals(&root.join("empty")), (0, 0, 0));
+        fs::remove_dir_all(root).unwrap();
+    }
+
+    #[derive(clap::Parser)]
+    struct Cli {
+        #[command(flatten)]
+        walk: WalkArgs,
+    }
+
+    fn walk_options(args: &[&str]) -> ScanConfig {
+        use clap::Parser;
+        let mut config = ScanConfig {
+            follow_symlinks: true,
+            ..ScanConfig::default()
+        };
+        Cli::parse_from(std::iter::once("scan").chain(args.iter().copied()))
+            .walk
+            .apply(&mut config);
+        config
+    }
+
+    #[test]
+    fn switches_override_the_config_both_ways() {
+        let config = walk_options(&[]);
+        assert!(config.skip_hidden && config.follow_symlinks && !config.same_file_system);
+
+        let config = walk_options(&["--hidden", "--same-file-system"]);
+        assert!(!config.skip_hidden && config.same_file_system);
+
+        let config = walk_options(&["--follow-symlinks=false", "--hidden=false"]);
+        assert!(!config.follow_symlinks && config.skip_hidden);
+    }
+}
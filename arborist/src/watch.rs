@@ -0,0 +1,160 @@
+//! Keeps the index of a directory in sync with the filesystem.
+//!
+//! Events are debounced, so a burst of writes to a file, or a folder being
+//! copied in, is indexed once after it settles. Changed files go through the
+//! same pipeline as a scan, which also picks up renames by content hash, and
+//! the points of deleted files are removed.
+
+use crate::config::{data_dir, ScanConfig};
+use crate::indexer::Indexer;
+use crate::progress::ScanProgress;
+use crate::report::ReportFormat;
+use crate::utils::DirScanConfig;
+use anyhow::{Context, Result};
+use notify_debouncer_full::notify::event::ModifyKind;
+use notify_debouncer_full::notify::{EventKind, RecursiveMode};
+use notify_debouncer_full::{new_debouncer, DebouncedEvent};
+use std::collections::BTreeSet;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use tokio::sync::mpsc;
+
+/// Paths touched by a batch of events, split by whether they still exist
+#[derive(Debug, Default)]
+struct Changes {
+    changed: BTreeSet<PathBuf>,
+    removed: BTreeSet<PathBuf>,
+}
+
+impl Changes {
+    fn from_events(events: &[DebouncedEvent]) -> Self {
+        // The journal lives in the data directory, indexing it would trigger itself
+        let data_dir = data_dir();
+        let mut changes = Changes::default();
+
+        for event in events {
+            // Folders are walked again when they appear, not whenever a file inside changes
+            let appeared = match event.kind {
+                EventKind::Access(_) => continue,
+                EventKind::Create(_) | EventKind::Modify(ModifyKind::Name(_)) => true,
+                _ => false,
+            };
+            for path in event
+                .paths
+                .iter()
+                .filter(|path| !path.starts_with(&data_dir))
+            {
+                // A rename reports the old path and the new one, only the new one exists
+                if !path.exists() {
+                    changes.removed.insert(path.clone());
+                } else if appeared || !path.is_dir() {
+                    changes.changed.insert(path.clone());
+                }
+            }
+        }
+
+        changes
+    }
+
+    fn is_empty(&self) -> bool {
+        self.changed.is_empty() && self.removed.is_empty()
+    }
+}
+
+/// Scan `root`, then index the changes made below it until Ctrl-C
+pub async fn watch(indexer: &Indexer, root: &Path, walk_options: &ScanConfig) -> Result<()> {
+    // Start watching before the scan, so changes made while it runs are not missed
+    let (event_tx, mut event_rx) = mpsc::unbounded_channel();
+    let mut debouncer = new_debouncer(
+        Duration::from_millis(indexer.config.scan.watch_debounce_ms),
+        None,
+        move |result| {
+            let _ = event_tx.send(result);
+        },
+    )
+    .context("Failed to start the filesystem watcher")?;
+    debouncer
+        .watch(root, RecursiveMode::Recursive)
+        .with_context(|| format!("Failed to watch {}", root.display()))?;
+
+    let report = indexer
+        .scan(root, walk_options, &ScanProgress::new())
+        .await?;
+    report.print(ReportFormat::Text)?;
+    if report.interrupted {
+        eprintln!("Run the scan again to resume.");
+        return Ok(());
+    }
+
+    println!();
+    println!("Watching {} for changes, Ctrl-C to stop.", root.display());
+
+    let scan_config = DirScanConfig::from_config(root.to_path_buf(), walk_options);
+    let ctrl_c = tokio::signal::ctrl_c();
+    tokio::pin!(ctrl_c);
+
+    loop {
+        tokio::select! {
+            result = event_rx.recv() => {
+                let Some(result) = result else {
+                    break;
+                };
+                let events = match result {
+                    Ok(events) => events,
+                    Err(errors) => {
+                        for error in errors {
+                            eprintln!("Watch error: {}", error);
+                        }
+                        continue;
+                    }
+                };
+
+                let changes = Changes::from_events(&events);
+                if changes.is_empty() {
+                    continue;
+                }
+                // A failed update is reported and the next change tried, the index catches up on the next scan
+                match apply_changes(indexer, &scan_config, changes).await {
+                    Ok(true) => break,
+                    Ok(false) => {}
+                    Err(e) => eprintln!("Failed to update the index: {:#}", e),
+                }
+            }
+            _ = &mut ctrl_c => break,
+        }
+    }
+
+    Ok(())
+}
+
+/// Index changed files and remove deleted ones, returning whether it was interrupted
+async fn apply_changes(
+    indexer: &Indexer,
+    scan_config: &DirScanConfig,
+    changes: Changes,
+) -> Result<bool> {
+    // Moved files are relocated while indexing, so their old paths have no points left to delete
+    let stats = indexer
+        .index_paths(scan_config, changes.changed.into_iter().collect())
+        .await?;
+    if stats.interrupted {
+        return Ok(true);
+    }
+    let removed: Vec<PathBuf> = changes.removed.into_iter().collect();
+    let deleted = indexer.delete_paths(&removed).await?;
+
+    if stats.indexed_files + stats.moved_files + stats.failed.len() + deleted.deleted_files > 0 {
+        println!(
+            "[{}] {} indexed ({} points), {} moved, {} failed, {} removed ({} points)",
+            chrono::Local::now().format("%H:%M:%S"),
+            stats.indexed_files,
+            stats.indexed_points,
+            stats.moved_files,
+            stats.failed.len(),
+            deleted.deleted_files,
+            deleted.deleted_points
+        );
+    }
+
+    Ok(false)
+}
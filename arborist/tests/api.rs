@@ -0,0 +1,170 @@
+//! Drives the HTTP API router over the embedded store and the stub summarizer.
+
+mod common;
+
+use anyhow::{bail, Result};
+use arborist::api;
+use axum::body::{self, Body};
+use axum::http::{header, Method, Request, StatusCode};
+use axum::Router;
+use serde_json::{json, Value};
+use std::time::{Duration, Instant};
+use tower::ServiceExt;
+
+/// Longest a scan of the sample tree may take, tokenizers are downloaded on first use
+const SCAN_TIMEOUT: Duration = Duration::from_secs(600);
+
+async fn send(
+    app: &Router,
+    method: Method,
+    uri: &str,
+    body: Option<Value>,
+) -> Result<(StatusCode, Value)> {
+    let request = Request::builder().method(method).uri(uri);
+    let request = match body {
+        Some(body) => request
+            .header(header::CONTENT_TYPE, "application/json")
+            .body(Body::from(body.to_string()))?,
+        None => request.body(Body::empty())?,
+    };
+    let response = app.clone().oneshot(request).await?;
+    let status = response.status();
+    let bytes = body::to_bytes(response.into_body(), usize::MAX).await?;
+    let value = if bytes.is_empty() {
+        Value::Null
+    } else {
+        serde_json::from_slice(&bytes)?
+    };
+    Ok((status, value))
+}
+
+/// Start a scan of `path` and wait for its job to finish
+async fn scan(app: &Router, path: &str) -> Result<Value> {
+    let (status, job) = send(
+        app,
+        Method::POST,
+        "/api/scans",
+        Some(json!({ "path": path })),
+    )
+    .await?;
+    assert_eq!(status, StatusCode::ACCEPTED, "{}", job);
+    assert_eq!(job["state"], "running");
+
+    let uri = format!("/api/scans/{}", job["id"]);
+    let started = Instant::now();
+    loop {
+        let (status, job) = send(app, Method::GET, &uri, None).await?;
+        assert_eq!(status, StatusCode::OK, "{}", job);
+        if job["state"] != "running" {
+            return Ok(job);
+        }
+        if started.elapsed() > SCAN_TIMEOUT {
+            bail!("Scan of {} did not finish", path);
+        }
+        tokio::time::sleep(Duration::from_millis(200)).await;
+    }
+}
+
+#[tokio::test]
+async fn unknown_files_and_jobs_are_not_found() -> Result<()> {
+    let app = api::router(common::indexer(common::config("api_not_found")).await?)?;
+
+    let (status, body) = send(&app, Method::GET, "/api/files?path=/nowhere/file.txt", None).await?;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+    assert!(body["error"].as_str().unwrap().contains("not indexed"));
+
+    let (status, _) = send(&app, Method::GET, "/api/scans/999", None).await?;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+
+    let (status, body) = send(&app, Method::GET, "/api/scans", None).await?;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body, json!([]));
+    Ok(())
+}
+
+#[tokio::test]
+async fn invalid_requests_are_rejected() -> Result<()> {
+    let app = api::router(common::indexer(common::config("api_invalid")).await?)?;
+
+    let filters = json!({ "query": "invoice", "filters": { "under": "/no/such/directory" } });
+    let (status, body) = send(&app, Method::POST, "/api/search", Some(filters)).await?;
+    assert_eq!(status, StatusCode::BAD_REQUEST, "{}", body);
+
+    let missing = json!({ "path": "/no/such/directory" });
+    let (status, body) = send(&app, Method::POST, "/api/scans", Some(missing)).await?;
+    assert_eq!(status, StatusCode::BAD_REQUEST, "{}", body);
+
+    // Neither indexed nor below one of the scan roots
+    let tree = common::sample_tree("api_invalid")?;
+    let outside = json!({ "path": tree });
+    let (status, body) = send(&app, Method::POST, "/api/scans", Some(outside)).await?;
+    assert_eq!(status, StatusCode::FORBIDDEN, "{}", body);
+    Ok(())
+}
+
+#[tokio::test]
+async fn requests_need_the_configured_token() -> Result<()> {
+    let mut config = common::config("api_token");
+    config.api.token_env = Some("ARBORIST_TEST_API_TOKEN".to_string());
+    std::env::set_var("ARBORIST_TEST_API_TOKEN", "secret");
+    let app = api::router(common::indexer(config).await?)?;
+
+    let (status, _) = send(&app, Method::GET, "/api/stats", None).await?;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+
+    let request = Request::get("/api/stats")
+        .header(header::AUTHORIZATION, "Bearer wrong")
+        .body(Body::empty())?;
+    let response = app.clone().oneshot(request).await?;
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+
+    let request = Request::get("/api/stats")
+        .header(header::AUTHORIZATION, "Bearer secret")
+        .body(Body::empty())?;
+    let response = app.clone().oneshot(request).await?;
+    assert_eq!(response.status(), StatusCode::OK);
+    Ok(())
+}
+
+#[tokio::test]
+async fn scanned_files_can_be_searched() -> Result<()> {
+    let tree = common::sample_tree("api_scan")?;
+    let mut config = common::config("api_scan");
+    config.api.scan_roots = vec![tree.clone()];
+    let app = api::router(common::indexer(config).await?)?;
+
+    let job = scan(&app, tree.to_str().unwrap()).await?;
+    assert_eq!(job["state"], "finished", "{}", job);
+    let report = &job["report"];
+    assert_eq!(
+        report["indexed_files"],
+        common::SAMPLE_FILES.len(),
+        "{}",
+        report
+    );
+    assert_eq!(report["failed_files"], 0, "{}", report);
+
+    let (status, stats) = send(&app, Method::GET, "/api/stats", None).await?;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(stats["files"], common::SAMPLE_FILES.len(), "{}", stats);
+
+    let invoice = tree.join("invoices/INV-2023-0042.txt");
+    let uri = format!("/api/files?path={}", invoice.display());
+    let (status, file) = send(&app, Method::GET, &uri, None).await?;
+    assert_eq!(status, StatusCode::OK, "{}", file);
+    assert!(file["summary"]
+        .as_str()
+        .unwrap()
+        .starts_with("Stub summary"));
+
+    let query = json!({ "query": "INV-2023-0042" });
+    let (status, hits) = send(&app, Method::POST, "/api/search", Some(query)).await?;
+    assert_eq!(status, StatusCode::OK, "{}", hits);
+    assert_eq!(hits[0]["path"], invoice.display().to_string(), "{}", hits);
+
+    // Scanning again leaves the unchanged files alone
+    let job = scan(&app, tree.join("notes").to_str().unwrap()).await?;
+    assert_eq!(job["state"], "finished", "{}", job);
+    assert_eq!(job["report"]["unchanged_files"], 2, "{}", job);
+    Ok(())
+}
@@ -0,0 +1,102 @@
+//! An arborist over the embedded store and the stub summarizer, with its
+//! state kept in a temporary directory, for tests that index real files.
+//!
+//! The embedding models are downloaded on first use like they are for the CLI.
+
+#![allow(dead_code)]
+
+use anyhow::Result;
+use arborist::config::{Config, LlmBackend, StoreBackend};
+use arborist::database;
+use arborist::indexer::Indexer;
+use arborist::llm::StubSummarizer;
+use arborist::store;
+use arborist::utils::setup_fastembed;
+use fastembed::{SparseTextEmbedding, TextEmbedding};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, OnceLock};
+
+/// Small dense model, the tests don't need the default one
+const DENSE_MODEL: &str = "Xenova/bge-small-en-v1.5";
+
+/// Files of the sample tree, by path relative to its root
+pub const SAMPLE_FILES: &[(&str, &str)] = &[
+    (
+        "invoices/INV-2023-0042.txt",
+        "Invoice INV-2023-0042 for the garden furniture delivered in March 2023.",
+    ),
+    (
+        "invoices/INV-2024-0007.txt",
+        "Invoice INV-2024-0007 for the winter tyres fitted in November 2024.",
+    ),
+    (
+        "notes/recipes.txt",
+        "Grandmother's apple pie: flour, butter, apples, cinnamon and sugar.",
+    ),
+    (
+        "notes/travel.txt",
+        "Train times from Vienna to Salzburg and the hotel booking reference.",
+    ),
+];
+
+/// Directory every test of this process keeps its files in
+pub fn temp_root() -> &'static Path {
+    static ROOT: OnceLock<PathBuf> = OnceLock::new();
+    ROOT.get_or_init(|| {
+        let root = std::env::temp_dir().join(format!("arborist-tests-{}", std::process::id()));
+        fs::create_dir_all(&root).expect("Failed to create the test directory");
+        // Journals, vocabularies and indexed roots go below it as well
+        std::env::set_var("ARBORIST_DATA_DIR", root.join("data"));
+        root
+    })
+}
+
+fn models() -> Result<(Arc<TextEmbedding>, Arc<SparseTextEmbedding>)> {
+    static MODELS: OnceLock<(Arc<TextEmbedding>, Arc<SparseTextEmbedding>)> = OnceLock::new();
+    if let Some(models) = MODELS.get() {
+        return Ok(models.clone());
+    }
+    let (model, sparse_model) = setup_fastembed(&config("models").embedding)?;
+    Ok(MODELS
+        .get_or_init(|| (Arc::new(model), Arc::new(sparse_model)))
+        .clone())
+}
+
+/// Configuration of a collection of its own named after the test
+pub fn config(name: &str) -> Config {
+    let mut config = Config {
+        collection_name: format!("test_{}", name),
+        store_backend: StoreBackend::Embedded,
+        embedded_path: Some(temp_root().join(format!("{}.json", name))),
+        ..Config::default()
+    };
+    config.scan.llm_backend = LlmBackend::Stub;
+    config.embedding.dense_model = DENSE_MODEL.to_string();
+    config
+}
+
+/// An indexer over a new collection for `config`
+pub async fn indexer(config: Config) -> Result<Indexer> {
+    let store = store::connect(&config)?;
+    database::create_hybrid_collection(store.as_ref(), &config).await?;
+    let (model, sparse_model) = models()?;
+    Ok(Indexer::new(
+        Arc::new(config),
+        store,
+        Arc::new(StubSummarizer),
+        model,
+        sparse_model,
+    ))
+}
+
+/// Write the sample tree to a new directory named after the test, canonicalized
+pub fn sample_tree(name: &str) -> Result<PathBuf> {
+    let root = temp_root().join(name).join("files");
+    for (path, text) in SAMPLE_FILES {
+        let path = root.join(path);
+        fs::create_dir_all(path.parent().unwrap())?;
+        fs::write(path, text)?;
+    }
+    Ok(root.canonicalize()?)
+}
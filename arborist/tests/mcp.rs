@@ -0,0 +1,184 @@
+//! Drives `arborist mcp` with the scripted sessions in `tests/transcripts`.
+//!
+//! In a transcript, `>` lines are sent to the server and `<` lines are the
+//! responses expected, in order. An expected response only has to contain the
+//! fields it lists, and tool output that is JSON text is compared as JSON.
+
+use anyhow::{bail, Result};
+use arborist::database::FileDetail;
+use arborist::filters::QueryFilters;
+use arborist::mcp::{self, IndexTools};
+use arborist::output::QueryHit;
+use arborist::roots::IndexedRoot;
+use async_trait::async_trait;
+use serde_json::{json, Value};
+use std::path::Path;
+use std::sync::Mutex;
+
+/// An index of a few files under /home/user
+struct FakeIndex {
+    roots: Mutex<Vec<IndexedRoot>>,
+}
+
+impl FakeIndex {
+    fn new() -> Self {
+        FakeIndex {
+            roots: Mutex::new(vec![
+                IndexedRoot {
+                    path: "/home/user/Documents".to_string(),
+                    scanned_at: 1717171717,
+                    files: 412,
+                },
+                IndexedRoot {
+                    path: "/home/user/taxes".to_string(),
+                    scanned_at: 1718000000,
+                    files: 12,
+                },
+            ]),
+        }
+    }
+}
+
+#[async_trait]
+impl IndexTools for FakeIndex {
+    async fn search(&self, query: &str, filters: &QueryFilters) -> Result<Vec<QueryHit>> {
+        filters.to_filter()?;
+        // The summary echoes what was asked, for the transcripts to check
+        Ok(vec![QueryHit {
+            score: 0.82,
+            path: "/home/user/taxes/2023.pdf".to_string(),
+            name: "2023.pdf".to_string(),
+            size: Some(48213),
+            filetype: Some("document".to_string()),
+            modified_at: Some(1710000000),
+            summary: Some(json!({ "query": query, "filters": filters }).to_string()),
+            passage: None,
+            lexical_score: None,
+            blended_score: None,
+            rerank_score: None,
+        }])
+    }
+
+    async fn file_summary(&self, path: &str) -> Result<Option<FileDetail>> {
+        if path != "/home/user/taxes/2023.pdf" {
+            return Ok(None);
+        }
+        Ok(Some(FileDetail {
+            path: path.to_string(),
+            name: Some("2023.pdf".to_string()),
+            size: Some(48213),
+            filetype: Some("document".to_string()),
+            extension: Some("pdf".to_string()),
+            created_at: Some(1709000000),
+            modified_at: Some(1710000000),
+            content_hash: Some("9f2c".to_string()),
+            summary: Some("Federal income tax return for 2023.".to_string()),
+            points: 3,
+        }))
+    }
+
+    async fn indexed_roots(&self) -> Result<Vec<IndexedRoot>> {
+        Ok(self.roots.lock().unwrap().clone())
+    }
+
+    async fn reindex(&self, path: &Path) -> Result<Value> {
+        if !path.starts_with("/home/user/taxes") {
+            bail!("Invalid path: {}", path.display());
+        }
+        let mut roots = self.roots.lock().unwrap();
+        if let Some(root) = roots.iter_mut().find(|root| Path::new(&root.path) == path) {
+            root.scanned_at = 1719000000;
+            root.files = 2;
+        }
+        Ok(json!({ "root": path, "indexed_files": 2, "unchanged_files": 10 }))
+    }
+}
+
+/// Whether `actual` has everything `expected` has
+fn contains(actual: &Value, expected: &Value) -> bool {
+    match (actual, expected) {
+        (Value::String(text), expected) if !expected.is_string() => {
+            serde_json::from_str::<Value>(text).is_ok_and(|parsed| contains(&parsed, expected))
+        }
+        (Value::Object(actual), Value::Object(expected)) => expected.iter().all(|(key, value)| {
+            actual
+                .get(key)
+                .is_some_and(|actual| contains(actual, value))
+        }),
+        (Value::Array(actual), Value::Array(expected)) => {
+            actual.len() == expected.len()
+                && actual.iter().zip(expected).all(|(a, e)| contains(a, e))
+        }
+        (actual, expected) => actual == expected,
+    }
+}
+
+async fn run_transcript(name: &str) {
+    let path = Path::new(env!("CARGO_MANIFEST_DIR"))
+        .join("tests/transcripts")
+        .join(name);
+    let transcript = std::fs::read_to_string(&path).unwrap();
+
+    let mut input = String::new();
+    let mut expected = Vec::new();
+    for line in transcript.lines() {
+        if let Some(request) = line.strip_prefix("> ") {
+            input.push_str(request);
+            input.push('\n');
+        } else if let Some(response) = line.strip_prefix("< ") {
+            expected.push(serde_json::from_str::<Value>(response).unwrap());
+        }
+    }
+
+    let mut output = Vec::new();
+    mcp::serve(&FakeIndex::new(), input.as_bytes(), &mut output)
+        .await
+        .unwrap();
+    let responses: Vec<Value> = String::from_utf8(output)
+        .unwrap()
+        .lines()
+        .map(|line| serde_json::from_str(line).unwrap())
+        .collect();
+
+    assert_eq!(
+        responses.len(),
+        expected.len(),
+        "{}: got {:#?}",
+        name,
+        responses
+    );
+    for (response, expected) in responses.iter().zip(&expected) {
+        assert!(
+            contains(response, expected),
+            "{}: expected {}\ngot {}",
+            name,
+            expected,
+            response
+        );
+    }
+}
+
+#[tokio::test]
+async fn handshake() {
+    run_transcript("handshake.jsonl").await;
+}
+
+#[tokio::test]
+async fn search() {
+    run_transcript("search.jsonl").await;
+}
+
+#[tokio::test]
+async fn summaries() {
+    run_transcript("summaries.jsonl").await;
+}
+
+#[tokio::test]
+async fn reindex() {
+    run_transcript("reindex.jsonl").await;
+}
+
+#[tokio::test]
+async fn errors() {
+    run_transcript("errors.jsonl").await;
+}
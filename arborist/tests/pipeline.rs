@@ -0,0 +1,90 @@
+//! Runs the scan pipeline over the embedded store and the stub summarizer.
+
+mod common;
+
+use anyhow::Result;
+use arborist::indexer::Indexer;
+use arborist::journal::ScanJournal;
+use arborist::progress::ScanProgress;
+use arborist::report::ScanReport;
+use arborist::store::Filter;
+use arborist::utils::DirScanConfig;
+use indicatif::ProgressBar;
+use std::fs;
+use std::path::Path;
+
+async fn scan(indexer: &Indexer, root: &Path) -> Result<ScanReport> {
+    indexer
+        .scan(root, &indexer.config.scan, &ScanProgress::hidden())
+        .await
+}
+
+#[tokio::test]
+async fn scans_index_new_changed_and_deleted_files() -> Result<()> {
+    let tree = common::sample_tree("pipeline_scan")?;
+    let indexer = common::indexer(common::config("pipeline_scan")).await?;
+
+    let report = scan(&indexer, &tree).await?;
+    assert!(!report.interrupted);
+    assert_eq!(report.scanned_files, common::SAMPLE_FILES.len());
+    assert_eq!(report.indexed_files, common::SAMPLE_FILES.len());
+    assert_eq!(report.failed_files, 0, "{:?}", report.failures);
+
+    // Every file was summarized by the stub, which quotes the prompt
+    let hits = indexer.search("apple pie", &Filter::default()).await?;
+    let recipes = hits
+        .iter()
+        .find(|hit| hit.name == "recipes.txt")
+        .expect("recipes.txt is indexed");
+    assert!(recipes
+        .summary
+        .as_deref()
+        .is_some_and(|summary| summary.starts_with("Stub summary")));
+
+    let report = scan(&indexer, &tree).await?;
+    assert_eq!(report.indexed_files, 0);
+    assert_eq!(report.unchanged_files, common::SAMPLE_FILES.len());
+
+    fs::write(
+        tree.join("notes/travel.txt"),
+        "Night train from Vienna to Venice, sleeper cabin for two.",
+    )?;
+    fs::remove_file(tree.join("invoices/INV-2024-0007.txt"))?;
+    let report = scan(&indexer, &tree).await?;
+    assert_eq!(report.indexed_files, 1);
+    assert_eq!(report.unchanged_files, common::SAMPLE_FILES.len() - 2);
+    assert_eq!(report.pruned.map(|pruned| pruned.deleted_files), Some(1));
+    Ok(())
+}
+
+#[tokio::test]
+async fn interrupted_scans_resume_from_the_journal() -> Result<()> {
+    let tree = common::sample_tree("pipeline_resume")?;
+    let indexer = common::indexer(common::config("pipeline_resume")).await?;
+    let collection_name = &indexer.config.collection_name;
+
+    // Leave behind the journal of a run that indexed the invoices, failed on
+    // one note and was stopped before the other
+    let walked = DirScanConfig::from_config(tree.clone(), &indexer.config.scan)
+        .walk(&ProgressBar::hidden())?;
+    let mut journal = ScanJournal::open(collection_name, &tree)?;
+    for file in &walked.file_metadata_list {
+        if file.path.contains("/invoices/") {
+            journal.record_done(file)?;
+        } else if file.name == "recipes.txt" {
+            journal.record_failed(&file.path, "summarizer unavailable")?;
+        }
+    }
+    drop(journal);
+
+    let report = scan(&indexer, &tree).await?;
+    assert!(!report.interrupted);
+    assert_eq!(report.resumed_files, 2);
+    assert_eq!(report.retried_files, 1);
+    assert_eq!(report.indexed_files, 2);
+    assert_eq!(report.failed_files, 0, "{:?}", report.failures);
+
+    // A finished scan removes its journal, the next one starts afresh
+    assert!(!ScanJournal::open(collection_name, &tree)?.is_resuming());
+    Ok(())
+}